@@ -0,0 +1,4 @@
+fn main() {
+    // generate the code for the `example_from_build` binary, see `src/main_from_build.rs`
+    dge_gen::build::generate_from_spec("graph.yaml").unwrap();
+}
@@ -3,13 +3,11 @@ use serde::Deserialize;
 
 #[derive(Serialize, Deserialize, Clone, Debug)]
 pub struct Integer {
-    pub msg_id: String,
     pub integer: i32,
 }
 
 #[derive(Serialize, Deserialize, Clone, Debug)]
 pub struct Float {
-    pub msg_id: String,
     pub float: f32,
 }
 
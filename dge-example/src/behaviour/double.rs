@@ -8,9 +8,8 @@ pub async fn init() -> () {
 }
 
 pub async fn handle(_state: State, msg: &Integer) -> Result<Integer, Error> {
-    let Integer {msg_id, integer} = msg;
+    let Integer {integer} = msg;
     Ok(Integer {
-        msg_id: msg_id.clone(),
         integer: integer + integer
     })
 }
@@ -3,6 +3,7 @@ use std::sync::Arc;
 use std::sync::Mutex;
 
 use dge_runtime::component::aggregate::AggregationStatus;
+use dge_runtime::MessageContext;
 
 use super::error::Error;
 use super::data::Integer;
@@ -22,23 +23,24 @@ pub enum Phase {
 }
 
 pub async fn aggregate(state: State, msg: &Integer) -> Result<AggregationStatus<Float>, Error> {
+    // the two numbers to multiply are computed from the same input, i.e. in the same run
+    let run_id = MessageContext::current().unwrap().envelope.run_id;
     let mut state = state.lock().unwrap();
-    let v = match state.get(&msg.msg_id) {
+    let v = match state.get(&run_id) {
         None => None,
         Some(x) => Some(x.clone())
     };
     let status = match v {
         None => {
-            state.insert(msg.msg_id.clone(), Phase::HaveOneNumber(msg.integer.clone()));
+            state.insert(run_id, Phase::HaveOneNumber(msg.integer.clone()));
             AggregationStatus::Ignore
         },
         Some(Phase::HaveTwoNumber(_, _)) => {
             AggregationStatus::Ignore
         },
         Some(Phase::HaveOneNumber(existing)) => {
-            state.insert(msg.msg_id.clone(), Phase::HaveTwoNumber(existing, msg.integer.clone()));
+            state.insert(run_id, Phase::HaveTwoNumber(existing, msg.integer.clone()));
             AggregationStatus::Aggregated(Float {
-                msg_id: msg.msg_id.clone(),
                 // since this is an example, we just unwrap it
                 float: (existing * msg.integer) as f32,
             })
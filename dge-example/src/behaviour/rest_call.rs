@@ -28,7 +28,6 @@ pub async fn check(msg: Float) -> Result<Option<Integer>, Error> {
         42
     };
     Ok(Some(Integer {
-        msg_id: msg.msg_id,
         integer: rest_result,
     }))
 }
\ No newline at end of file
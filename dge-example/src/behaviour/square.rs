@@ -1,16 +1,24 @@
+use dge_runtime::Handler;
+
 use super::error::Error;
 use super::data::Integer;
 
-pub type State = ();
+/// The behaviour of node square, implemented on a type instead of as a module.
+pub struct Square;
 
-pub async fn init() -> () {
-    ()
-}
+#[dge_runtime::async_trait]
+impl Handler for Square {
+    type Input = Integer;
+    type Output = Integer;
+    type State = ();
+    type Error = Error;
+
+    async fn init() -> Self::State {}
 
-pub async fn handle(_state: State, msg: &Integer) -> Result<Integer, Error> {
-    let Integer {msg_id, integer} = msg;
-    Ok(Integer {
-        msg_id: msg_id.clone(),
-        integer: integer * integer
-    })
+    async fn handle(_state: Self::State, msg: &Integer) -> Result<Integer, Error> {
+        let Integer {integer} = msg;
+        Ok(Integer {
+            integer: integer * integer
+        })
+    }
 }
@@ -22,19 +22,60 @@ use dge_runtime::rmq_primitive::Responsibility;
 use dge_runtime::Error;
 use dge_runtime::Result;
 
+use dge_runtime::Handler;
+
+const NODE: &str = "double";
+
+// The defaults are declared in the graph, and can be overridden by the command line.
 #[rustfmt::skip]
-#[tokio::main(worker_threads = 2)]
-pub(crate) async fn main() -> Result<()> {
-    let rmq_uri = dge_example::behaviour::get_rmq_uri();
+pub(crate) fn settings(options: dge_runtime::NodeOptions) -> dge_runtime::NodeSettings {
+    options.or(dge_runtime::NodeSettings {
+        prefetch_count: 8,
+        worker_threads: 2,
+        max_in_flight: Some(4),
+    })
+}
 
-    let handler_state = dge_example::behaviour::double::init().await;
+#[rustfmt::skip]
+pub(crate) fn main(options: dge_runtime::NodeOptions) -> Result<()> {
+    let connection = options.connection.clone();
+    let http = options.http.clone();
+    let shutdown = options.shutdown.clone();
+    let settings = settings(options);
+    let _telemetry = dge_runtime::telemetry::init(NODE)?;
+    let broker = dge_runtime::broker::Broker::new(dge_example::behaviour::get_rmq_uri(), connection);
+    let node = dge_runtime::shutdown::on_signal(shutdown, broker.clone(), run(broker, settings));
+    settings.block_on(dge_runtime::http::serving(http, node))
+}
+
+// How the messages rejected from the input queue are retried, declared in the graph.
+#[rustfmt::skip]
+const RETRY_POLICY: dge_runtime::retry::RetryPolicy = dge_runtime::retry::RetryPolicy {
+    retry_exchange: "dge_example_retry_exchange",
+    retry_queue: "retry_input_copy_1",
+    retry_interval_in_seconds: 11,
+    tiers: &[],
+    jitter_percent: 0,
+    max_attempts: None,
+    parking_queue: "input_copy_1_parked",
+};
+
+#[rustfmt::skip]
+pub(crate) async fn run(
+    broker: dge_runtime::broker::Broker,
+    settings: dge_runtime::NodeSettings,
+) -> Result<()> {
+    let instance = dge_runtime::NodeInstance::start(NODE);
+    let handler_state = Behaviour::init().await;
 
     let () = dge_runtime::rmq::consume_forever(
-        &rmq_uri,
+        &broker,
+        instance,
         "input_copy_1",
+        RETRY_POLICY,
         handler,
         handler_state,
-        1,
+        settings,
     ).await;
 
     Ok(())
@@ -43,7 +84,7 @@ pub(crate) async fn main() -> Result<()> {
 
 #[rustfmt::skip]
 async fn handler(
-    state: dge_example::behaviour::double::State,
+    state: <Behaviour as Handler>::State,
     channel: Channel,
     msg: dge_example::behaviour::data::Integer,
 ) -> Result<Responsibility>
@@ -52,9 +93,38 @@ async fn handler(
         state = state,
         channel = channel,
         msg = msg,
-        user_handler = dge_example::behaviour::double::handle,
+        user_handler = Behaviour::handle,
         accept_failure = dge_example::behaviour::accept_failure::accept_failure,
         output_queue = Some("multiply"),
         exchange = "dge_example_work_exchange",
     )
+}
+
+// Binds the behaviour module to `dge_runtime::Handler`.
+struct Behaviour;
+
+#[rustfmt::skip]
+#[dge_runtime::async_trait]
+impl Handler for Behaviour {
+    type Input = dge_example::behaviour::data::Integer;
+    type Output = dge_example::behaviour::data::Integer;
+    type State = dge_example::behaviour::double::State;
+    type Error = dge_example::behaviour::error::Error;
+
+    async fn init() -> Self::State {
+        dge_example::behaviour::double::init().await
+    }
+
+    async fn handle(state: Self::State, msg: &Self::Input) -> std::result::Result<Self::Output, Self::Error> {
+        dge_example::behaviour::double::handle(state, msg).await
+    }
+}
+
+// Never called, this only asks the compiler to check that
+// the behaviour consumes and produces the message types declared in the graph.
+#[rustfmt::skip]
+#[allow(dead_code)]
+fn assert_message_types() {
+    fn assert_handler<B: Handler<Input = dge_example::behaviour::data::Integer, Output = dge_example::behaviour::data::Integer, Error = dge_example::behaviour::error::Error>>() {}
+    assert_handler::<Behaviour>();
 }
\ No newline at end of file
@@ -24,19 +24,58 @@ use dge_runtime::Result;
 
 type HandlerState = ();
 
+const NODE: &str = "duplicate_input";
+
+// The defaults are declared in the graph, and can be overridden by the command line.
+#[rustfmt::skip]
+pub(crate) fn settings(options: dge_runtime::NodeOptions) -> dge_runtime::NodeSettings {
+    options.or(dge_runtime::NodeSettings {
+        prefetch_count: 1,
+        worker_threads: 2,
+        max_in_flight: None,
+    })
+}
+
 #[rustfmt::skip]
-#[tokio::main(worker_threads = 2)]
-pub(crate) async fn main() -> Result<()> {
-    let rmq_uri = dge_example::behaviour::get_rmq_uri();
+pub(crate) fn main(options: dge_runtime::NodeOptions) -> Result<()> {
+    let connection = options.connection.clone();
+    let http = options.http.clone();
+    let shutdown = options.shutdown.clone();
+    let settings = settings(options);
+    let _telemetry = dge_runtime::telemetry::init(NODE)?;
+    let broker = dge_runtime::broker::Broker::new(dge_example::behaviour::get_rmq_uri(), connection);
+    let node = dge_runtime::shutdown::on_signal(shutdown, broker.clone(), run(broker, settings));
+    settings.block_on(dge_runtime::http::serving(http, node))
+}
+
+// How the messages rejected from the input queue are retried, declared in the graph.
+#[rustfmt::skip]
+const RETRY_POLICY: dge_runtime::retry::RetryPolicy = dge_runtime::retry::RetryPolicy {
+    retry_exchange: "dge_example_retry_exchange",
+    retry_queue: "retry_input",
+    retry_interval_in_seconds: 10,
+    tiers: &[],
+    jitter_percent: 0,
+    max_attempts: None,
+    parking_queue: "input_parked",
+};
 
+#[rustfmt::skip]
+pub(crate) async fn run(
+    broker: dge_runtime::broker::Broker,
+    settings: dge_runtime::NodeSettings,
+) -> Result<()> {
+    let instance = dge_runtime::NodeInstance::start(NODE);
     let handler_state = ();
 
     let () = dge_runtime::rmq::consume_forever(
-        &rmq_uri,
+        &broker,
+        instance,
         "input",
+        RETRY_POLICY,
         handler,
         handler_state,
-        1,
+        settings,
     ).await;
 
     Ok(())
@@ -48,6 +48,31 @@ pub(crate) async fn main() -> Result<()> {
         all_queues,
     ).await?;
 
+    // the retry queues of the backoff tiers
+    // (work_queue, retry_queue_of_tier)
+    let all_retry_tiers: Vec<(&str, &str)> = vec![
+        ("rest_call", "retry_rest_call_1"),
+        ("rest_call", "retry_rest_call_2"),
+        ("rest_call", "retry_rest_call_3"),
+    ];
+
+    let () = rmq_init::init_retry_tiers(
+        rmq_uri.as_ref(),
+        "dge_example_work_exchange",
+        "dge_example_retry_exchange",
+        all_retry_tiers,
+    ).await?;
+
+    // the queues the messages failing too many times are parked in
+    let all_parking_queues: Vec<&str> = vec![
+        "rest_call_parked",
+    ];
+
+    let () = rmq_init::init_parking_queues(
+        rmq_uri.as_ref(),
+        all_parking_queues,
+    ).await?;
+
     info!("all necessary exchanges and queues initialized");
 
     Ok(())
@@ -16,29 +16,116 @@ mod multiply;
 mod rest_call;
 mod square;
 
+#[rustfmt::skip]
+#[derive(Debug, StructOpt)]
+struct Options {
+    /// Prefixed to the names of all the exchanges and queues, e.g. staging.input for queue input
+    #[structopt(long, global = true, env = dge_runtime::namespace::NAMESPACE_ENV)]
+    namespace: Option<String>,
+
+    #[structopt(subcommand)]
+    command: Command,
+}
+
 #[rustfmt::skip]
 #[derive(Debug, StructOpt)]
 enum Command {
-    Double,
-    DuplicateInput,
+    /// Run node double
+    Double(dge_runtime::NodeOptions),
+    /// Run node duplicate_input
+    DuplicateInput(dge_runtime::NodeOptions),
+    /// Declare the exchanges and queues used by the graph
     InitExchangesAndQueues,
-    Multiply,
-    RestCall,
-    Square,
+    /// Run node multiply
+    Multiply(dge_runtime::NodeOptions),
+    /// Run node rest_call
+    RestCall(dge_runtime::NodeOptions),
+    /// Run node square
+    Square(dge_runtime::NodeOptions),
+    /// Run all the nodes in this process, for development
+    RunAll(dge_runtime::RunAllOptions),
+    /// Migrate the queues and exchanges of a previous version of the graph, as planned by `dge plan-migration`
+    Migrate(dge_runtime::MigrateOptions),
+    /// Inspect, replay and purge the messages parked after failing too many times
+    Parked(dge_runtime::ParkedCommand),
 }
 
 #[rustfmt::skip]
-fn main() -> Result<()> {
+pub fn main() -> Result<()> {
     dge_example::behaviour::setup_logger();
 
-    let command = Command::from_args();
+    let options = Options::from_args();
+    if let Some(namespace) = &options.namespace {
+        dge_runtime::namespace::set_namespace(namespace)?;
+    }
 
-    match command {
-        Command::Double => double::main(),
-        Command::DuplicateInput => duplicate_input::main(),
+    match options.command {
+        Command::Double(options) => double::main(options),
+        Command::DuplicateInput(options) => duplicate_input::main(options),
         Command::InitExchangesAndQueues => init_exchanges_and_queues::main(),
-        Command::Multiply => multiply::main(),
-        Command::RestCall => rest_call::main(),
-        Command::Square => square::main(),
+        Command::Multiply(options) => multiply::main(options),
+        Command::RestCall(options) => rest_call::main(options),
+        Command::Square(options) => square::main(options),
+        Command::RunAll(options) => run_all(options),
+        Command::Migrate(options) => migrate(options),
+        Command::Parked(command) => parked(command),
     }
+}
+
+#[rustfmt::skip]
+fn run_all(options: dge_runtime::RunAllOptions) -> Result<()> {
+    options.check_nodes(&[
+        "double",
+        "duplicate_input",
+        "multiply",
+        "rest_call",
+        "square",
+    ])?;
+
+    let _telemetry = dge_runtime::telemetry::init("run-all")?;
+    let broker = dge_runtime::broker::Broker::new(dge_example::behaviour::get_rmq_uri(), options.connection.clone());
+    let nodes = async {
+        let mut nodes = Vec::new();
+        for _ in 0..options.instances_of("double") {
+            let settings = double::settings(Default::default());
+            nodes.push(tokio::spawn(double::run(broker.clone(), settings)));
+        }
+        for _ in 0..options.instances_of("duplicate_input") {
+            let settings = duplicate_input::settings(Default::default());
+            nodes.push(tokio::spawn(duplicate_input::run(broker.clone(), settings)));
+        }
+        for _ in 0..options.instances_of("multiply") {
+            let settings = multiply::settings(Default::default());
+            nodes.push(tokio::spawn(multiply::run(broker.clone(), settings)));
+        }
+        for _ in 0..options.instances_of("rest_call") {
+            let settings = rest_call::settings(Default::default());
+            nodes.push(tokio::spawn(rest_call::run(broker.clone(), settings)));
+        }
+        for _ in 0..options.instances_of("square") {
+            let settings = square::settings(Default::default());
+            nodes.push(tokio::spawn(square::run(broker.clone(), settings)));
+        }
+        dge_runtime::run_all::wait_all(nodes).await
+    };
+    let nodes = dge_runtime::shutdown::on_signal(options.shutdown.clone(), broker.clone(), nodes);
+    options.block_on(dge_runtime::http::serving(options.http.clone(), nodes))
+}
+
+#[rustfmt::skip]
+#[tokio::main]
+async fn migrate(options: dge_runtime::MigrateOptions) -> Result<()> {
+    let broker = dge_runtime::broker::Broker::new(dge_example::behaviour::get_rmq_uri(), Default::default());
+    dge_runtime::rmq_migrate::migrate(&broker, &options).await
+}
+
+#[rustfmt::skip]
+#[tokio::main]
+async fn parked(command: dge_runtime::ParkedCommand) -> Result<()> {
+    let broker = dge_runtime::broker::Broker::new(dge_example::behaviour::get_rmq_uri(), Default::default());
+    // (work_queue, parking_queue)
+    let parking_queues: &[(&str, &str)] = &[
+        ("rest_call", "rest_call_parked"),
+    ];
+    dge_runtime::rmq_parked::parked(&broker, "dge_example_work_exchange", parking_queues, &command).await
 }
\ No newline at end of file
@@ -28,35 +28,86 @@ use tokio::sync::RwLock;
 use dge_runtime::component::poll::Jobs;
 use dge_runtime::component::poll::new_job;
 use dge_runtime::component::poll::poll_forever;
+use dge_runtime::Poller;
 
+const NODE: &str = "rest_call";
+
+// The defaults are declared in the graph, and can be overridden by the command line.
+#[rustfmt::skip]
+pub(crate) fn settings(options: dge_runtime::NodeOptions) -> dge_runtime::NodeSettings {
+    options.or(dge_runtime::NodeSettings {
+        prefetch_count: 1,
+        worker_threads: 10,
+        max_in_flight: None,
+    })
+}
+
+#[rustfmt::skip]
+pub(crate) fn main(options: dge_runtime::NodeOptions) -> Result<()> {
+    let connection = options.connection.clone();
+    let http = options.http.clone();
+    let shutdown = options.shutdown.clone();
+    let settings = settings(options);
+    let _telemetry = dge_runtime::telemetry::init(NODE)?;
+    let broker = dge_runtime::broker::Broker::new(dge_example::behaviour::get_rmq_uri(), connection);
+    let node = dge_runtime::shutdown::on_signal(shutdown, broker.clone(), run(broker, settings));
+    settings.block_on(dge_runtime::http::serving(http, node))
+}
+
+// How the messages rejected from the input queue are retried, declared in the graph.
 #[rustfmt::skip]
-#[tokio::main(worker_threads = 10)]
-pub(crate) async fn main() -> Result<()> {
+const RETRY_POLICY: dge_runtime::retry::RetryPolicy = dge_runtime::retry::RetryPolicy {
+    retry_exchange: "dge_example_retry_exchange",
+    retry_queue: "retry_rest_call",
+    retry_interval_in_seconds: 13,
+    tiers: &[
+        ("retry_rest_call_1", 30),
+        ("retry_rest_call_2", 120),
+        ("retry_rest_call_3", 600),
+    ],
+    jitter_percent: 20,
+    max_attempts: Some(10),
+    parking_queue: "rest_call_parked",
+};
+
+#[rustfmt::skip]
+pub(crate) async fn run(
+    broker: dge_runtime::broker::Broker,
+    settings: dge_runtime::NodeSettings,
+) -> Result<()> {
+    let instance = dge_runtime::NodeInstance::start(NODE);
+
     // load existing jobs
     let jobs = load_jobs().await?;
 
     // start a thread to poll the jobs
-    tokio::spawn(poll_forever(
-        dge_example::behaviour::rest_call::get_capacity(),
+    let polling = tokio::spawn(poll_forever(
+        NODE,
+        Behaviour::get_capacity(),
         jobs.clone(),
 
         // these are used when do the actual checking
-        dge_example::behaviour::rest_call::check,
+        check,
         dge_example::behaviour::accept_failure::accept_failure,
-        dge_example::behaviour::get_rmq_uri,
-        "dge_example_work_exchange",
-        Some("result"),
+        dge_runtime::component::poll::Output {
+            broker: broker.clone(),
+            work_exchange: "dge_example_work_exchange",
+            queue: Some("result"),
+        },
     ));
 
-    let rmq_uri = dge_example::behaviour::get_rmq_uri();
     let () = dge_runtime::rmq::consume_forever(
-        &rmq_uri,
+        &broker,
+        instance,
         "rest_call",
+        RETRY_POLICY,
         handler,
         jobs,
-        1,
+        settings,
     ).await;
 
+    // shutting down, the running checks are waited for
+    polling.await.map_err(|e| dge_runtime::Error::NodePanicked(e.to_string()))?;
     Ok(())
 }
 
@@ -67,7 +118,7 @@ async fn load_jobs() -> Result<Jobs<dge_example::behaviour::data::Float>> {
     let mut write_jobs = write_jobs.write().await;
 
     info!("loading messages");
-    let msgs = dge_example::behaviour::rest_call::init().await;
+    let msgs = Behaviour::init().await;
 
     info!("loaded {} messages, adding them to the job queue", msgs.len());
     for msg in msgs {
@@ -90,6 +141,47 @@ async fn handler(
     dge_runtime::add_to_jobs!(
         jobs = jobs,
         msg = msg,
-        save_msg = dge_example::behaviour::rest_call::save_msg,
+        save_msg = Behaviour::save_msg,
     )
+}
+
+#[rustfmt::skip]
+async fn check(msg: dge_example::behaviour::data::Float) -> std::result::Result<Option<dge_example::behaviour::data::Integer>, dge_example::behaviour::error::Error> {
+    Behaviour::check(msg).await
+}
+
+// Binds the behaviour module to `dge_runtime::Poller`.
+struct Behaviour;
+
+#[rustfmt::skip]
+#[dge_runtime::async_trait]
+impl Poller for Behaviour {
+    type Input = dge_example::behaviour::data::Float;
+    type Output = dge_example::behaviour::data::Integer;
+    type Error = dge_example::behaviour::error::Error;
+
+    async fn init() -> Vec<Self::Input> {
+        dge_example::behaviour::rest_call::init().await
+    }
+
+    async fn save_msg(msg: Self::Input) -> std::result::Result<(), Self::Error> {
+        dge_example::behaviour::rest_call::save_msg(msg).await
+    }
+
+    fn get_capacity() -> dge_runtime::component::poll::Capacity {
+        dge_example::behaviour::rest_call::get_capacity()
+    }
+
+    async fn check(msg: Self::Input) -> std::result::Result<Option<Self::Output>, Self::Error> {
+        dge_example::behaviour::rest_call::check(msg).await
+    }
+}
+
+// Never called, this only asks the compiler to check that
+// the behaviour consumes and produces the message types declared in the graph.
+#[rustfmt::skip]
+#[allow(dead_code)]
+fn assert_message_types() {
+    fn assert_poller<B: Poller<Input = dge_example::behaviour::data::Float, Output = dge_example::behaviour::data::Integer, Error = dge_example::behaviour::error::Error>>() {}
+    assert_poller::<Behaviour>();
 }
\ No newline at end of file
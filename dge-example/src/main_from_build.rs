@@ -0,0 +1,3 @@
+// Same as the `example` binary, but the code is generated by `build.rs` on every build,
+// from the graph saved in `graph.yaml`.
+include!(concat!(env!("OUT_DIR"), "/dge/main.rs"));
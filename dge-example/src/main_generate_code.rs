@@ -1,70 +1,84 @@
 use dge_gen;
 
 fn main() {
-    let mut graph = dge_gen::Graph::new(
-        "dge_example::behaviour::accept_failure::accept_failure",
-        "dge_example::behaviour::error::Error",
-    );
-    let start = graph.start("start");
-    let fan_out = graph.fan_out(
-        start,
-        "input",
-        "dge_example::behaviour::data::Integer",
-        "duplicate_input",
-        10
-    );
-    let double = graph.process(
-        fan_out,
-        "input_copy_1".into(),
-        "dge_example::behaviour::data::Integer",
-        "double",
-        "dge_example::behaviour::double".into(),
-        11,
-    );
-    let square = graph.process(
-        fan_out,
-        "input_copy_2".into(),
-        "dge_example::behaviour::data::Integer",
-        "square",
-        "dge_example::behaviour::square".into(),
-        12,
-    );
-    let multiply = graph.aggregate(
-        vec![double, square],
-        "multiply".into(),
-        "dge_example::behaviour::data::Integer",
-        "multiply",
-        "dge_example::behaviour::multiply".into(),
-        13,
-    );
-    let rest_call = graph.poll(
-        multiply,
-        "rest_call".into(),
-        "dge_example::behaviour::data::Float",
-        "rest_call",
-        "dge_example::behaviour::rest_call".into(),
-        13,
-    );
+    let mut graph = dge_gen::dge_graph! {
+        accept_failure = dge_example::behaviour::accept_failure::accept_failure;
+        type_error = dge_example::behaviour::error::Error;
 
-    let () = graph.terminate(
-        rest_call,
-        "result",
-        "dge_example::behaviour::data::Integer",
-        "terminate",
-        1
-    );
+        let start = start();
+        let duplicate_input = fan_out(
+            start,
+            queue = "input",
+            type_input = dge_example::behaviour::data::Integer,
+            retry_interval_in_seconds = 10,
+        );
+        let double = process(
+            duplicate_input,
+            queue = "input_copy_1",
+            type_input = dge_example::behaviour::data::Integer,
+            type_output = dge_example::behaviour::data::Integer,
+            behaviour_module = dge_example::behaviour::double,
+            retry_interval_in_seconds = 11,
+            prefetch_count = 8,
+            max_in_flight = 4,
+        );
+        let square = process(
+            duplicate_input,
+            queue = "input_copy_2",
+            type_input = dge_example::behaviour::data::Integer,
+            type_output = dge_example::behaviour::data::Integer,
+            behaviour = dge_example::behaviour::square::Square,
+            retry_interval_in_seconds = 12,
+        );
+        let multiply = aggregate(
+            [double, square],
+            queue = "multiply",
+            type_input = dge_example::behaviour::data::Integer,
+            type_output = dge_example::behaviour::data::Float,
+            behaviour_module = dge_example::behaviour::multiply,
+            retry_interval_in_seconds = 13,
+        );
+        let rest_call = poll(
+            multiply,
+            queue = "rest_call",
+            type_input = dge_example::behaviour::data::Float,
+            type_output = dge_example::behaviour::data::Integer,
+            behaviour_module = dge_example::behaviour::rest_call,
+            retry_interval_in_seconds = 13,
+            retry_backoff_in_seconds = [30, 120, 600],
+            retry_jitter_percent = 20,
+            max_attempts = 10,
+        );
+        let terminate = terminate(
+            rest_call,
+            queue = "result",
+            type_input = dge_example::behaviour::data::Integer,
+            retry_interval_in_seconds = 1,
+        );
+    };
 
+    graph.set_options(dge_gen::GenerateOptions {
+        get_rmq_uri: "dge_example::behaviour::get_rmq_uri".into(),
+        work_exchange: "dge_example_work_exchange".into(),
+        retry_exchange: "dge_example_retry_exchange".into(),
+        retry_queue_prefix: "retry_".into(),
+        retry_queue_suffix: "".into(),
+        init_input_queue: true,
+        init_output_queue: true,
+        main_init: "dge_example::behaviour::setup_logger".into(),
+        diagram: dge_gen::DiagramBackend::Builtin,
+    });
+
+    // when run by the `dge` command line tool, just hand over the graph
+    if graph.save_if_requested().unwrap() {
+        return;
+    }
+
+    // the same graph as data, for review and for loading with `dge_gen::Graph::load`
+    graph.save("dge-example/graph.yaml").unwrap();
+
+    let options = graph.options().cloned().unwrap();
     graph
-        .generate(
-            "dge-example/src/generated",
-            "dge_example::behaviour::get_rmq_uri",
-            "dge_example_work_exchange",
-            "dge_example_retry_exchange",
-            "retry_",
-            "",
-            true,
-            true,
-            "dge_example::behaviour::setup_logger",
-        )
+        .generate_with_options("dge-example/src/generated", &options)
         .unwrap()
 }
@@ -0,0 +1,418 @@
+use std::path::Path;
+use std::path::PathBuf;
+use structopt::StructOpt;
+
+use dge_gen::DeployOptions;
+use dge_gen::DiagramBackend;
+use dge_gen::Error;
+use dge_gen::Graph;
+
+/// Generate, validate and inspect computational graphs of dge.
+///
+/// The graph is read either from a spec file (--spec),
+/// or from a crate defining the graph in Rust (--manifest-path),
+/// such a crate is run with `cargo run`, and should call `Graph::save_if_requested`.
+#[derive(Debug, StructOpt)]
+#[structopt(name = "dge")]
+enum Command {
+    /// Generate the code for executing the graph
+    Generate {
+        #[structopt(flatten)]
+        source: Source,
+
+        /// The directory to write the generated code to
+        #[structopt(long, short, parse(from_os_str))]
+        output_dir: PathBuf,
+
+        /// Write nothing, fail if the files in the output directory differ from the generated ones
+        #[structopt(long)]
+        check: bool,
+    },
+
+    /// Write a skeleton of each behaviour module which does not exist yet
+    Scaffold {
+        #[structopt(flatten)]
+        source: Source,
+
+        /// The source directory of the crate defining the behaviour modules,
+        /// e.g. `my_crate::behaviour::double` is written to `<src-dir>/behaviour/double.rs`
+        #[structopt(long, parse(from_os_str))]
+        src_dir: PathBuf,
+    },
+
+    /// Plan the migration of the queues and exchanges of a previous version of the graph,
+    /// run the plan with the `migrate` subcommand of the generated executable
+    PlanMigration {
+        #[structopt(flatten)]
+        source: Source,
+
+        /// The topology of the previous version, as written by `dge topology`
+        #[structopt(long, parse(from_os_str))]
+        from: PathBuf,
+
+        /// Write the plan to this file instead of stdout
+        #[structopt(long, short, parse(from_os_str))]
+        output: Option<PathBuf>,
+    },
+
+    /// Write docker-compose, systemd and Kubernetes manifests running the generated executable
+    Deploy {
+        #[structopt(flatten)]
+        source: Source,
+
+        /// The directory to write the manifests to
+        #[structopt(long, short, parse(from_os_str))]
+        output_dir: PathBuf,
+
+        /// The name of the generated executable
+        #[structopt(long)]
+        binary: String,
+
+        /// The container image containing the executable, for docker-compose and Kubernetes
+        #[structopt(long)]
+        image: String,
+
+        /// The directory the executable is installed to, for systemd
+        #[structopt(long, default_value = "/usr/local/bin")]
+        install_dir: String,
+
+        /// An environment variable given to every service, e.g. `--env RUST_LOG=info`,
+        /// can be given multiple times
+        #[structopt(long, parse(try_from_str = parse_env))]
+        env: Vec<(String, String)>,
+
+        /// The environment variable the graph reads the RabbitMQ URI from,
+        /// docker-compose sets it to the RabbitMQ it starts
+        #[structopt(long)]
+        rmq_uri_env: Option<String>,
+
+        /// The port the nodes serve their metrics and health at,
+        /// probed by Kubernetes for liveness and readiness
+        #[structopt(long)]
+        http_port: Option<u16>,
+
+        /// The replicas of every node, e.g. `--replicas 2`, or of one node, e.g. `--replicas double=3`
+        #[structopt(long, parse(try_from_str = parse_per_node))]
+        replicas: Vec<(Option<String>, usize)>,
+
+        /// The CPU limit in thousandths of a core, of every node or of one node, e.g. `--cpu-millis double=500`
+        #[structopt(long, parse(try_from_str = parse_per_node))]
+        cpu_millis: Vec<(Option<String>, u32)>,
+
+        /// The memory limit in MiB, of every node or of one node, e.g. `--memory-mib double=256`
+        #[structopt(long, parse(try_from_str = parse_per_node))]
+        memory_mib: Vec<(Option<String>, u32)>,
+    },
+
+    /// Check the graph and report all the problems found
+    Validate {
+        #[structopt(flatten)]
+        source: Source,
+    },
+
+    /// Render the graph as a diagram
+    Render {
+        #[structopt(flatten)]
+        source: Source,
+
+        #[structopt(long, default_value = "svg", possible_values = &["dot", "svg", "html", "mermaid", "plantuml"])]
+        format: String,
+
+        /// Render svg with the `dot` command of Graphviz, instead of the builtin renderer
+        #[structopt(long)]
+        graphviz: bool,
+
+        /// Write the diagram to this file instead of stdout
+        #[structopt(long, short, parse(from_os_str))]
+        output: Option<PathBuf>,
+    },
+
+    /// Print a summary of the nodes and queues of the graph
+    Describe {
+        #[structopt(flatten)]
+        source: Source,
+    },
+
+    /// Print the nodes, queues and exchanges of the graph as JSON, for other tools
+    Topology {
+        #[structopt(flatten)]
+        source: Source,
+
+        /// Write the topology to this file instead of stdout
+        #[structopt(long, short, parse(from_os_str))]
+        output: Option<PathBuf>,
+    },
+}
+
+#[derive(Debug, StructOpt)]
+struct Source {
+    /// Graph spec file (.json, .yaml/.yml or .toml)
+    #[structopt(
+        long,
+        parse(from_os_str),
+        required_unless = "manifest-path",
+        conflicts_with = "manifest-path"
+    )]
+    spec: Option<PathBuf>,
+
+    /// Cargo.toml of the crate defining the graph
+    #[structopt(long, parse(from_os_str))]
+    manifest_path: Option<PathBuf>,
+
+    /// Binary of the crate defining the graph, if the crate has more than one binary
+    #[structopt(long, requires = "manifest-path")]
+    bin: Option<String>,
+}
+
+#[derive(Debug, thiserror::Error)]
+enum CliError {
+    #[error(transparent)]
+    DgeError(#[from] Error),
+
+    #[error(transparent)]
+    IoError(#[from] std::io::Error),
+
+    #[error("The crate defining the graph failed to run: {}", .0)]
+    GraphDefinitionFailed(String),
+}
+
+fn main() {
+    let command = Command::from_args();
+
+    match run(command) {
+        Ok(()) => (),
+        Err(CliError::DgeError(Error::InvalidGraph { errors })) => {
+            eprintln!("the graph is invalid:");
+            for error in errors {
+                eprintln!("- {}", error);
+            }
+            std::process::exit(1);
+        }
+        Err(CliError::DgeError(Error::OutdatedFiles { dir, files })) => {
+            eprintln!("the generated files in {} are out of date:", dir);
+            for file in files {
+                eprintln!("- {}", file);
+            }
+            std::process::exit(1);
+        }
+        Err(e) => {
+            eprintln!("error: {}", e);
+            std::process::exit(1);
+        }
+    }
+}
+
+fn run(command: Command) -> Result<(), CliError> {
+    match command {
+        Command::Generate {
+            source,
+            output_dir,
+            check,
+        } => {
+            let graph = load(&source)?;
+            let options = graph.options().ok_or(Error::MissingGenerateOptions)?;
+            let files = graph.render(options)?;
+            if check {
+                files.check(&output_dir)?;
+                eprintln!("the generated files in {} are up to date", output_dir.display());
+            } else {
+                std::fs::create_dir_all(&output_dir)?;
+                files.write_to(&output_dir)?;
+                eprintln!("code generated to {}", output_dir.display());
+            }
+        }
+        Command::Scaffold { source, src_dir } => {
+            let graph = load(&source)?;
+            let scaffolded = graph.scaffold(&src_dir)?;
+            if scaffolded.written.is_empty() {
+                eprintln!("all the behaviour modules exist already");
+            }
+            for file in scaffolded.written.iter() {
+                eprintln!("written {}", file.display());
+            }
+            for (parent, declaration) in scaffolded.mod_declarations.iter() {
+                eprintln!("add `{}` to {}", declaration, parent);
+            }
+            for (file, types) in scaffolded.missing_types.iter() {
+                eprintln!(
+                    "{} exists already, add the behaviour types it does not mention: {}",
+                    file.display(),
+                    types.join(", ")
+                );
+            }
+        }
+        Command::PlanMigration {
+            source,
+            from,
+            output,
+        } => {
+            let graph = load(&source)?;
+            let options = graph.options().ok_or(Error::MissingGenerateOptions)?;
+            let previous = serde_json::from_str(&std::fs::read_to_string(&from)?).map_err(Error::from)?;
+            let plan = graph.migration_plan(options, &previous)?;
+            eprint!("{}", plan.describe());
+            let plan = serde_json::to_string_pretty(&plan).map_err(Error::from)?;
+            write_output(output.as_deref(), &format!("{}\n", plan))?;
+        }
+        Command::Deploy {
+            source,
+            output_dir,
+            binary,
+            image,
+            install_dir,
+            env,
+            rmq_uri_env,
+            http_port,
+            replicas,
+            cpu_millis,
+            memory_mib,
+        } => {
+            let graph = load(&source)?;
+            let mut options = DeployOptions::new(binary, image);
+            options.install_dir = install_dir;
+            options.env = env;
+            options.rmq_uri_env = rmq_uri_env;
+            options.http_port = http_port;
+            // the values for every node first, so that the ones for a node win regardless of the order
+            for (_, n) in replicas.iter().filter(|(node, _)| node.is_none()) {
+                options.default.replicas = *n;
+            }
+            for (_, n) in cpu_millis.iter().filter(|(node, _)| node.is_none()) {
+                options.default.cpu_millis = Some(*n);
+            }
+            for (_, n) in memory_mib.iter().filter(|(node, _)| node.is_none()) {
+                options.default.memory_mib = Some(*n);
+            }
+            for (node, n) in replicas.iter() {
+                if let Some(node) = node {
+                    options.node_mut(node).replicas = *n;
+                }
+            }
+            for (node, n) in cpu_millis.iter() {
+                if let Some(node) = node {
+                    options.node_mut(node).cpu_millis = Some(*n);
+                }
+            }
+            for (node, n) in memory_mib.iter() {
+                if let Some(node) = node {
+                    options.node_mut(node).memory_mib = Some(*n);
+                }
+            }
+
+            let files = graph.deploy(&options)?;
+            std::fs::create_dir_all(&output_dir)?;
+            files.write_to(&output_dir)?;
+            eprintln!("deployment manifests written to {}", output_dir.display());
+        }
+        Command::Validate { source } => {
+            let graph = load(&source)?;
+            graph.validate()?;
+            eprintln!("the graph is valid");
+        }
+        Command::Render {
+            source,
+            format,
+            graphviz,
+            output,
+        } => {
+            let graph = load(&source)?;
+            let backend = if graphviz {
+                DiagramBackend::Graphviz
+            } else {
+                DiagramBackend::Builtin
+            };
+            let rendered = match format.as_str() {
+                "dot" => graph.to_dot(),
+                "html" => graph.to_html(),
+                "mermaid" => graph.to_mermaid(),
+                "plantuml" => graph.to_plantuml(),
+                _ => graph.to_svg(backend)?,
+            };
+            write_output(output.as_deref(), &rendered)?;
+        }
+        Command::Describe { source } => {
+            let graph = load(&source)?;
+            print!("{}", graph.describe());
+        }
+        Command::Topology { source, output } => {
+            let graph = load(&source)?;
+            let options = graph.options().ok_or(Error::MissingGenerateOptions)?;
+            let topology = serde_json::to_string_pretty(&graph.topology(options)).map_err(Error::from)?;
+            write_output(output.as_deref(), &format!("{}\n", topology))?;
+        }
+    }
+
+    Ok(())
+}
+
+fn load(source: &Source) -> Result<Graph, CliError> {
+    match (&source.spec, &source.manifest_path) {
+        (Some(spec), _) => Ok(Graph::load(spec)?),
+        (None, Some(manifest_path)) => load_from_crate(manifest_path, source.bin.as_deref()),
+        // this is ruled out by structopt
+        (None, None) => unreachable!("either --spec or --manifest-path is required"),
+    }
+}
+
+/// Run the crate defining the graph, and read the graph it saved.
+fn load_from_crate(manifest_path: &Path, bin: Option<&str>) -> Result<Graph, CliError> {
+    let spec_path =
+        std::env::temp_dir().join(format!("dge-spec-{}.json", std::process::id()));
+
+    let cargo = std::env::var_os("CARGO").unwrap_or_else(|| "cargo".into());
+    let mut cargo = std::process::Command::new(cargo);
+    cargo
+        .arg("run")
+        .arg("--quiet")
+        .arg("--manifest-path")
+        .arg(manifest_path);
+    if let Some(bin) = bin {
+        cargo.arg("--bin").arg(bin);
+    }
+    cargo.env(dge_gen::SPEC_OUTPUT_ENV, &spec_path);
+
+    let status = cargo.status()?;
+    if !status.success() {
+        return Err(CliError::GraphDefinitionFailed(status.to_string()));
+    }
+    if !spec_path.exists() {
+        return Err(CliError::GraphDefinitionFailed(String::from(
+            "no graph is saved, does it call Graph::save_if_requested?",
+        )));
+    }
+
+    let graph = Graph::load(&spec_path);
+    let _ = std::fs::remove_file(&spec_path);
+    Ok(graph?)
+}
+
+fn write_output(output: Option<&Path>, content: &str) -> Result<(), CliError> {
+    match output {
+        None => print!("{}", content),
+        Some(path) => std::fs::write(path, content)?,
+    }
+    Ok(())
+}
+
+/// Parse `NAME=VALUE`.
+fn parse_env(s: &str) -> Result<(String, String), String> {
+    let (name, value) = s
+        .split_once('=')
+        .ok_or_else(|| String::from("expecting NAME=VALUE"))?;
+    Ok((String::from(name), String::from(value)))
+}
+
+/// Parse `value` for every node, or `node=value` for one node,
+/// the node name is accepted in both `snake_case` and `kebab-case`.
+fn parse_per_node<T>(s: &str) -> Result<(Option<String>, T), String>
+where
+    T: std::str::FromStr,
+    T::Err: std::fmt::Display,
+{
+    let (node, value) = match s.split_once('=') {
+        Some((node, value)) => (Some(node.trim().replace('-', "_")), value),
+        None => (None, s),
+    };
+    let value = value.trim().parse::<T>().map_err(|e| e.to_string())?;
+    Ok((node, value))
+}
@@ -0,0 +1,62 @@
+//! Generate the code of the graph from a build script,
+//! so that the executable is always in sync with the graph.
+//!
+//! In `build.rs`:
+//!
+//! ```ignore
+//! fn main() {
+//!     dge_gen::build::generate_from_spec("graph.yaml").unwrap();
+//! }
+//! ```
+//!
+//! And in the source file of the executable, e.g. `src/main.rs`:
+//!
+//! ```ignore
+//! include!(concat!(env!("OUT_DIR"), "/dge/main.rs"));
+//! ```
+//!
+//! Note that a build script cannot use the library of its own crate,
+//! so the graph should be loaded from a spec file, or built with the string based methods of `Graph`.
+
+use std::path::Path;
+use std::path::PathBuf;
+
+use crate::generate;
+use crate::generate::graph::Layout;
+use crate::Error;
+use crate::GenerateOptions;
+use crate::Graph;
+use crate::Result;
+
+/// The sub directory of `OUT_DIR` the code is generated to.
+const SUB_DIR: &str = "dge";
+
+/// Generate the code of the graph to `$OUT_DIR/dge`, and return this directory.
+///
+/// `$OUT_DIR/dge/main.rs` defines `pub fn main`, which runs the subcommand of the node given
+/// in the command line, the code of the nodes is included by it, so it is the only file to include.
+/// The diagrams of the graph are not generated.
+pub fn generate(graph: &Graph, options: &GenerateOptions) -> Result<PathBuf> {
+    let out_dir = std::env::var_os("OUT_DIR")
+        .ok_or_else(|| Error::MissingEnvVar(String::from("OUT_DIR")))?;
+    let dir = Path::new(&out_dir).join(SUB_DIR);
+    std::fs::create_dir_all(&dir)?;
+
+    let layout = Layout::OutDir {
+        sub_dir: String::from(SUB_DIR),
+    };
+    generate::graph::render(graph, options, &layout, false)?.write_to(&dir)?;
+
+    Ok(dir)
+}
+
+/// Same as `generate`, but load the graph and the options from the spec file at `path`,
+/// and ask cargo to rerun the build script when the spec file changes.
+pub fn generate_from_spec<P: AsRef<Path>>(path: P) -> Result<PathBuf> {
+    let path = path.as_ref();
+    println!("cargo:rerun-if-changed={}", path.display());
+
+    let graph = Graph::load(path)?;
+    let options = graph.options().ok_or(Error::MissingGenerateOptions)?;
+    generate(&graph, options)
+}
@@ -0,0 +1,110 @@
+use petgraph::visit::EdgeRef;
+use petgraph::Direction;
+use std::collections::BTreeMap;
+use std::fmt::Write;
+
+use crate::graph::BehaviourKind;
+use crate::graph::Edge;
+use crate::graph::Graph;
+
+/// A human readable summary of the nodes and queues of the graph.
+pub(crate) fn describe(graph: &Graph) -> String {
+    let g = &graph.g;
+    let mut out = String::new();
+
+    // queue -> (producers, consumer, edge)
+    let mut queues: BTreeMap<String, (Vec<String>, String, &Edge)> = BTreeMap::new();
+    for edge in g.edge_references() {
+        let (producers, _, _) = queues.entry(edge.weight().queue.clone()).or_insert_with(|| {
+            (Vec::new(), g[edge.target()].name(), edge.weight())
+        });
+        producers.push(g[edge.source()].name());
+    }
+
+    let _ = writeln!(out, "accept failure: {}", graph.accept_failure);
+    let _ = writeln!(out, "error type: {}", graph.type_error);
+    let _ = writeln!(out);
+
+    let _ = writeln!(out, "{} nodes:", g.node_count());
+    for node_i in g.node_indices() {
+        let node = &g[node_i];
+        let mut inputs: Vec<_> = g
+            .edges_directed(node_i, Direction::Incoming)
+            .map(|e| e.weight().queue.clone())
+            .collect();
+        inputs.sort();
+        inputs.dedup();
+        let mut outputs: Vec<_> = g
+            .edges_directed(node_i, Direction::Outgoing)
+            .map(|e| e.weight().queue.clone())
+            .collect();
+        outputs.sort();
+
+        let _ = writeln!(out, "  {} ({})", node.name(), node.kind());
+        if let Some(behaviour_module) = node.behaviour_module() {
+            match node.behaviour_kind() {
+                Some(BehaviourKind::Type) => {
+                    let _ = writeln!(out, "    behaviour: {} (type)", behaviour_module);
+                }
+                _ => {
+                    let _ = writeln!(out, "    behaviour: {}", behaviour_module);
+                }
+            }
+        }
+        if let Some(type_output) = node.type_output() {
+            let _ = writeln!(out, "    output type: {}", type_output);
+        }
+        if let Some(node_options) = node.node_options() {
+            let mut options = Vec::new();
+            if let Some(n) = node_options.prefetch_count {
+                options.push(format!("prefetch count {}", n));
+            }
+            if let Some(n) = node_options.worker_threads {
+                options.push(format!("{} worker threads", n));
+            }
+            if let Some(n) = node_options.max_in_flight {
+                options.push(format!("at most {} in flight", n));
+            }
+            if !options.is_empty() {
+                let _ = writeln!(out, "    options: {}", options.join(", "));
+            }
+        }
+        if !inputs.is_empty() {
+            let _ = writeln!(out, "    reads from: {}", inputs.join(", "));
+        }
+        if !outputs.is_empty() {
+            let _ = writeln!(out, "    writes to: {}", outputs.join(", "));
+        }
+    }
+    let _ = writeln!(out);
+
+    let _ = writeln!(out, "{} queues:", queues.len());
+    for (queue, (mut producers, consumer, edge)) in queues {
+        producers.sort();
+        let _ = writeln!(out, "  {}", queue);
+        let _ = writeln!(out, "    {} -> {}", producers.join(", "), consumer);
+        let _ = writeln!(out, "    message type: {}", edge.msg_type);
+        let _ = writeln!(
+            out,
+            "    retry interval: {} seconds",
+            edge.retry_interval_in_seconds
+        );
+        let backoff = &edge.retry_backoff;
+        if !backoff.tiers_in_seconds.is_empty() {
+            let tiers: Vec<String> = backoff
+                .tiers_in_seconds
+                .iter()
+                .map(|delay| format!("{}s", delay))
+                .collect();
+            let _ = writeln!(out, "    then backing off: {}", tiers.join(", "));
+        }
+        if backoff.jitter_percent > 0 {
+            let _ = writeln!(out, "    retry jitter: up to {}%", backoff.jitter_percent);
+        }
+        if let Some(max_attempts) = edge.max_attempts {
+            let _ = writeln!(out, "    parked after {} attempts", max_attempts);
+        }
+    }
+
+    out
+}
@@ -0,0 +1,49 @@
+use std::io::Write;
+use std::process::Stdio;
+
+use crate::graph::PetGraph;
+use crate::misc;
+use crate::Error;
+use crate::Result;
+
+/// Render the graph in Graphviz's dot format.
+pub(crate) fn render_dot(g: &PetGraph) -> String {
+    let graph_for_display = map_to_string(g);
+    let dot = petgraph::dot::Dot::with_attr_getters(
+        &graph_for_display,
+        &[],
+        &|_, _| String::from(r#"arrowhead = "onormal""#),
+        &|_, _| String::from(r#"shape = "box" style = "rounded""#)
+    );
+    format!("{}", dot)
+}
+
+/// Convert a dot graph to svg, this requires the `dot` command of Graphviz.
+pub(crate) fn render_svg(dot: &str) -> Result<String> {
+    let mut child = std::process::Command::new("dot")
+        .arg("-Tsvg")
+        .stdin(Stdio::piped())
+        .stdout(Stdio::piped())
+        .spawn()
+        .map_err(|_| Error::GraphvizNotAvailable)?;
+    child
+        .stdin
+        .take()
+        .expect("stdin of dot is piped")
+        .write_all(dot.as_bytes())?;
+    let output = child.wait_with_output()?;
+    if output.status.success() {
+        String::from_utf8(output.stdout).map_err(|_| Error::ErrorGeneratingSvg)
+    } else {
+        Err(Error::ErrorGeneratingSvg)
+    }
+}
+
+fn map_to_string(old: &PetGraph) -> petgraph::Graph<String, String> {
+    let all_msg_types: Vec<String> = old.edge_weights().map(|e| e.msg_type.clone()).collect();
+    let msg_prefix = misc::longest_common_prefix(all_msg_types);
+    old.map(
+        |_, node| node.name(),
+        |_, edge| format!("{}\n{}", edge.queue, edge.msg_type.trim_start_matches(&msg_prefix)),
+    )
+}
@@ -0,0 +1,48 @@
+use std::fmt::Write;
+
+use super::svg;
+use crate::graph::Graph;
+
+/// A standalone html page showing the diagram of the graph,
+/// the details of a node or a queue are shown next to the diagram when hovering over it.
+pub(crate) fn render(graph: &Graph) -> String {
+    let mut out = String::new();
+    let _ = writeln!(out, "<!DOCTYPE html>");
+    let _ = writeln!(out, r#"<html lang="en">"#);
+    let _ = writeln!(out, "<head>");
+    let _ = writeln!(out, r#"<meta charset="utf-8">"#);
+    let _ = writeln!(out, "<title>{}</title>", svg::escape(&title(graph)));
+    let _ = writeln!(out, "<style>{}</style>", STYLE);
+    let _ = writeln!(out, "</head>");
+    let _ = writeln!(out, "<body>");
+    let _ = write!(out, "{}", svg::render(graph));
+    let _ = writeln!(
+        out,
+        r#"<pre id="details">Hover over a node or a queue to see its details.</pre>"#
+    );
+    let _ = writeln!(out, "<script>{}</script>", SCRIPT);
+    let _ = writeln!(out, "</body>");
+    let _ = writeln!(out, "</html>");
+    out
+}
+
+fn title(graph: &Graph) -> String {
+    let names: Vec<_> = graph.g.node_weights().map(|n| n.name()).collect();
+    format!("dge graph: {}", names.join(", "))
+}
+
+const STYLE: &str = r#"
+body { display: flex; align-items: flex-start; gap: 32px; margin: 32px; font-family: sans-serif; }
+#details { min-width: 320px; padding: 16px; background: #f6f8fa; border-radius: 8px; }
+.node, .edge { cursor: default; }
+.node:hover rect, .edge:hover path { stroke: #0969da; stroke-width: 2; }
+"#;
+
+const SCRIPT: &str = r#"
+const details = document.getElementById("details");
+for (const element of document.querySelectorAll(".node, .edge")) {
+    element.addEventListener("mouseenter", () => {
+        details.textContent = element.querySelector("title").textContent;
+    });
+}
+"#;
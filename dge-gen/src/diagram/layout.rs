@@ -0,0 +1,210 @@
+//! A simple layered (Sugiyama style) layout, from top to bottom:
+//!
+//! 1. nodes are assigned to layers by the longest path from the start nodes
+//! 2. edges spanning more than one layer are broken by dummy vertices, one per layer crossed
+//! 3. vertices in each layer are reordered by the barycenter of their neighbours,
+//!    to reduce the crossings of the edges
+//! 4. each layer is centered horizontally
+
+use petgraph::visit::EdgeRef;
+
+use crate::graph::PetGraph;
+
+const MARGIN: f64 = 20.0;
+const NODE_HEIGHT: f64 = 44.0;
+const LAYER_GAP: f64 = 64.0;
+const VERTEX_GAP: f64 = 40.0;
+const DUMMY_WIDTH: f64 = 20.0;
+const ORDERING_SWEEPS: usize = 4;
+
+/// Positions of the nodes and the edges, indexed by their indices in the graph.
+pub(crate) struct Layout {
+    pub(crate) nodes: Vec<NodeBox>,
+    pub(crate) edges: Vec<EdgePath>,
+    pub(crate) width: f64,
+    pub(crate) height: f64,
+}
+
+/// The box of a node, `(x, y)` is its center.
+pub(crate) struct NodeBox {
+    pub(crate) x: f64,
+    pub(crate) y: f64,
+    pub(crate) width: f64,
+    pub(crate) height: f64,
+}
+
+/// An edge, drawn as line segments connecting `points`.
+pub(crate) struct EdgePath {
+    pub(crate) points: Vec<(f64, f64)>,
+}
+
+impl EdgePath {
+    /// Where to put the label of the edge: the middle of its last segment.
+    pub(crate) fn label_position(&self) -> (f64, f64) {
+        let n = self.points.len();
+        let (x0, y0) = self.points[n - 2];
+        let (x1, y1) = self.points[n - 1];
+        ((x0 + x1) / 2.0, (y0 + y1) / 2.0)
+    }
+}
+
+/// Lay out the graph, `node_width` gives the width of each node,
+/// and `slot_width` the horizontal room needed by each edge (e.g. for its label).
+pub(crate) fn layout<N, E>(g: &PetGraph, node_width: N, slot_width: E) -> Layout
+where
+    N: Fn(petgraph::graph::NodeIndex) -> f64,
+    E: Fn(petgraph::graph::EdgeIndex) -> f64,
+{
+    let node_count = g.node_count();
+
+    // 1. layering, the iterations are bounded so that a cycle (in an invalid graph) terminates
+    let mut layer_of = vec![0; node_count];
+    for _ in 0..node_count {
+        let mut changed = false;
+        for edge in g.edge_references() {
+            let (source, target) = (edge.source().index(), edge.target().index());
+            if source != target && layer_of[target] < layer_of[source] + 1 {
+                layer_of[target] = layer_of[source] + 1;
+                changed = true;
+            }
+        }
+        if !changed {
+            break;
+        }
+    }
+
+    // 2. vertices are the nodes followed by the dummies,
+    // and each edge becomes a chain of vertices
+    let mut widths: Vec<f64> = g.node_indices().map(&node_width).collect();
+    let mut chains: Vec<Vec<usize>> = Vec::with_capacity(g.edge_count());
+    for edge in g.edge_references() {
+        let (source, target) = (edge.source().index(), edge.target().index());
+        let mut chain = vec![source];
+        // an edge going upwards is only possible in a cycle, it is drawn directly
+        if layer_of[target] > layer_of[source] {
+            for layer in layer_of[source] + 1..layer_of[target] {
+                layer_of.push(layer);
+                widths.push(DUMMY_WIDTH);
+                chain.push(widths.len() - 1);
+            }
+        }
+        chain.push(target);
+
+        // leave room for the label, which is placed just above the target
+        widths[target] = widths[target].max(slot_width(edge.id()));
+
+        chains.push(chain);
+    }
+    let vertex_count = widths.len();
+
+    let layer_count = layer_of.iter().max().map_or(0, |max| max + 1);
+    let mut layers: Vec<Vec<usize>> = vec![Vec::new(); layer_count];
+    for (vertex, layer) in layer_of.iter().enumerate() {
+        layers[*layer].push(vertex);
+    }
+
+    let mut uppers: Vec<Vec<usize>> = vec![Vec::new(); vertex_count];
+    let mut lowers: Vec<Vec<usize>> = vec![Vec::new(); vertex_count];
+    for chain in chains.iter() {
+        for pair in chain.windows(2) {
+            if layer_of[pair[1]] == layer_of[pair[0]] + 1 {
+                lowers[pair[0]].push(pair[1]);
+                uppers[pair[1]].push(pair[0]);
+            }
+        }
+    }
+
+    // 3. ordering
+    let mut position = vec![0.0; vertex_count];
+    update_positions(&layers, &mut position);
+    for _ in 0..ORDERING_SWEEPS {
+        for vertices in layers.iter_mut().skip(1) {
+            order_by_barycenter(vertices, &uppers, &mut position);
+        }
+        for vertices in layers.iter_mut().rev().skip(1) {
+            order_by_barycenter(vertices, &lowers, &mut position);
+        }
+    }
+
+    // 4. coordinates
+    let layer_widths: Vec<f64> = layers
+        .iter()
+        .map(|vertices| {
+            let gaps = vertices.len().saturating_sub(1) as f64 * VERTEX_GAP;
+            vertices.iter().map(|v| widths[*v]).sum::<f64>() + gaps
+        })
+        .collect();
+    let content_width = layer_widths.iter().cloned().fold(0.0, f64::max);
+
+    let mut centers = vec![(0.0, 0.0); vertex_count];
+    for (layer, vertices) in layers.iter().enumerate() {
+        let y = MARGIN + layer as f64 * (NODE_HEIGHT + LAYER_GAP) + NODE_HEIGHT / 2.0;
+        let mut x = MARGIN + (content_width - layer_widths[layer]) / 2.0;
+        for vertex in vertices {
+            centers[*vertex] = (x + widths[*vertex] / 2.0, y);
+            x += widths[*vertex] + VERTEX_GAP;
+        }
+    }
+
+    let nodes = g
+        .node_indices()
+        .map(|i| {
+            let (x, y) = centers[i.index()];
+            NodeBox {
+                x,
+                y,
+                width: node_width(i),
+                height: NODE_HEIGHT,
+            }
+        })
+        .collect();
+
+    let edges = chains
+        .iter()
+        .map(|chain| {
+            let (source, target) = (chain[0], chain[chain.len() - 1]);
+            let mut points = Vec::with_capacity(chain.len());
+            points.push((centers[source].0, centers[source].1 + NODE_HEIGHT / 2.0));
+            for dummy in &chain[1..chain.len() - 1] {
+                points.push(centers[*dummy]);
+            }
+            points.push((centers[target].0, centers[target].1 - NODE_HEIGHT / 2.0));
+            EdgePath { points }
+        })
+        .collect();
+
+    Layout {
+        nodes,
+        edges,
+        width: content_width + 2.0 * MARGIN,
+        height: (layer_count as f64 * (NODE_HEIGHT + LAYER_GAP) - LAYER_GAP).max(0.0)
+            + 2.0 * MARGIN,
+    }
+}
+
+fn update_positions(layers: &[Vec<usize>], position: &mut [f64]) {
+    for vertices in layers {
+        for (i, vertex) in vertices.iter().enumerate() {
+            position[*vertex] = i as f64;
+        }
+    }
+}
+
+/// Sort the `vertices` of a layer by the average position of their `neighbours`
+/// in the adjacent layer, vertices without neighbours keep their position.
+fn order_by_barycenter(vertices: &mut Vec<usize>, neighbours: &[Vec<usize>], position: &mut [f64]) {
+    let barycenter = |vertex: usize| {
+        let ns = &neighbours[vertex];
+        if ns.is_empty() {
+            position[vertex]
+        } else {
+            ns.iter().map(|n| position[*n]).sum::<f64>() / ns.len() as f64
+        }
+    };
+    let mut keyed: Vec<(f64, usize)> = vertices.iter().map(|v| (barycenter(*v), *v)).collect();
+    keyed.sort_by(|a, b| a.0.partial_cmp(&b.0).unwrap_or(std::cmp::Ordering::Equal));
+    *vertices = keyed.into_iter().map(|(_, v)| v).collect();
+    for (i, vertex) in vertices.iter().enumerate() {
+        position[*vertex] = i as f64;
+    }
+}
@@ -0,0 +1,62 @@
+use petgraph::visit::EdgeRef;
+use std::fmt::Write;
+
+use crate::graph::Graph;
+use crate::graph::Node;
+use crate::misc;
+
+/// Render the graph as a Mermaid flowchart, which can be embedded in Markdown.
+///
+/// Nodes are identified by their indices, since node names may be keywords of Mermaid (e.g. `end`).
+pub(crate) fn render(graph: &Graph) -> String {
+    let g = &graph.g;
+    let all_msg_types: Vec<String> = g.edge_weights().map(|e| e.msg_type.clone()).collect();
+    let msg_prefix = misc::longest_common_prefix(all_msg_types);
+
+    let mut out = String::new();
+    let _ = writeln!(out, "flowchart TD");
+    for node_i in g.node_indices() {
+        let node = &g[node_i];
+        let label = format!("{}<br/>{}", escape(&node.name()), escape(node.kind()));
+        let (open, close) = match node {
+            Node::Start { .. } | Node::Terminate { .. } => ("([", "])"),
+            Node::FanOut { .. } => ("{{", "}}"),
+            Node::Aggregate { .. } => ("[/", "\\]"),
+            Node::UserHandler { .. } => ("(", ")"),
+            Node::Poll { .. } => ("[(", ")]"),
+        };
+        let _ = writeln!(out, "    n{}{}\"{}\"{}", node_i.index(), open, label, close);
+    }
+    for edge in g.edge_references() {
+        let weight = edge.weight();
+        let label = format!(
+            "{}<br/>{}<br/>retry after {}s",
+            escape(&weight.queue),
+            escape(weight.msg_type.trim_start_matches(&msg_prefix)),
+            weight.retry_interval_in_seconds,
+        );
+        let _ = writeln!(
+            out,
+            "    n{} -->|\"{}\"| n{}",
+            edge.source().index(),
+            label,
+            edge.target().index(),
+        );
+    }
+    out
+}
+
+/// Escape text inside a quoted Mermaid label.
+fn escape(s: &str) -> String {
+    let mut escaped = String::with_capacity(s.len());
+    for c in s.chars() {
+        match c {
+            '"' => escaped.push_str("#quot;"),
+            '<' => escaped.push_str("#lt;"),
+            '>' => escaped.push_str("#gt;"),
+            '#' => escaped.push_str("#35;"),
+            c => escaped.push(c),
+        }
+    }
+    escaped
+}
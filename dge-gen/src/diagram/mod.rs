@@ -0,0 +1,30 @@
+use serde::Deserialize;
+use serde::Serialize;
+
+use crate::graph::Graph;
+use crate::Result;
+
+pub(crate) mod graphviz;
+pub(crate) mod html;
+mod layout;
+pub(crate) mod mermaid;
+pub(crate) mod plantuml;
+pub(crate) mod svg;
+
+/// How the svg diagram of the graph is rendered.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum DiagramBackend {
+    /// The renderer shipped with dge, no external tool is needed.
+    #[default]
+    Builtin,
+    /// The `dot` command of Graphviz, which must be installed.
+    Graphviz,
+}
+
+pub(crate) fn render_svg(graph: &Graph, backend: DiagramBackend) -> Result<String> {
+    match backend {
+        DiagramBackend::Builtin => Ok(svg::render(graph)),
+        DiagramBackend::Graphviz => graphviz::render_svg(&graphviz::render_dot(&graph.g)),
+    }
+}
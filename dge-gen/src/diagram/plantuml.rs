@@ -0,0 +1,58 @@
+use petgraph::visit::EdgeRef;
+use std::fmt::Write;
+
+use crate::graph::Graph;
+use crate::misc;
+
+/// Render the graph as a PlantUML diagram, the kind of each node is shown as its stereotype.
+pub(crate) fn render(graph: &Graph) -> String {
+    let g = &graph.g;
+    let all_msg_types: Vec<String> = g.edge_weights().map(|e| e.msg_type.clone()).collect();
+    let msg_prefix = misc::longest_common_prefix(all_msg_types);
+
+    let mut out = String::new();
+    let _ = writeln!(out, "@startuml");
+    let _ = writeln!(out, "skinparam rectangle {{");
+    let _ = writeln!(out, "    RoundCorner 16");
+    let _ = writeln!(out, "}}");
+    for node_i in g.node_indices() {
+        let node = &g[node_i];
+        let _ = writeln!(
+            out,
+            "rectangle \"{}\" as n{} <<{}>>",
+            escape(&node.name()),
+            node_i.index(),
+            node.kind(),
+        );
+    }
+    for edge in g.edge_references() {
+        let weight = edge.weight();
+        let _ = writeln!(
+            out,
+            "n{} --> n{} : {}\\n{}\\nretry after {}s",
+            edge.source().index(),
+            edge.target().index(),
+            escape(&weight.queue),
+            escape(weight.msg_type.trim_start_matches(&msg_prefix)),
+            weight.retry_interval_in_seconds,
+        );
+    }
+    let _ = writeln!(out, "@enduml");
+    out
+}
+
+/// Escape text so that generics are not taken as creole markup, nor quotes end the string.
+fn escape(s: &str) -> String {
+    let mut escaped = String::with_capacity(s.len());
+    for c in s.chars() {
+        match c {
+            '"' => escaped.push('\''),
+            '~' | '<' | '>' => {
+                escaped.push('~');
+                escaped.push(c);
+            }
+            c => escaped.push(c),
+        }
+    }
+    escaped
+}
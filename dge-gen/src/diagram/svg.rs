@@ -0,0 +1,149 @@
+use petgraph::visit::EdgeRef;
+use std::fmt::Write;
+
+use super::layout;
+use crate::graph::Graph;
+use crate::misc;
+
+const FONT_SIZE: f64 = 12.0;
+/// A rough estimation, there is no font metrics to measure the text precisely.
+const CHAR_WIDTH: f64 = 7.0;
+const LINE_HEIGHT: f64 = 14.0;
+const NODE_PADDING: f64 = 24.0;
+
+/// Render the graph as svg, without using any external tool.
+///
+/// Each node and edge is a group with a `<title>` describing it,
+/// which is shown when hovering over it.
+pub(crate) fn render(graph: &Graph) -> String {
+    let g = &graph.g;
+
+    let all_msg_types: Vec<String> = g.edge_weights().map(|e| e.msg_type.clone()).collect();
+    let msg_prefix = misc::longest_common_prefix(all_msg_types);
+    let edge_label = |i: petgraph::graph::EdgeIndex| {
+        let edge = &g[i];
+        (
+            edge.queue.clone(),
+            String::from(edge.msg_type.trim_start_matches(&msg_prefix)),
+        )
+    };
+
+    let layout = layout::layout(
+        g,
+        |i| {
+            let node = &g[i];
+            let chars = node.name().len().max(node.kind().len());
+            chars as f64 * CHAR_WIDTH + NODE_PADDING
+        },
+        |i| {
+            let (queue, msg_type) = edge_label(i);
+            2.0 * queue.len().max(msg_type.len()) as f64 * CHAR_WIDTH
+        },
+    );
+
+    let mut out = String::new();
+    let _ = writeln!(
+        out,
+        r#"<svg xmlns="http://www.w3.org/2000/svg" width="{w:.0}" height="{h:.0}" viewBox="0 0 {w:.0} {h:.0}" font-family="sans-serif" font-size="{f}">"#,
+        w = layout.width,
+        h = layout.height,
+        f = FONT_SIZE,
+    );
+    let _ = writeln!(
+        out,
+        r##"<defs><marker id="arrow" viewBox="0 0 10 10" refX="10" refY="5" markerWidth="8" markerHeight="8" orient="auto"><path d="M 0 0 L 10 5 L 0 10 z" fill="white" stroke="#333"/></marker></defs>"##
+    );
+
+    for edge in g.edge_references() {
+        let weight = edge.weight();
+        let path = &layout.edges[edge.id().index()];
+        let details = format!(
+            "queue: {}\n{} -> {}\nmessage type: {}\nretry interval: {} seconds",
+            weight.queue,
+            g[edge.source()].name(),
+            g[edge.target()].name(),
+            weight.msg_type,
+            weight.retry_interval_in_seconds,
+        );
+        let d = path
+            .points
+            .iter()
+            .enumerate()
+            .map(|(i, (x, y))| format!("{} {:.1} {:.1}", if i == 0 { "M" } else { "L" }, x, y))
+            .collect::<Vec<_>>()
+            .join(" ");
+        let (label_x, label_y) = path.label_position();
+        let (queue, msg_type) = edge_label(edge.id());
+
+        let _ = writeln!(out, r#"<g class="edge">"#);
+        let _ = writeln!(out, "<title>{}</title>", escape(&details));
+        let _ = writeln!(
+            out,
+            r##"<path d="{}" fill="none" stroke="#333" marker-end="url(#arrow)"/>"##,
+            d
+        );
+        let _ = writeln!(
+            out,
+            r##"<text x="{x:.1}" y="{y:.1}" stroke="white" stroke-width="3" paint-order="stroke"><tspan x="{x:.1}">{}</tspan><tspan x="{x:.1}" dy="{dy}" fill="#666">{}</tspan></text>"##,
+            escape(&queue),
+            escape(&msg_type),
+            x = label_x + 6.0,
+            y = label_y - LINE_HEIGHT / 2.0 + FONT_SIZE / 2.0,
+            dy = LINE_HEIGHT,
+        );
+        let _ = writeln!(out, "</g>");
+    }
+
+    for node_i in g.node_indices() {
+        let node = &g[node_i];
+        let node_box = &layout.nodes[node_i.index()];
+
+        let mut details = format!("node: {}\nkind: {}", node.name(), node.kind());
+        if let Some(behaviour_module) = node.behaviour_module() {
+            let _ = write!(details, "\nbehaviour: {}", behaviour_module);
+        }
+        if let Some(type_output) = node.type_output() {
+            let _ = write!(details, "\noutput type: {}", type_output);
+        }
+
+        let _ = writeln!(out, r#"<g class="node">"#);
+        let _ = writeln!(out, "<title>{}</title>", escape(&details));
+        let _ = writeln!(
+            out,
+            r##"<rect x="{:.1}" y="{:.1}" width="{:.1}" height="{:.1}" rx="8" fill="white" stroke="#333"/>"##,
+            node_box.x - node_box.width / 2.0,
+            node_box.y - node_box.height / 2.0,
+            node_box.width,
+            node_box.height,
+        );
+        let _ = writeln!(
+            out,
+            r##"<text x="{x:.1}" y="{y:.1}" text-anchor="middle"><tspan x="{x:.1}" font-weight="bold">{}</tspan><tspan x="{x:.1}" dy="{dy}" fill="#666">{}</tspan></text>"##,
+            escape(&node.name()),
+            escape(node.kind()),
+            x = node_box.x,
+            y = node_box.y - LINE_HEIGHT / 2.0 + FONT_SIZE / 3.0,
+            dy = LINE_HEIGHT,
+        );
+        let _ = writeln!(out, "</g>");
+    }
+
+    let _ = writeln!(out, "</svg>");
+    out
+}
+
+/// Escape text for xml (and html).
+pub(crate) fn escape(s: &str) -> String {
+    let mut escaped = String::with_capacity(s.len());
+    for c in s.chars() {
+        match c {
+            '&' => escaped.push_str("&amp;"),
+            '<' => escaped.push_str("&lt;"),
+            '>' => escaped.push_str("&gt;"),
+            '"' => escaped.push_str("&quot;"),
+            '\'' => escaped.push_str("&#39;"),
+            c => escaped.push(c),
+        }
+    }
+    escaped
+}
@@ -9,6 +9,36 @@ pub enum Error {
     #[error("Node is ill formed: {}", .node)]
     IllFormedNode { node: String },
 
+    #[error("The graph is invalid: {}", display_all(.errors))]
+    InvalidGraph { errors: Vec<ValidationError> },
+
+    #[error("Node {} is referred to, but not defined", .0)]
+    UnknownNode(String),
+
+    #[error("Queue {} is referred to, but no edge uses it", .0)]
+    UnknownQueue(String),
+
+    #[error("No options for generating the code are given")]
+    MissingGenerateOptions,
+
+    #[error("Environment variable {} is not set, is this run by a build script?", .0)]
+    MissingEnvVar(String),
+
+    #[error("Cannot tell the format of graph spec file {}", .0)]
+    UnknownSpecFormat(String),
+
+    #[error(transparent)]
+    JsonError(#[from] serde_json::Error),
+
+    #[error(transparent)]
+    YamlError(#[from] serde_yaml::Error),
+
+    #[error(transparent)]
+    TomlDeError(#[from] toml::de::Error),
+
+    #[error(transparent)]
+    TomlSerError(#[from] toml::ser::Error),
+
     #[error(transparent)]
     AskamaError(#[from] askama::Error),
 
@@ -23,4 +53,97 @@ pub enum Error {
 
     #[error("Failed to generate svg for the dot graph")]
     ErrorGeneratingSvg,
+
+    #[error("Failed to run the dot command, is Graphviz installed?")]
+    GraphvizNotAvailable,
+
+    #[error("The generated files in {} are out of date: {}", .dir, .files.join("; "))]
+    OutdatedFiles { dir: String, files: Vec<String> },
+
+    #[error(
+        "The document is of format version {}, only versions up to {} are supported",
+        .format_version, .supported
+    )]
+    UnsupportedFormatVersion { format_version: u32, supported: u32 },
+
+    #[error("Cannot scaffold the behaviour module of node {}: {}", .node, .reason)]
+    CannotScaffold { node: String, reason: String },
+}
+
+/// A problem found in the graph by `Graph::validate`.
+#[derive(Clone, Debug, PartialEq, Eq, thiserror::Error)]
+pub enum ValidationError {
+    #[error("the nodes {:?} form a cycle", .nodes)]
+    Cycle { nodes: Vec<String> },
+
+    #[error("queue {} is consumed by more than one node: {:?}", .queue, .consumers)]
+    QueueWithMultipleConsumers { queue: String, consumers: Vec<String> },
+
+    #[error("queue {} is used by unrelated edges: {:?}", .queue, .edges)]
+    DuplicateQueue {
+        queue: String,
+        /// `(from, to)` node names of the edges sharing the queue
+        edges: Vec<(String, String)>,
+    },
+
+    #[error("more than one node is named {}", .name)]
+    DuplicateNodeName { name: String },
+
+    #[error("node name {} is reserved for generated code", .name)]
+    ReservedNodeName { name: String },
+
+    #[error("node name {} is not a valid Rust module identifier", .name)]
+    InvalidNodeName { name: String },
+
+    #[error("behaviour module {} of node {} is not a valid Rust path", .behaviour_module, .node)]
+    InvalidBehaviourModule { node: String, behaviour_module: String },
+
+    #[error("queue name {:?} is not valid: {}", .queue, .reason)]
+    InvalidQueueName { queue: String, reason: String },
+
+    #[error("queue name {} is given to more than one queue: {:?}", .queue, .uses)]
+    QueueNameCollision {
+        queue: String,
+        /// what each queue of this name is for, e.g. `retry queue of <work queue>`
+        uses: Vec<String>,
+    },
+
+    #[error(
+        "queue {} carries {}, but its producer {} outputs {}",
+        .queue, .found, .producer, .expected
+    )]
+    MismatchedEdgeType {
+        queue: String,
+        producer: String,
+        /// the output type of the producer
+        expected: String,
+        /// the message type declared by the edge
+        found: String,
+    },
+
+    #[error("start node {} has no output", .start)]
+    StartWithoutOutput { start: String },
+
+    #[error("node {} is not reachable from any start node", .node)]
+    UnreachableNode { node: String },
+
+    #[error("node {} expects {} input queue(s), found {}", .node, .expected, .found)]
+    UnexpectedInputs { node: String, expected: usize, found: usize },
+
+    #[error("node {} can have at most one output, found {:?}", .node, .queues)]
+    TooManyOutputs { node: String, queues: Vec<String> },
+
+    #[error("options of node {} are not valid: {}", .node, .reason)]
+    InvalidNodeOptions { node: String, reason: String },
+
+    #[error("retry policy of queue {} is not valid: {}", .queue, .reason)]
+    InvalidRetryPolicy { queue: String, reason: String },
+}
+
+fn display_all(errors: &[ValidationError]) -> String {
+    errors
+        .iter()
+        .map(|e| e.to_string())
+        .collect::<Vec<_>>()
+        .join("; ")
 }
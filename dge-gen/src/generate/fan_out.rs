@@ -1,38 +1,37 @@
 use askama::Template;
 
+use super::graph::NodeInputs;
+use super::graph::NodeSettings;
+use super::graph::RetryPolicy;
 use super::graph::RmqOptions;
 use super::rust::gen_ident;
 use super::rust::gen_str;
-use super::rust::gen_u32;
 use super::rust::gen_vec_str;
 use crate::Result;
 
 #[derive(Template)]
 #[template(path = "fan_out.rs", escape = "none")]
 struct FanOutTemplate {
+    node: String,
     type_input: String,
     accept_failure: String,
     output_queues: String,
     input_queue: String,
-    prefetch_count: String,
+    node_settings: NodeSettings,
     rmq_options: RmqOptions,
+    retry_policy: RetryPolicy,
 }
 
-pub(crate) fn generate(
-    input_queue: String,
-    output_queues: Vec<String>,
-    accept_failure: String,
-    type_input: String,
-    rmq_options: RmqOptions,
-) -> Result<String> {
+pub(crate) fn generate(inputs: NodeInputs, output_queues: Vec<String>) -> Result<String> {
     let template = FanOutTemplate {
-        type_input: gen_ident(type_input),
-        accept_failure: gen_ident(accept_failure),
+        node: inputs.node,
+        type_input: gen_ident(inputs.type_input),
+        accept_failure: gen_ident(inputs.accept_failure),
         output_queues: gen_vec_str(output_queues),
-        input_queue: gen_str(input_queue),
-        // TODO @incomplete: make it configurable
-        prefetch_count: gen_u32(1),
-        rmq_options,
+        input_queue: gen_str(inputs.input_queue),
+        node_settings: inputs.node_settings,
+        rmq_options: inputs.rmq_options,
+        retry_policy: inputs.retry_policy,
     };
 
     let generated = template.render()?;
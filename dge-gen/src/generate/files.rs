@@ -0,0 +1,137 @@
+use log::info;
+use std::collections::BTreeMap;
+use std::collections::BTreeSet;
+use std::path::Path;
+
+use crate::Error;
+use crate::Result;
+
+/// The file listing the generated files, written together with them,
+/// so that the files no longer generated can be told apart from the files written by hand.
+pub const MANIFEST_FILE_NAME: &str = ".dge-manifest";
+
+const MANIFEST_HEADER: &str = "# files generated by dge, files listed here are deleted when no longer generated";
+
+/// The files generated for a graph, in memory.
+///
+/// The files are kept sorted by their names, so the result is the same for the same graph.
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct GeneratedFiles {
+    files: BTreeMap<String, String>,
+}
+
+impl GeneratedFiles {
+    pub(crate) fn new() -> GeneratedFiles {
+        GeneratedFiles::default()
+    }
+
+    pub(crate) fn insert<S: Into<String>>(&mut self, file_name: S, content: S) {
+        self.files.insert(file_name.into(), content.into());
+    }
+
+    /// The file names (relative to the output directory) and the contents, sorted by the file names.
+    pub fn iter(&self) -> impl Iterator<Item = (&str, &str)> {
+        self.files
+            .iter()
+            .map(|(file_name, content)| (file_name.as_str(), content.as_str()))
+    }
+
+    /// The content of the file named `file_name`.
+    pub fn get(&self, file_name: &str) -> Option<&str> {
+        self.files.get(file_name).map(String::as_str)
+    }
+
+    /// The names of the files, sorted.
+    pub fn file_names(&self) -> Vec<&str> {
+        self.files.keys().map(String::as_str).collect()
+    }
+
+    /// Write the files, together with the manifest, to `dir`.
+    ///
+    /// Files listed in the manifest of a previous run but not generated anymore,
+    /// e.g. the modules of removed or renamed nodes, are deleted.
+    /// Other files in `dir` are left untouched.
+    pub fn write_to<P: AsRef<Path>>(&self, dir: P) -> Result<()> {
+        let dir = dir.as_ref();
+
+        for file_name in self.stale_files(dir)? {
+            let file_path = dir.join(&file_name);
+            info!("deleting stale file {}", file_path.display());
+            match std::fs::remove_file(&file_path) {
+                Err(e) if e.kind() != std::io::ErrorKind::NotFound => return Err(e.into()),
+                _ => (),
+            }
+        }
+
+        for (file_name, content) in self.files.iter() {
+            let file_path = dir.join(file_name);
+            info!("writing to {}", file_path.display());
+            std::fs::write(&file_path, content)?;
+        }
+
+        std::fs::write(dir.join(MANIFEST_FILE_NAME), self.manifest())?;
+        Ok(())
+    }
+
+    /// Compare the files with the ones in `dir`, without writing anything.
+    ///
+    /// `Error::OutdatedFiles` lists the files which are missing or differ,
+    /// and the stale files which `write_to` would delete.
+    pub fn check<P: AsRef<Path>>(&self, dir: P) -> Result<()> {
+        let dir = dir.as_ref();
+        let mut files = Vec::new();
+
+        for (file_name, content) in self.files.iter() {
+            match std::fs::read_to_string(dir.join(file_name)) {
+                Ok(existing) if &existing == content => (),
+                Ok(_) => files.push(format!("{} differs", file_name)),
+                Err(e) if e.kind() == std::io::ErrorKind::NotFound => {
+                    files.push(format!("{} is missing", file_name))
+                }
+                Err(e) => return Err(e.into()),
+            }
+        }
+        for file_name in self.stale_files(dir)? {
+            if dir.join(&file_name).exists() {
+                files.push(format!("{} is no longer generated", file_name));
+            }
+        }
+
+        if files.is_empty() {
+            Ok(())
+        } else {
+            Err(Error::OutdatedFiles {
+                dir: dir.display().to_string(),
+                files,
+            })
+        }
+    }
+
+    fn manifest(&self) -> String {
+        let mut manifest = String::from(MANIFEST_HEADER);
+        manifest.push('\n');
+        for file_name in self.files.keys() {
+            manifest.push_str(file_name);
+            manifest.push('\n');
+        }
+        manifest
+    }
+
+    /// The files listed in the manifest in `dir` but not in `self`.
+    fn stale_files(&self, dir: &Path) -> Result<BTreeSet<String>> {
+        let manifest = match std::fs::read_to_string(dir.join(MANIFEST_FILE_NAME)) {
+            Ok(manifest) => manifest,
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => return Ok(BTreeSet::new()),
+            Err(e) => return Err(e.into()),
+        };
+        Ok(manifest
+            .lines()
+            .map(str::trim)
+            .filter(|line| !line.is_empty() && !line.starts_with('#'))
+            // never follow a path out of `dir`
+            .filter(|file_name| !file_name.contains('/') && !file_name.contains('\\'))
+            .filter(|file_name| !self.files.contains_key(*file_name))
+            .map(String::from)
+            .collect())
+    }
+}
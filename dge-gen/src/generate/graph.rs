@@ -1,19 +1,27 @@
-use log::info;
 use petgraph::Direction;
-use std::collections::HashMap;
+use std::collections::BTreeMap;
 use std::collections::HashSet;
 use std::path::Path;
-use std::path::PathBuf;
 
+use crate::graph::BehaviourKind;
 use crate::graph::Edge;
+use crate::graph::GenerateOptions;
 use crate::graph::Graph;
 use crate::graph::Node;
 use crate::graph::NodeIndex;
+use crate::graph::NodeOptions;
 use crate::graph::PetGraph;
 use crate::Error;
 use crate::Result;
 
-use crate::misc;
+use crate::diagram;
+
+use super::files::GeneratedFiles;
+
+use super::rust::gen_opt_u32;
+use super::rust::gen_opt_usize;
+use super::rust::gen_u16;
+use super::rust::gen_usize;
 
 #[derive(Clone)]
 pub(crate) struct RmqOptions {
@@ -24,251 +32,262 @@ pub(crate) struct RmqOptions {
     pub(crate) retry_queue_suffix: String,
 }
 
-/// Generate codes corresponding the graph.
-pub(crate) fn generate<P: AsRef<Path>, S: AsRef<str>>(
-    graph: Graph,
+impl RmqOptions {
+    /// The name of the retry queue for the work queue `queue`.
+    pub(crate) fn retry_queue(&self, queue: &str) -> String {
+        format!("{}{}{}", self.retry_queue_prefix, queue, self.retry_queue_suffix)
+    }
+
+    /// The name of the retry queue of the `tier`-th (from 1) backoff tier for the work queue `queue`.
+    pub(crate) fn retry_tier_queue(&self, queue: &str, tier: usize) -> String {
+        format!("{}_{}", self.retry_queue(queue), tier)
+    }
+
+    /// The name of the parking queue for the work queue `queue`.
+    pub(crate) fn parking_queue(&self, queue: &str) -> String {
+        format!("{}_parked", queue)
+    }
+}
+
+/// How the messages rejected from the input queue of a node are retried,
+/// see `dge_runtime::retry::RetryPolicy`.
+#[derive(Clone)]
+pub(crate) struct RetryPolicy {
+    pub(crate) retry_queue: String,
+    pub(crate) retry_interval_in_seconds: u32,
+    /// `(retry queue, delay in seconds)` of each backoff tier
+    pub(crate) tiers: Vec<(String, u32)>,
+    pub(crate) jitter_percent: u8,
+    /// as a Rust expression
+    pub(crate) max_attempts: String,
+    pub(crate) parking_queue: String,
+}
+
+impl RetryPolicy {
+    fn new(rmq_options: &RmqOptions, input: &Edge) -> Self {
+        RetryPolicy {
+            retry_queue: rmq_options.retry_queue(&input.queue),
+            retry_interval_in_seconds: input.retry_interval_in_seconds,
+            tiers: retry_tiers(rmq_options, input),
+            jitter_percent: input.retry_backoff.jitter_percent,
+            max_attempts: gen_opt_u32(input.max_attempts),
+            parking_queue: rmq_options.parking_queue(&input.queue),
+        }
+    }
+}
+
+/// `(retry queue, delay in seconds)` of each backoff tier of the edge.
+pub(crate) fn retry_tiers(rmq_options: &RmqOptions, edge: &Edge) -> Vec<(String, u32)> {
+    edge.retry_backoff
+        .tiers_in_seconds
+        .iter()
+        .enumerate()
+        .map(|(i, delay)| (rmq_options.retry_tier_queue(&edge.queue, i + 1), *delay))
+        .collect()
+}
+
+impl From<&GenerateOptions> for RmqOptions {
+    fn from(options: &GenerateOptions) -> Self {
+        RmqOptions {
+            get_rmq_uri: options.get_rmq_uri.clone(),
+            work_exchange: options.work_exchange.clone(),
+            retry_exchange: options.retry_exchange.clone(),
+            retry_queue_prefix: options.retry_queue_prefix.clone(),
+            retry_queue_suffix: options.retry_queue_suffix.clone(),
+        }
+    }
+}
+
+const DEFAULT_PREFETCH_COUNT: u16 = 1;
+const DEFAULT_WORKER_THREADS: usize = 2;
+/// poll nodes spend most of their time waiting for the external systems
+const DEFAULT_POLL_WORKER_THREADS: usize = 10;
+
+/// The runtime options of a node as Rust expressions,
+/// these are the defaults, which can be overridden when launching the node.
+#[derive(Clone)]
+pub(crate) struct NodeSettings {
+    pub(crate) prefetch_count: String,
+    pub(crate) worker_threads: String,
+    pub(crate) max_in_flight: String,
+}
+
+impl NodeSettings {
+    fn new(options: &NodeOptions, default_worker_threads: usize) -> Self {
+        NodeSettings {
+            prefetch_count: gen_u16(options.prefetch_count.unwrap_or(DEFAULT_PREFETCH_COUNT)),
+            worker_threads: gen_usize(options.worker_threads.unwrap_or(default_worker_threads)),
+            max_in_flight: gen_opt_usize(options.max_in_flight),
+        }
+    }
+}
+
+/// What the code of a node is generated from, common to all the kinds of nodes,
+/// built once for each node by `generate_sources`.
+pub(crate) struct NodeInputs {
+    pub(crate) node: String,
+    pub(crate) input_queue: String,
+    pub(crate) type_input: String,
+    pub(crate) type_error: String,
+    pub(crate) accept_failure: String,
+    pub(crate) rmq_options: RmqOptions,
+    pub(crate) retry_policy: RetryPolicy,
+    pub(crate) node_settings: NodeSettings,
+}
+
+impl NodeInputs {
+    fn new(graph: &Graph, node: &Node, input: &Edge, rmq_options: &RmqOptions) -> Self {
+        let default_worker_threads = match node {
+            Node::Poll { .. } => DEFAULT_POLL_WORKER_THREADS,
+            _ => DEFAULT_WORKER_THREADS,
+        };
+        let node_options = node.node_options().cloned().unwrap_or_default();
+        NodeInputs {
+            node: node.name(),
+            input_queue: input.queue.clone(),
+            type_input: input.msg_type.clone(),
+            type_error: graph.type_error.clone(),
+            accept_failure: graph.accept_failure.clone(),
+            rmq_options: rmq_options.clone(),
+            retry_policy: RetryPolicy::new(rmq_options, input),
+            node_settings: NodeSettings::new(&node_options, default_worker_threads),
+        }
+    }
+}
+
+/// The user-provided behaviour of a node, for the kinds of nodes having one.
+pub(crate) struct Behaviour {
+    pub(crate) module: String,
+    pub(crate) kind: BehaviourKind,
+    pub(crate) type_output: String,
+}
+
+impl Behaviour {
+    fn new(module: &str, kind: BehaviourKind, type_output: &str) -> Self {
+        Behaviour {
+            module: String::from(module),
+            kind,
+            type_output: String::from(type_output),
+        }
+    }
+}
+
+/// Where the generated files are placed,
+/// this decides how `main.rs` refers to the modules of the other nodes.
+#[derive(Clone, Debug)]
+pub(crate) enum Layout {
+    /// The files are part of the source tree, they are declared with `mod node;`
+    SourceTree,
+    /// The files are in `sub_dir` of `OUT_DIR`, they are declared with
+    /// `mod node { include!(concat!(env!("OUT_DIR"), "/sub_dir/node.rs")); }`
+    OutDir { sub_dir: String },
+}
+
+/// Generate codes corresponding the graph, and write them, together with the diagrams, to `dir`.
+pub(crate) fn generate<P: AsRef<Path>>(
+    graph: &Graph,
     dir: P,
-    rmq_options: RmqOptions,
-    init_input_queue: bool,
-    init_output_queue: bool,
-    main_init: S,
+    options: &GenerateOptions,
 ) -> Result<()> {
-    let g = &graph.g;
-    let dir = dir.as_ref();
-    let main_init = main_init.as_ref();
+    render(graph, options, &Layout::SourceTree, true)?.write_to(dir)
+}
+
+/// Generate codes corresponding the graph, and optionally the diagrams, in memory.
+pub(crate) fn render(
+    graph: &Graph,
+    options: &GenerateOptions,
+    layout: &Layout,
+    with_diagrams: bool,
+) -> Result<GeneratedFiles> {
+    let mut files = GeneratedFiles::new();
+    for (module, content) in generate_sources(graph, options, layout)? {
+        files.insert(format!("{}.rs", module), content);
+    }
+
+    if with_diagrams {
+        files.insert(String::from("graph.dot"), diagram::graphviz::render_dot(&graph.g));
+        files.insert(String::from("graph.svg"), diagram::render_svg(graph, options.diagram)?);
+        files.insert(String::from("graph.html"), diagram::html::render(graph));
+    }
+
+    Ok(files)
+}
+
+/// Generate codes corresponding the graph, in memory.
+///
+/// The result maps module names (e.g. the name of the node, `main`) to their content.
+pub(crate) fn generate_sources(
+    graph: &Graph,
+    options: &GenerateOptions,
+    layout: &Layout,
+) -> Result<BTreeMap<String, String>> {
+    graph.validate_with_options(Some(options))?;
 
-    let mut outputs = HashMap::new();
+    let g = &graph.g;
+    let rmq_options = RmqOptions::from(options);
 
-    // TODO @incomplete: check the validity of the graph
-    // - no cycle
-    // - every node has exactly one input queue
-    // - every queue has exactly one consumer
-    // - ?
+    let mut outputs = BTreeMap::new();
 
     // generate code for each node
     for node_i in g.node_indices() {
         let node = &g[node_i];
-        match node {
+        let content = match node {
             // start node doesn't need any code
-            Node::Start { .. } => (),
+            Node::Start { .. } => continue,
             // terminate node doesn't need any code
-            Node::Terminate { .. } => (),
-            Node::Aggregate { name, behaviour_module } => {
-                let content = generate_aggregate(
-                    g,
-                    node_i,
-                    behaviour_module.into(),
-                    graph.accept_failure.clone(),
-                    rmq_options.clone(),
-                )?;
-                update_outputs(&mut outputs, dir, name, content);
+            Node::Terminate { .. } => continue,
+            Node::Aggregate { behaviour_module, behaviour_kind, type_output, .. } => {
+                let input = expect_one_input_edge_for_aggregation_node(g, node_i)?;
+                super::aggregate::generate(
+                    NodeInputs::new(graph, node, &input, &rmq_options),
+                    Behaviour::new(behaviour_module, *behaviour_kind, type_output),
+                    expect_optional_outgoing_edge(g, node_i)?.map(|e| e.queue.clone()),
+                )?
             }
-            Node::FanOut { name } => {
-                let content = generate_fan_out(
-                        g,
-                        node_i,
-                        graph.accept_failure.clone(),
-                        rmq_options.clone()
-                    )?;
-                update_outputs(&mut outputs, dir, name, content);
+            Node::FanOut { .. } => {
+                let input = expect_one_incoming_edge(g, node_i)?;
+                let output_queues = g
+                    .edges_directed(node_i, Direction::Outgoing)
+                    .map(|out_edge| out_edge.weight().queue.clone())
+                    .collect();
+                super::fan_out::generate(
+                    NodeInputs::new(graph, node, input, &rmq_options),
+                    output_queues,
+                )?
             }
-            Node::UserHandler {
-                name,
-                behaviour_module,
-            } => {
-                let content = generate_user_handler(
-                    g,
-                    node_i,
-                    behaviour_module.into(),
-                    graph.accept_failure.clone(),
-                    rmq_options.clone(),
-                )?;
-                update_outputs(&mut outputs, dir, name, content);
+            Node::UserHandler { behaviour_module, behaviour_kind, type_output, .. } => {
+                let input = expect_one_incoming_edge(g, node_i)?;
+                super::user_handler::generate(
+                    NodeInputs::new(graph, node, input, &rmq_options),
+                    Behaviour::new(behaviour_module, *behaviour_kind, type_output),
+                    expect_optional_outgoing_edge(g, node_i)?.map(|e| e.queue.clone()),
+                )?
             }
-            Node::Poll {
-                name,
-                behaviour_module,
-            } => {
-                let content = generate_poll(
-                    g,
-                    node_i,
-                    behaviour_module.into(),
-                    graph.accept_failure.clone(),
-                    rmq_options.clone(),
-                )?;
-                update_outputs(&mut outputs, dir, name, content);
+            Node::Poll { behaviour_module, behaviour_kind, type_output, .. } => {
+                let input = expect_one_incoming_edge(g, node_i)?;
+                super::poll::generate(
+                    NodeInputs::new(graph, node, input, &rmq_options),
+                    Behaviour::new(behaviour_module, *behaviour_kind, type_output),
+                    expect_optional_outgoing_edge(g, node_i)?.map(|e| e.queue.clone()),
+                )?
             }
-        }
+        };
+        outputs.insert(node.name(), content);
     }
 
     // generate queue declarations
     let content = generate_init_exchanges_and_queues(
         g,
         rmq_options.clone(),
-        init_input_queue,
-        init_output_queue,
+        options.init_input_queue,
+        options.init_output_queue,
     )?;
-    update_outputs(&mut outputs, dir, "init_exchanges_and_queues", content);
-
-    let content = generate_main(&outputs, main_init)?;
-    update_outputs(&mut outputs, dir, "main", content);
-
-    // write the graph in dot format
-    let graph_for_display = map_to_string(g);
-    let dot = petgraph::dot::Dot::with_attr_getters(
-        &graph_for_display,
-        &[],
-        &|_, _| String::from(r#"arrowhead = "onormal""#),
-        &|_, _| String::from(r#"shape = "box" style = "rounded""#)
-    );
-    let dot_file_path = dir.join("graph.dot");
-    info!("writing dot graph to {}", &dot_file_path.display());
-    std::fs::write(&dot_file_path, format!("{}", dot))?;
-    let f = std::fs::OpenOptions::new()
-        .create(false)
-        .read(true)
-        .write(false)
-        .open(&dot_file_path)?
-        .sync_all()?;
-
-    // convert dot to svg
-    let svg_file_path = dir.join("graph.svg");
-    let gen_svg_output = std::process::Command::new("dot")
-        .arg("-Tsvg")
-        .arg(
-            dot_file_path
-                .to_str()
-                .ok_or(Error::InvalidFileName(dot_file_path.display().to_string()))?,
-        )
-        .arg("-o")
-        .arg(
-            svg_file_path
-                .to_str()
-                .ok_or(Error::InvalidFileName(svg_file_path.display().to_string()))?,
-        )
-        .output()?;
-    if gen_svg_output.status.success() {
-        ()
-    } else {
-        return Err(Error::ErrorGeneratingSvg);
-    }
-
-    let mut mods = Vec::new();
-    // write each file
-    for (file_path, content) in outputs.iter() {
-        info!("writing to {}", file_path.display());
-        std::fs::write(file_path, content)?;
-
-        match file_path.file_stem() {
-            None => return Err(Error::InvalidFileName((file_path).display().to_string())),
-            Some(file_stem) => match (*file_stem).to_str() {
-                None => return Err(Error::InvalidFileName((file_path).display().to_string())),
-                Some(s) => mods.push(format!("pub(crate) mod {};", s)),
-            },
-        }
-    }
-
-    Ok(())
-}
-
-fn generate_aggregate(
-    g: &PetGraph,
-    node_i: NodeIndex,
-    behaviour_module: String,
-    accept_failure: String,
-    rmq_options: RmqOptions,
-) -> Result<String> {
-    let Edge {
-        queue: input_queue,
-        msg_type: type_input,
-        retry_interval_in_seconds: _,
-    } = expect_one_input_edge_for_aggregation_node(g, node_i)?;
-    let output_queue = expect_optional_outgoing_edge(g, node_i)?.map(|e| e.queue.clone());
-    super::aggregate::generate(
-        input_queue,
-        behaviour_module,
-        output_queue,
-        accept_failure,
-        type_input,
-        rmq_options,
-    )
-}
-
-fn generate_fan_out(
-    g: &PetGraph,
-    node_i: NodeIndex,
-    accept_failure: String,
-    rmq_options: RmqOptions,
-) -> Result<String> {
-    let Edge {
-        queue: input_queue,
-        msg_type: type_input,
-        retry_interval_in_seconds: _,
-    } = expect_one_incoming_edge(g, node_i)?;
-
-    // find output queues
-    // TODO @incomplete: check for duplicate queues
-    let mut output_queues = Vec::new();
-    let out_edges = g.edges_directed(node_i, Direction::Outgoing);
-    for out_edge in out_edges {
-        let output_queue = out_edge.weight().queue.clone();
-        output_queues.push(output_queue)
-    }
-
-    super::fan_out::generate(
-        input_queue.clone(),
-        output_queues,
-        accept_failure,
-        type_input.clone(),
-        rmq_options.clone(),
-    )
-}
-
-fn generate_user_handler(
-    g: &PetGraph,
-    node_i: NodeIndex,
-    module: String,
-    accept_failure: String,
-    rmq_options: RmqOptions,
-) -> Result<String> {
-    let Edge {
-        queue: input_queue,
-        msg_type: type_input,
-        retry_interval_in_seconds: _,
-    } = expect_one_incoming_edge(g, node_i)?;
-    let output_queue = expect_optional_outgoing_edge(g, node_i)?.map(|e| e.queue.clone());
-    super::user_handler::generate(
-        input_queue.clone(),
-        output_queue,
-        module,
-        accept_failure,
-        type_input.clone(),
-        rmq_options,
-    )
-}
+    outputs.insert(String::from("init_exchanges_and_queues"), content);
 
+    let content = generate_main(g, &outputs, &options.main_init, &rmq_options, layout)?;
+    outputs.insert(String::from("main"), content);
 
-fn generate_poll(
-    g: &PetGraph,
-    node_i: NodeIndex,
-    module: String,
-    accept_failure: String,
-    rmq_options: RmqOptions,
-) -> Result<String> {
-    let Edge {
-        queue: input_queue,
-        msg_type: type_input,
-        retry_interval_in_seconds: _,
-    } = expect_one_incoming_edge(g, node_i)?;
-    let output_queue = expect_optional_outgoing_edge(g, node_i)?.map(|e| e.queue.clone());
-    super::poll::generate(
-        input_queue.clone(),
-        output_queue,
-        module,
-        accept_failure,
-        type_input.clone(),
-        rmq_options,
-    )
+    Ok(outputs)
 }
 
 fn expect_one_incoming_edge(g: &PetGraph, node_i: NodeIndex) -> Result<&Edge> {
@@ -311,34 +330,13 @@ fn expect_one_input_edge_for_aggregation_node(g: &PetGraph, node_i: NodeIndex) -
     }
 }
 
-fn update_outputs<P: AsRef<Path>, S: AsRef<str>>(
-    outputs: &mut HashMap<PathBuf, String>,
-    dir: P,
-    name: S,
-    content: String,
-) {
-    let dir = dir.as_ref();
-    let basename = format!("{}.rs", name.as_ref());
-    let file_path = dir.join(basename);
-    outputs.insert(file_path, content);
-}
-
-fn map_to_string(old: &PetGraph) -> petgraph::Graph<String, String> {
-    let all_msg_types: Vec<String> = old.edge_weights().map(|e| e.msg_type.clone()).collect();
-    let msg_prefix = misc::longest_common_prefix(all_msg_types);
-    old.map(
-        |node_index, node| node.name(),
-        |edge_index, edge| format!("{}\n{}", edge.queue, edge.msg_type.trim_start_matches(&msg_prefix)),
-    )
-}
-
 fn generate_init_exchanges_and_queues(graph: &PetGraph, rmq_options: RmqOptions, init_input_queue: bool, init_output_queue: bool) -> Result<String> {
     let mut all_queues: Vec<_> = graph
         .edge_weights()
         .map(|edge|
             (
                 (&edge.queue).clone(),
-                format!("{}{}{}", &rmq_options.retry_queue_prefix, &edge.queue, &rmq_options.retry_queue_suffix),
+                rmq_options.retry_queue(&edge.queue),
                 edge.retry_interval_in_seconds,
             )
         ).collect();
@@ -386,27 +384,67 @@ fn generate_init_exchanges_and_queues(graph: &PetGraph, rmq_options: RmqOptions,
     }
     wanted_queues.sort();
     wanted_queues.dedup();
-    super::init_exchanges_and_queues::generate(rmq_options, wanted_queues)
+    // (work_queue, retry_queue_of_tier)
+    let mut retry_tiers: Vec<(String, String)> = graph
+        .edge_weights()
+        .filter(|edge| !unwanted_queues.contains(&edge.queue))
+        .flat_map(|edge| {
+            self::retry_tiers(&rmq_options, edge)
+                .into_iter()
+                .map(move |(retry_queue, _)| (edge.queue.clone(), retry_queue))
+        })
+        .collect();
+    retry_tiers.sort();
+    retry_tiers.dedup();
+    let parking_queues = parking_queues(graph, &rmq_options)
+        .into_iter()
+        .filter(|(queue, _)| !unwanted_queues.contains(queue))
+        .map(|(_, parking_queue)| parking_queue)
+        .collect();
+    super::init_exchanges_and_queues::generate(
+        rmq_options,
+        wanted_queues,
+        retry_tiers,
+        parking_queues,
+    )
 }
 
-fn generate_main<S: AsRef<str>>(outputs: &HashMap<PathBuf, String>, main_init: S) -> Result<String> {
-    let main_init = main_init.as_ref();
-    let mut modules = Vec::new();
-    for file_path in outputs.keys() {
-        let file_stem = file_path
-            .file_stem()
-            .ok_or(Error::InvalidFileName(file_path.display().to_string()))?
-            .to_str()
-            .ok_or(Error::InvalidFileName(file_path.display().to_string()))?;
-
-        if file_stem == "main" {
-            continue
-        }
-
-        modules.push(String::from(file_stem));
-    }
+/// `(work queue, parking queue)` of each queue with a maximum number of attempts.
+fn parking_queues(graph: &PetGraph, rmq_options: &RmqOptions) -> Vec<(String, String)> {
+    let mut parking_queues: Vec<_> = graph
+        .edge_weights()
+        .filter(|edge| edge.max_attempts.is_some())
+        .map(|edge| (edge.queue.clone(), rmq_options.parking_queue(&edge.queue)))
+        .collect();
+    parking_queues.sort();
+    parking_queues.dedup();
+    parking_queues
+}
 
-    modules.sort();
+fn generate_main<S: AsRef<str>>(
+    graph: &PetGraph,
+    outputs: &BTreeMap<String, String>,
+    main_init: S,
+    rmq_options: &RmqOptions,
+    layout: &Layout,
+) -> Result<String> {
+    // every module except the queue declarations runs a node
+    let modules: Vec<(String, bool)> = outputs
+        .keys()
+        .filter(|module| module.as_str() != "main")
+        .map(|module| (module.clone(), module.as_str() != "init_exchanges_and_queues"))
+        .collect();
+
+    let include_dir = match layout {
+        Layout::SourceTree => None,
+        Layout::OutDir { sub_dir } => Some(sub_dir.clone()),
+    };
 
-    super::main::generate(modules, main_init)
-}
\ No newline at end of file
+    super::main::generate(
+        modules,
+        main_init,
+        rmq_options,
+        parking_queues(graph, rmq_options),
+        include_dir,
+    )
+}
@@ -8,16 +8,22 @@ use crate::Result;
 #[template(path = "init_exchanges_and_queues.rs", escape = "none")]
 struct DeclareTemplate {
     rmq_options: RmqOptions,
-    all_queues: Vec<(String, String, u32)>
+    all_queues: Vec<(String, String, u32)>,
+    all_retry_tiers: Vec<(String, String)>,
+    all_parking_queues: Vec<String>,
 }
 
 pub(crate) fn generate(
     rmq_options: RmqOptions,
     all_queues: Vec<(String, String, u32)>,
+    all_retry_tiers: Vec<(String, String)>,
+    all_parking_queues: Vec<String>,
 ) -> Result<String> {
     let template = DeclareTemplate {
         rmq_options,
         all_queues,
+        all_retry_tiers,
+        all_parking_queues,
     };
 
     let generated = template.render()?;
@@ -1,11 +1,14 @@
 use askama::Template;
 use heck::CamelCase;
 
+use super::graph::RmqOptions;
 use crate::Result;
 
 struct Command {
     module: String,
     variant: String,
+    /// whether the subcommand runs a node, and accepts `dge_runtime::NodeOptions`
+    runs_node: bool,
 }
 
 #[derive(Template)]
@@ -13,26 +16,40 @@ struct Command {
 struct MainTemplate {
     commands: Vec<Command>,
     setup_logger: String,
+    get_rmq_uri: String,
+    work_exchange: String,
+    /// `(work queue, parking queue)`
+    parking_queues: Vec<(String, String)>,
+    /// when given, the modules are included from this sub directory of `OUT_DIR`
+    include_dir: Option<String>,
 }
 
 pub(crate) fn generate<S: AsRef<str>>(
-    modules: Vec<String>,
+    modules: Vec<(String, bool)>,
     setup_logger: S,
+    rmq_options: &RmqOptions,
+    parking_queues: Vec<(String, String)>,
+    include_dir: Option<String>,
 ) -> Result<String> {
     let setup_logger = setup_logger.as_ref();
 
     let mut commands = Vec::new();
-    for module in modules {
+    for (module, runs_node) in modules {
         let variant = &module.to_camel_case();
         commands.push(Command {
             module,
-            variant: variant.clone()
+            variant: variant.clone(),
+            runs_node,
         })
     }
 
     let template = MainTemplate {
         commands,
         setup_logger: String::from(setup_logger),
+        get_rmq_uri: rmq_options.get_rmq_uri.clone(),
+        work_exchange: rmq_options.work_exchange.clone(),
+        parking_queues,
+        include_dir,
     };
 
     let generated = template.render()?;
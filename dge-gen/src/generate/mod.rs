@@ -7,4 +7,5 @@ mod user_handler;
 mod init_exchanges_and_queues;
 mod poll;
 
+pub(crate) mod files;
 pub(crate) mod graph;
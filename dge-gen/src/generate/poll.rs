@@ -1,41 +1,51 @@
 use askama::Template;
 
+use super::graph::Behaviour;
+use super::graph::NodeInputs;
+use super::graph::NodeSettings;
+use super::graph::RetryPolicy;
 use super::graph::RmqOptions;
 use super::rust::gen_ident;
 use super::rust::gen_opt_str;
 use super::rust::gen_str;
-use super::rust::gen_u32;
+use crate::BehaviourKind;
 use crate::Result;
 
 #[derive(Template)]
 #[template(path = "poll.rs", escape = "none")]
 struct PollTemplate {
+    node: String,
     type_input: String,
+    type_output: String,
+    type_error: String,
     accept_failure: String,
     output_queue: String,
     input_queue: String,
-    prefetch_count: String,
+    node_settings: NodeSettings,
     behaviour_module: String,
+    behaviour_is_type: bool,
     rmq_options: RmqOptions,
+    retry_policy: RetryPolicy,
 }
 
 pub(crate) fn generate(
-    input_queue: String,
+    inputs: NodeInputs,
+    behaviour: Behaviour,
     output_queue: Option<String>,
-    behaviour_module: String,
-    accept_failure: String,
-    type_input: String,
-    rmq_options: RmqOptions,
 ) -> Result<String> {
     let template = PollTemplate {
-        type_input: gen_ident(type_input),
-        accept_failure: gen_ident(accept_failure),
+        node: inputs.node,
+        type_input: gen_ident(inputs.type_input),
+        type_output: gen_ident(behaviour.type_output),
+        type_error: gen_ident(inputs.type_error),
+        accept_failure: gen_ident(inputs.accept_failure),
         output_queue: gen_opt_str(output_queue),
-        input_queue: gen_str(input_queue),
-        // TODO @incomplete: make it configurable
-        prefetch_count: gen_u32(1),
-        behaviour_module: gen_ident(behaviour_module),
-        rmq_options,
+        input_queue: gen_str(inputs.input_queue),
+        node_settings: inputs.node_settings,
+        behaviour_module: gen_ident(behaviour.module),
+        behaviour_is_type: behaviour.kind == BehaviourKind::Type,
+        rmq_options: inputs.rmq_options,
+        retry_policy: inputs.retry_policy,
     };
 
     let generated = template.render()?;
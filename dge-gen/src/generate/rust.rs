@@ -34,10 +34,28 @@ pub(crate) fn gen_str(s: String) -> String {
     format!(r#""{}""#, s)
 }
 
-pub(crate) fn gen_u32(n: u32) -> String {
+pub(crate) fn gen_u16(n: u16) -> String {
     n.to_string()
 }
 
+pub(crate) fn gen_usize(n: usize) -> String {
+    n.to_string()
+}
+
+pub(crate) fn gen_opt_u32(n: Option<u32>) -> String {
+    match n {
+        None => "None".into(),
+        Some(n) => format!("Some({})", n),
+    }
+}
+
+pub(crate) fn gen_opt_usize(n: Option<usize>) -> String {
+    match n {
+        None => "None".into(),
+        Some(n) => format!("Some({})", n),
+    }
+}
+
 pub(crate) fn gen_ident(s: String) -> String {
     s
 }
@@ -1,20 +1,39 @@
 use petgraph;
+use serde::Deserialize;
+use serde::Serialize;
 use std::path::Path;
 
 pub use petgraph::graph::EdgeIndex;
 pub use petgraph::graph::NodeIndex;
 
+use super::deploy;
+use super::deploy::DeployOptions;
+use super::describe;
+use super::diagram;
+use super::diagram::DiagramBackend;
 use super::generate;
+use super::generate::files::GeneratedFiles;
+use super::generate::graph::Layout;
+use super::migration;
+use super::migration::MigrationPlan;
+use super::scaffold;
+use super::scaffold::Scaffolded;
+use super::spec;
+use super::spec::SpecFormat;
+use super::topology;
+use super::topology::Topology;
 
 pub(crate) type PetGraph = petgraph::Graph<Node, Edge>;
 
+use crate::Error;
 use crate::Result;
 
 /// A node represents the computation.
 ///
 /// Every node has an unique name associated with it,
 /// which will be used as the file name of the generated file.
-#[derive(Clone, Debug)]
+#[derive(Clone, Debug, Serialize, Deserialize)]
+#[serde(tag = "kind", rename_all = "snake_case", deny_unknown_fields)]
 pub(crate) enum Node {
     /// A no-op node that indicates the start of the computation.
     Start {
@@ -29,20 +48,37 @@ pub(crate) enum Node {
     Aggregate {
         name: String,
         behaviour_module: String,
+        #[serde(default, skip_serializing_if = "BehaviourKind::is_module")]
+        behaviour_kind: BehaviourKind,
+        type_output: String,
+        #[serde(default, skip_serializing_if = "NodeOptions::is_default")]
+        node_options: NodeOptions,
     },
     /// Duplicate the output of one node to multiple nodes.
     FanOut {
         name: String,
+        #[serde(default, skip_serializing_if = "NodeOptions::is_default")]
+        node_options: NodeOptions,
     },
     /// A user-provided handler that transform the input message into the output message.
     UserHandler {
         name: String,
         behaviour_module: String,
+        #[serde(default, skip_serializing_if = "BehaviourKind::is_module")]
+        behaviour_kind: BehaviourKind,
+        type_output: String,
+        #[serde(default, skip_serializing_if = "NodeOptions::is_default")]
+        node_options: NodeOptions,
     },
     /// A node that polls the incoming messages.
     Poll {
         name: String,
         behaviour_module: String,
+        #[serde(default, skip_serializing_if = "BehaviourKind::is_module")]
+        behaviour_kind: BehaviourKind,
+        type_output: String,
+        #[serde(default, skip_serializing_if = "NodeOptions::is_default")]
+        node_options: NodeOptions,
     },
 }
 
@@ -57,6 +93,62 @@ impl Node {
             Node::Poll { name, .. } => name.clone(),
         }
     }
+
+    /// The kind of the node, for displaying.
+    pub(crate) fn kind(&self) -> &'static str {
+        match self {
+            Node::Start { .. } => "start",
+            Node::Terminate { .. } => "terminate",
+            Node::Aggregate { .. } => "aggregate",
+            Node::FanOut { .. } => "fan out",
+            Node::UserHandler { .. } => "user handler",
+            Node::Poll { .. } => "poll",
+        }
+    }
+
+    /// The behaviour module of the node, if the node has user-provided behaviour.
+    pub(crate) fn behaviour_module(&self) -> Option<&str> {
+        match self {
+            Node::Aggregate { behaviour_module, .. } => Some(behaviour_module),
+            Node::UserHandler { behaviour_module, .. } => Some(behaviour_module),
+            Node::Poll { behaviour_module, .. } => Some(behaviour_module),
+            Node::Start { .. } | Node::Terminate { .. } | Node::FanOut { .. } => None,
+        }
+    }
+
+    /// How the behaviour of the node is defined, if the node has user-provided behaviour.
+    pub(crate) fn behaviour_kind(&self) -> Option<BehaviourKind> {
+        match self {
+            Node::Aggregate { behaviour_kind, .. } => Some(*behaviour_kind),
+            Node::UserHandler { behaviour_kind, .. } => Some(*behaviour_kind),
+            Node::Poll { behaviour_kind, .. } => Some(*behaviour_kind),
+            Node::Start { .. } | Node::Terminate { .. } | Node::FanOut { .. } => None,
+        }
+    }
+
+    /// The runtime options of the node, if the node is run by a subcommand of the generated code.
+    pub(crate) fn node_options(&self) -> Option<&NodeOptions> {
+        match self {
+            Node::Aggregate { node_options, .. } => Some(node_options),
+            Node::FanOut { node_options, .. } => Some(node_options),
+            Node::UserHandler { node_options, .. } => Some(node_options),
+            Node::Poll { node_options, .. } => Some(node_options),
+            Node::Start { .. } | Node::Terminate { .. } => None,
+        }
+    }
+
+    /// The type of the messages this node outputs, if it is declared by the node itself.
+    ///
+    /// Start nodes don't declare an output type (the outgoing edge does),
+    /// and a fan out node outputs whatever it receives.
+    pub(crate) fn type_output(&self) -> Option<String> {
+        match self {
+            Node::Aggregate { type_output, .. } => Some(type_output.clone()),
+            Node::UserHandler { type_output, .. } => Some(type_output.clone()),
+            Node::Poll { type_output, .. } => Some(type_output.clone()),
+            Node::Start { .. } | Node::Terminate { .. } | Node::FanOut { .. } => None,
+        }
+    }
 }
 
 /// An edge represents a RabbitMQ queue carrying a specific type of message.
@@ -65,8 +157,121 @@ pub(crate) struct Edge {
     pub(crate) queue: String,
     pub(crate) msg_type: String,
     pub(crate) retry_interval_in_seconds: u32,
+    pub(crate) retry_backoff: RetryBackoff,
+    /// after failing this many times, a message is moved to the parking queue of the queue
+    pub(crate) max_attempts: Option<u32>,
+}
+
+/// How the user-provided behaviour of a node is defined,
+/// in both cases the generated code calls it through the trait of the node kind,
+/// i.e. `dge_runtime::Handler`, `dge_runtime::Aggregator` or `dge_runtime::Poller`.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Serialize, Deserialize, Default)]
+#[serde(rename_all = "snake_case")]
+pub enum BehaviourKind {
+    /// The behaviour path names a module defining the items of the trait as free functions
+    /// (and `State` as a type alias), e.g. `init` and `handle`,
+    /// the generated code implements the trait by forwarding to them.
+    #[default]
+    Module,
+    /// The behaviour path names a type implementing the trait.
+    Type,
 }
 
+impl BehaviourKind {
+    pub(crate) fn is_module(&self) -> bool {
+        *self == BehaviourKind::Module
+    }
+}
+
+/// Runtime options of a node, built like `NodeOptions::new().prefetch_count(10).worker_threads(4)`.
+///
+/// These are the defaults of the generated subcommand running the node,
+/// each of them can be overridden when launching the subcommand,
+/// e.g. `example double --prefetch-count 20`.
+#[derive(Clone, Debug, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct NodeOptions {
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub(crate) prefetch_count: Option<u16>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub(crate) worker_threads: Option<usize>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub(crate) max_in_flight: Option<usize>,
+}
+
+impl NodeOptions {
+    /// The default options: a prefetch count of 1,
+    /// 2 worker threads (10 for poll nodes), and no limit on the handlers running at the same time.
+    pub fn new() -> NodeOptions {
+        NodeOptions::default()
+    }
+
+    /// The number of unacknowledged messages RabbitMQ delivers to an instance of the node.
+    pub fn prefetch_count(mut self, prefetch_count: u16) -> NodeOptions {
+        self.prefetch_count = Some(prefetch_count);
+        self
+    }
+
+    /// The number of worker threads of the tokio runtime running the node.
+    pub fn worker_threads(mut self, worker_threads: usize) -> NodeOptions {
+        self.worker_threads = Some(worker_threads);
+        self
+    }
+
+    /// The maximum number of messages an instance of the node handles at the same time.
+    pub fn max_in_flight(mut self, max_in_flight: usize) -> NodeOptions {
+        self.max_in_flight = Some(max_in_flight);
+        self
+    }
+
+    pub(crate) fn is_default(&self) -> bool {
+        *self == NodeOptions::default()
+    }
+}
+
+/// Retries of the messages of a queue after the first one, each waiting longer than the one before,
+/// built like `RetryBackoff::new(vec![10, 60, 600]).jitter_percent(20)`, see `Graph::set_retry_backoff`.
+///
+/// The first retry waits for the `retry_interval_in_seconds` of the queue, the next ones for the tiers
+/// in turn, and the retries after the last tier wait for the last tier.
+/// Each tier has its own retry queue.
+#[derive(Clone, Debug, Default, PartialEq, Eq, PartialOrd, Ord, Hash, Serialize, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct RetryBackoff {
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub(crate) tiers_in_seconds: Vec<u32>,
+    #[serde(default, skip_serializing_if = "is_zero")]
+    pub(crate) jitter_percent: u8,
+}
+
+impl RetryBackoff {
+    /// The delays of the retries after the first one, in seconds.
+    pub fn new(tiers_in_seconds: Vec<u32>) -> RetryBackoff {
+        RetryBackoff {
+            tiers_in_seconds,
+            jitter_percent: 0,
+        }
+    }
+
+    /// Shorten each delay (including the first one) by a random amount up to this percentage of it,
+    /// so that the messages failed together are not retried together.
+    pub fn jitter_percent(mut self, jitter_percent: u8) -> RetryBackoff {
+        self.jitter_percent = jitter_percent;
+        self
+    }
+
+    pub(crate) fn is_default(&self) -> bool {
+        *self == RetryBackoff::default()
+    }
+}
+
+fn is_zero(n: &u8) -> bool {
+    *n == 0
+}
+
+/// The environment variable used by `Graph::save_if_requested`.
+pub const SPEC_OUTPUT_ENV: &str = "DGE_SPEC_OUTPUT";
+
 /// A computational graph
 ///
 /// - edges of the graph represent messages of static types delivered between nodes via RabbitMQ queue
@@ -75,6 +280,27 @@ pub struct Graph {
     pub(crate) g: PetGraph,
     pub(crate) accept_failure: String,
     pub(crate) type_error: String,
+    pub(crate) options: Option<GenerateOptions>,
+}
+
+/// Options controlling the generated code,
+/// see `Graph::generate` for the meaning of each field.
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct GenerateOptions {
+    pub get_rmq_uri: String,
+    pub work_exchange: String,
+    pub retry_exchange: String,
+    #[serde(default)]
+    pub retry_queue_prefix: String,
+    #[serde(default)]
+    pub retry_queue_suffix: String,
+    pub init_input_queue: bool,
+    pub init_output_queue: bool,
+    pub main_init: String,
+    /// how `graph.svg` is rendered
+    #[serde(default)]
+    pub diagram: DiagramBackend,
 }
 
 impl Graph {
@@ -100,9 +326,22 @@ impl Graph {
             g: petgraph::Graph::new(),
             accept_failure: accept_failure.into(),
             type_error: type_error.into(),
+            options: None,
         }
     }
 
+    /// Attach the options used to generate the code to the graph,
+    /// so that they are saved together with the graph (see `Graph::save`),
+    /// and can be used later by `Graph::generate_with_options`.
+    pub fn set_options(&mut self, options: GenerateOptions) {
+        self.options = Some(options);
+    }
+
+    /// The options attached to the graph, if any.
+    pub fn options(&self) -> Option<&GenerateOptions> {
+        self.options.as_ref()
+    }
+
     /// Represent the start of the computation.
     ///
     /// Usually this is the first function been called to acquire a starting point
@@ -115,7 +354,7 @@ impl Graph {
 
     /// Read a message of type `type_input` from node `input` via RabbitMQ queue `queue`,
     /// and process it with this node, which is named `name`,
-    /// using behaviour defined by `behaviour_module`.
+    /// using behaviour defined by `behaviour_module`, producing a message of type `type_output`.
     /// Retry after `retry_interval_in_seconds` if some error happened during the processing,
     /// (transient or non-transient), and dge decides that the processing should be retried.
     ///
@@ -125,6 +364,17 @@ impl Graph {
     /// The arguments can be read as:
     ///
     /// `input -- queue carrying message of type_input --> name`
+    ///
+    /// The `type_output` is checked against the message type of the outgoing edge of this node,
+    /// and against the behaviour module when the generated code is compiled.
+    /// `behaviour_module` can also name a type implementing `dge_runtime::Handler`,
+    /// declared by `set_behaviour_kind` (this applies to `aggregate` and `poll` as well).
+    ///
+    /// The node runs with the default `NodeOptions`, see `set_node_options`.
+    // the arguments are in the order of `input -- queue --> node`, as in `dge_graph!`,
+    // `type_output` comes last, after the retry interval, so a call passing it among the other
+    // strings does not compile, instead of mixing up the types, the name and the behaviour
+    #[allow(clippy::too_many_arguments)]
     pub fn process<S: Into<String>>(
         &mut self,
         input: NodeIndex,
@@ -133,16 +383,22 @@ impl Graph {
         name: S,
         behaviour_module: S,
         retry_interval_in_seconds: u32,
+        type_output: S,
     ) -> NodeIndex {
         let handler_node = Node::UserHandler {
             name: name.into(),
             behaviour_module: behaviour_module.into(),
+            behaviour_kind: BehaviourKind::Module,
+            type_output: type_output.into(),
+            node_options: NodeOptions::default(),
         };
         let handler_node_i = self.g.add_node(handler_node);
         let edge = Edge {
             queue: queue.into(),
             msg_type: type_input.into(),
             retry_interval_in_seconds,
+            retry_backoff: RetryBackoff::default(),
+            max_attempts: None,
         };
         self.g.add_edge(input, handler_node_i, edge);
         handler_node_i
@@ -151,7 +407,9 @@ impl Graph {
     /// Add a node that aggregates messages from `inputs` that belong to a single run,
     /// and aggregate them for later consumption.
     ///
-    /// `behaviour_module` defines how the input messages should be aggregated.
+    /// `behaviour_module` defines how the input messages should be aggregated
+    /// into a message of type `type_output`.
+    #[allow(clippy::too_many_arguments)]
     pub fn aggregate<S: Into<String>>(
         &mut self,
         inputs: Vec<NodeIndex>,
@@ -160,11 +418,15 @@ impl Graph {
         name: S,
         behaviour_module: S,
         retry_interval_in_seconds: u32,
+        type_output: S,
     ) -> NodeIndex {
         let type_input = type_input.into();
         let wait_node_i = self.g.add_node(Node::Aggregate {
             name: name.into(),
             behaviour_module: behaviour_module.into(),
+            behaviour_kind: BehaviourKind::Module,
+            type_output: type_output.into(),
+            node_options: NodeOptions::default(),
         });
         let queue = queue.into();
         for input_i in inputs {
@@ -175,6 +437,8 @@ impl Graph {
                     queue: queue.clone(),
                     msg_type: type_input.clone(),
                     retry_interval_in_seconds,
+                    retry_backoff: RetryBackoff::default(),
+                    max_attempts: None,
                 },
             );
         }
@@ -191,7 +455,10 @@ impl Graph {
         name: S,
         retry_interval_in_seconds: u32,
     ) -> NodeIndex {
-        let fan_out_i = self.g.add_node(Node::FanOut { name: name.into() });
+        let fan_out_i = self.g.add_node(Node::FanOut {
+            name: name.into(),
+            node_options: NodeOptions::default(),
+        });
         self.g.add_edge(
             input,
             fan_out_i,
@@ -199,6 +466,8 @@ impl Graph {
                 queue: queue.into(),
                 msg_type: type_input.into(),
                 retry_interval_in_seconds,
+                retry_backoff: RetryBackoff::default(),
+                max_attempts: None,
             },
         );
 
@@ -212,6 +481,9 @@ impl Graph {
     ///
     /// For example this can be used to query an third-party REST service for the availability
     /// of the resources corresponding the input messages.
+    ///
+    /// Once the polling is done, a message of type `type_output` is sent to the next node.
+    #[allow(clippy::too_many_arguments)]
     pub fn poll<S: Into<String>>(
         &mut self,
         input: NodeIndex,
@@ -220,16 +492,22 @@ impl Graph {
         name: S,
         behaviour_module: S,
         retry_interval_in_seconds: u32,
+        type_output: S,
     ) -> NodeIndex {
         let poll_node = Node::Poll {
             name: name.into(),
             behaviour_module: behaviour_module.into(),
+            behaviour_kind: BehaviourKind::Module,
+            type_output: type_output.into(),
+            node_options: NodeOptions::default(),
         };
         let poll_node_i = self.g.add_node(poll_node);
         let edge = Edge {
             queue: queue.into(),
             msg_type: type_input.into(),
             retry_interval_in_seconds,
+            retry_backoff: RetryBackoff::default(),
+            max_attempts: None,
         };
         self.g.add_edge(input, poll_node_i, edge);
         poll_node_i
@@ -241,10 +519,203 @@ impl Graph {
         self.g.add_edge(input, terminate_node, Edge {
             queue: queue.into(),
             msg_type: type_input.into(),
-            retry_interval_in_seconds
+            retry_interval_in_seconds,
+            retry_backoff: RetryBackoff::default(),
+            max_attempts: None,
         });
     }
 
+    /// Declare how the behaviour of `node` is defined, nodes are added with `BehaviourKind::Module`.
+    ///
+    /// Fails for the nodes without user-provided behaviour, i.e. start, terminate and fan out nodes.
+    pub fn set_behaviour_kind(&mut self, node: NodeIndex, kind: BehaviourKind) -> Result<()> {
+        match self.g.node_weight_mut(node) {
+            Some(Node::Aggregate { behaviour_kind, .. })
+            | Some(Node::UserHandler { behaviour_kind, .. })
+            | Some(Node::Poll { behaviour_kind, .. }) => {
+                *behaviour_kind = kind;
+                Ok(())
+            }
+            Some(other) => Err(Error::IllFormedNode {
+                node: format!("{:?}", other),
+            }),
+            None => Err(Error::UnknownNode(format!("{:?}", node))),
+        }
+    }
+
+    /// Declare how `node` is run, see `NodeOptions`, nodes are added with the default options.
+    ///
+    /// Fails for the nodes not run by a subcommand of the generated code, i.e. start and terminate nodes.
+    pub fn set_node_options(&mut self, node: NodeIndex, options: NodeOptions) -> Result<()> {
+        match self.g.node_weight_mut(node) {
+            Some(Node::Aggregate { node_options, .. })
+            | Some(Node::FanOut { node_options, .. })
+            | Some(Node::UserHandler { node_options, .. })
+            | Some(Node::Poll { node_options, .. }) => {
+                *node_options = options;
+                Ok(())
+            }
+            Some(other) => Err(Error::IllFormedNode {
+                node: format!("{:?}", other),
+            }),
+            None => Err(Error::UnknownNode(format!("{:?}", node))),
+        }
+    }
+
+    /// Retry the messages of `queue` with increasing delays, see `RetryBackoff`.
+    ///
+    /// Fails if no edge of the graph uses `queue`.
+    pub fn set_retry_backoff<S: AsRef<str>>(
+        &mut self,
+        queue: S,
+        retry_backoff: RetryBackoff,
+    ) -> Result<()> {
+        self.update_edges_of(queue.as_ref(), |edge| {
+            edge.retry_backoff = retry_backoff.clone()
+        })
+    }
+
+    /// Move the messages of `queue` failing `max_attempts` times to its parking queue,
+    /// `<queue>_parked`, instead of retrying them forever.
+    /// The parked messages are inspected and replayed with the `parked` subcommand of the
+    /// generated executable.
+    ///
+    /// Fails if no edge of the graph uses `queue`.
+    pub fn set_max_attempts<S: AsRef<str>>(&mut self, queue: S, max_attempts: u32) -> Result<()> {
+        self.update_edges_of(queue.as_ref(), |edge| edge.max_attempts = Some(max_attempts))
+    }
+
+    /// Update all the edges backed by `queue`, failing if there is none.
+    fn update_edges_of<F: FnMut(&mut Edge)>(&mut self, queue: &str, mut update: F) -> Result<()> {
+        let mut found = false;
+        for edge in self.g.edge_weights_mut() {
+            if edge.queue == queue {
+                update(edge);
+                found = true;
+            }
+        }
+        if found {
+            Ok(())
+        } else {
+            Err(Error::UnknownQueue(String::from(queue)))
+        }
+    }
+
+    /// Load a graph from a spec file,
+    /// the format of the file is decided by its extension (`.json`, `.yaml`/`.yml` or `.toml`).
+    ///
+    /// A spec file lists the nodes and the edges (which refer to nodes by name) of the graph,
+    /// together with the optional `GenerateOptions`.
+    /// The easiest way to get started is to `save` a graph built in Rust.
+    pub fn load<P: AsRef<Path>>(path: P) -> Result<Graph> {
+        let path = path.as_ref();
+        let format = SpecFormat::from_path(path)?;
+        let content = std::fs::read_to_string(path)?;
+        Graph::from_spec_str(&content, format)
+    }
+
+    /// Save the graph to a spec file, which can be loaded back with `Graph::load`.
+    pub fn save<P: AsRef<Path>>(&self, path: P) -> Result<()> {
+        let path = path.as_ref();
+        let format = SpecFormat::from_path(path)?;
+        std::fs::write(path, self.to_spec_string(format)?)?;
+        Ok(())
+    }
+
+    /// Parse a graph from the content of a spec file.
+    pub fn from_spec_str(s: &str, format: SpecFormat) -> Result<Graph> {
+        spec::from_str(s, format)
+    }
+
+    /// Serialize the graph to the content of a spec file.
+    pub fn to_spec_string(&self, format: SpecFormat) -> Result<String> {
+        spec::to_string(self, format)
+    }
+
+    /// Save the graph to the spec file named by the environment variable `DGE_SPEC_OUTPUT`,
+    /// return whether the graph is saved.
+    ///
+    /// This is how the `dge` command line tool reads a graph defined by a Rust program:
+    /// the program calls this first, and skips its own generation when it returns `true`.
+    pub fn save_if_requested(&self) -> Result<bool> {
+        match std::env::var_os(SPEC_OUTPUT_ENV) {
+            None => Ok(false),
+            Some(path) => {
+                self.save(path)?;
+                Ok(true)
+            }
+        }
+    }
+
+    /// A human readable summary of the nodes and queues in the graph.
+    pub fn describe(&self) -> String {
+        describe::describe(self)
+    }
+
+    /// Render the graph in Graphviz's dot format.
+    pub fn to_dot(&self) -> String {
+        diagram::graphviz::render_dot(&self.g)
+    }
+
+    /// Render the graph as svg,
+    /// `DiagramBackend::Graphviz` requires the `dot` command of Graphviz.
+    pub fn to_svg(&self, backend: DiagramBackend) -> Result<String> {
+        diagram::render_svg(self, backend)
+    }
+
+    /// Render the graph as a Mermaid flowchart, e.g. for embedding in Markdown.
+    pub fn to_mermaid(&self) -> String {
+        diagram::mermaid::render(self)
+    }
+
+    /// Render the graph as a PlantUML diagram.
+    pub fn to_plantuml(&self) -> String {
+        diagram::plantuml::render(self)
+    }
+
+    /// Describe the graph, together with the RabbitMQ entities created for it according to `options`,
+    /// as a document for other tools, see `dge_gen::topology`.
+    pub fn topology(&self, options: &GenerateOptions) -> Topology {
+        topology::topology(self, options)
+    }
+
+    /// The steps migrating the queues and exchanges of a previous version of the graph,
+    /// described by its topology, to the ones of this graph, see `dge_gen::migration`.
+    pub fn migration_plan(
+        &self,
+        options: &GenerateOptions,
+        previous: &Topology,
+    ) -> Result<MigrationPlan> {
+        migration::plan(previous, &self.topology(options))
+    }
+
+    /// Render the graph as a standalone html page,
+    /// which shows the details of the nodes and the queues when hovering over them.
+    pub fn to_html(&self) -> String {
+        diagram::html::render(self)
+    }
+
+    /// Check the validity of the graph.
+    ///
+    /// All the problems found are reported at once via `Error::InvalidGraph`.
+    /// Message types are compared by their paths as written,
+    /// so the same type should always be spelled the same way.
+    /// The names of the retry and parking queues are checked with the options loaded
+    /// together with the graph, if any.
+    /// This is also run by `generate` before any code is emitted, with the options it is given.
+    pub fn validate(&self) -> Result<()> {
+        self.validate_with_options(self.options.as_ref())
+    }
+
+    /// Same as `validate`, checking the names of the retry and parking queues with `options`.
+    pub(crate) fn validate_with_options(&self, options: Option<&GenerateOptions>) -> Result<()> {
+        let errors = super::validate::validate(self, options);
+        if errors.is_empty() {
+            Ok(())
+        } else {
+            Err(Error::InvalidGraph { errors })
+        }
+    }
 
     /// Generate code represented by the graph.
     ///
@@ -271,13 +742,69 @@ impl Graph {
         init_output_queue: bool,
         main_init: S,
     ) -> Result<()> {
-        let rmq_options = generate::graph::RmqOptions {
+        let options = GenerateOptions {
             get_rmq_uri: get_rmq_uri.as_ref().into(),
             work_exchange: work_exchange.as_ref().into(),
             retry_exchange: retry_exchange.as_ref().into(),
             retry_queue_prefix: retry_queue_prefix.as_ref().into(),
             retry_queue_suffix: retry_queue_suffix.as_ref().into(),
+            init_input_queue,
+            init_output_queue,
+            main_init: main_init.as_ref().into(),
+            diagram: DiagramBackend::default(),
         };
-        generate::graph::generate(self, output_dir, rmq_options, init_input_queue, init_output_queue, main_init)
+        self.generate_with_options(output_dir, &options)
+    }
+
+    /// Same as `generate`, but with the options bundled in a `GenerateOptions`.
+    ///
+    /// Use `graph.options()` to generate with the options loaded together with the graph.
+    /// The files generated by a previous run but not anymore, e.g. the module of a removed node,
+    /// are deleted, see `GeneratedFiles::write_to`.
+    pub fn generate_with_options<P: AsRef<Path>>(
+        self,
+        output_dir: P,
+        options: &GenerateOptions,
+    ) -> Result<()> {
+        generate::graph::generate(&self, output_dir, options)
+    }
+
+    /// Write a skeleton of each behaviour module which does not exist yet,
+    /// with the functions and types the generated code expects from it.
+    ///
+    /// `src_dir` is the source directory of the crate defining the behaviour modules,
+    /// e.g. the module `my_crate::behaviour::double` is written to `src_dir/behaviour/double.rs`,
+    /// and a behaviour type `my_crate::behaviour::square::Square` to `src_dir/behaviour/square.rs`,
+    /// together with the other behaviour types of the same module.
+    /// The paths in the stubs starting with the crate name are replaced by `crate::`.
+    /// Existing files are never overwritten, the behaviour types they do not mention are returned
+    /// instead, and so are the `mod` declarations the new modules need
+    /// (e.g. `pub mod double;` in `src_dir/behaviour/mod.rs`).
+    pub fn scaffold<P: AsRef<Path>>(&self, src_dir: P) -> Result<Scaffolded> {
+        scaffold::scaffold(self, src_dir.as_ref())
+    }
+
+    /// Generate the same files as `generate_with_options`, in memory.
+    ///
+    /// Use `GeneratedFiles::write_to` to write them,
+    /// or `GeneratedFiles::check` to find out whether the files on disk are up to date.
+    pub fn render(&self, options: &GenerateOptions) -> Result<GeneratedFiles> {
+        generate::graph::render(self, options, &Layout::SourceTree, true)
+    }
+
+    /// Generate the deployment manifests of the executable generated for the graph, in memory.
+    ///
+    /// For each subcommand running a node, these are:
+    ///
+    /// - a service in `docker-compose.yml`,
+    ///   which also starts RabbitMQ and runs `init-exchanges-and-queues` before the nodes
+    /// - a systemd template unit `<binary>-<subcommand>@.service`, one instance per replica,
+    ///   requiring the oneshot unit `<binary>-init-exchanges-and-queues.service`
+    /// - a Kubernetes Deployment `kubernetes-<subcommand>.yaml`,
+    ///   and a Job `kubernetes-init-exchanges-and-queues.yaml`
+    ///
+    /// The replicas and the resource limits of each node are given by `options`.
+    pub fn deploy(&self, options: &DeployOptions) -> Result<GeneratedFiles> {
+        deploy::deploy(self, options)
     }
 }
@@ -1,9 +1,32 @@
+pub mod build;
+mod deploy;
+mod describe;
+mod diagram;
 mod error;
 mod generate;
 mod graph;
+pub mod migration;
 mod misc;
+mod scaffold;
+mod spec;
+pub mod topology;
+mod validate;
 
+pub use deploy::DeployOptions;
+pub use deploy::NodeDeployment;
+pub use dge_macro::dge_graph;
+pub use diagram::DiagramBackend;
 pub use error::Error;
 pub use error::Result;
+pub use error::ValidationError;
+pub use generate::files::GeneratedFiles;
+pub use generate::files::MANIFEST_FILE_NAME;
+pub use graph::BehaviourKind;
+pub use graph::GenerateOptions;
 pub use graph::Graph;
 pub use graph::NodeIndex;
+pub use graph::NodeOptions;
+pub use graph::RetryBackoff;
+pub use graph::SPEC_OUTPUT_ENV;
+pub use scaffold::Scaffolded;
+pub use spec::SpecFormat;
@@ -0,0 +1,766 @@
+//! Migrating the RabbitMQ queues and exchanges of one version of the graph to the ones of another.
+//!
+//! RabbitMQ refuses to declare a queue again with different arguments,
+//! e.g. a retry queue with a different `x-message-ttl` after `retry_interval_in_seconds` changes,
+//! and the queues no longer used are left behind, together with the messages in them.
+//! The plan lists the steps taking the queues of the previous version
+//! (described by its `dge_gen::topology::Topology`) to the ones of the current version,
+//! it is executed by the `migrate` subcommand of the generated executable.
+//!
+//! Only the queues declared by `init-exchanges-and-queues` are migrated.
+//! The document is versioned by `MigrationPlan::format_version`.
+
+use serde::Deserialize;
+use serde::Serialize;
+use std::collections::BTreeMap;
+use std::fmt::Write;
+
+use crate::topology;
+use crate::topology::Exchanges;
+use crate::topology::Topology;
+use crate::topology::TopologyQueue;
+use crate::Error;
+use crate::Result;
+
+/// The current version of the migration plan document.
+pub const FORMAT_VERSION: u32 = 1;
+
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub struct MigrationPlan {
+    pub format_version: u32,
+    /// the exchanges of the current version
+    pub exchanges: Exchanges,
+    /// in the order they are executed
+    pub steps: Vec<MigrationStep>,
+}
+
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(tag = "action", rename_all = "snake_case")]
+pub enum MigrationStep {
+    /// Declare a new work queue, together with its retry queue,
+    /// the retry queues of its backoff tiers and its parking queue.
+    Declare {
+        queue: String,
+        retry_queue: String,
+        retry_interval_in_seconds: u32,
+        #[serde(default, skip_serializing_if = "Vec::is_empty")]
+        retry_tiers: Vec<String>,
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        parking_queue: Option<String>,
+    },
+    /// The queue is renamed, i.e. it is consumed by the same node and carries the same message type:
+    /// move the messages in the old queue and its retry queues to the new queue,
+    /// and the parked ones to the new parking queue,
+    /// which are declared by an earlier step, then delete the old queues.
+    /// Without a new parking queue, the step fails if messages are parked in the old one,
+    /// they are to be replayed or purged by the previous version first.
+    Move {
+        from: String,
+        from_retry_queue: String,
+        #[serde(default, skip_serializing_if = "Vec::is_empty")]
+        from_retry_tiers: Vec<String>,
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        from_parking_queue: Option<String>,
+        to: String,
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        to_parking_queue: Option<String>,
+    },
+    /// The arguments of the queue or of its retry queues change:
+    /// move the messages to a temporary queue, delete the queues and declare them again,
+    /// then move the messages back to the work queue.
+    Recreate {
+        queue: String,
+        old_retry_queue: String,
+        #[serde(default, skip_serializing_if = "Vec::is_empty")]
+        old_retry_tiers: Vec<String>,
+        retry_queue: String,
+        retry_interval_in_seconds: u32,
+        #[serde(default, skip_serializing_if = "Vec::is_empty")]
+        retry_tiers: Vec<String>,
+        /// why the queue is recreated, for humans
+        reason: String,
+    },
+    /// Backoff tiers are added to the queue: declare their retry queues.
+    DeclareRetryTiers {
+        queue: String,
+        retry_tiers: Vec<String>,
+    },
+    /// Backoff tiers are removed from the queue:
+    /// move the messages waiting in their retry queues back to the queue, then delete them.
+    DeleteRetryTiers {
+        queue: String,
+        retry_tiers: Vec<String>,
+    },
+    /// The queue is given a maximum number of attempts: declare its parking queue.
+    DeclareParkingQueue {
+        queue: String,
+        parking_queue: String,
+    },
+    /// The queue no longer has a maximum number of attempts: delete its parking queue,
+    /// the step fails if messages are parked in it.
+    DeleteParkingQueue {
+        queue: String,
+        parking_queue: String,
+    },
+    /// The queue is no longer used:
+    /// wait until its consumer (of the previous version) empties it and its retry queues,
+    /// then delete them all and its parking queue, the step fails if messages are parked in it.
+    DrainAndDelete {
+        queue: String,
+        retry_queue: String,
+        #[serde(default, skip_serializing_if = "Vec::is_empty")]
+        retry_tiers: Vec<String>,
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        parking_queue: Option<String>,
+    },
+    /// The exchange is no longer used.
+    DeleteExchange { exchange: String },
+}
+
+impl MigrationPlan {
+    pub fn is_empty(&self) -> bool {
+        self.steps.is_empty()
+    }
+
+    /// A human readable summary of the steps.
+    pub fn describe(&self) -> String {
+        let mut out = String::new();
+        if self.steps.is_empty() {
+            let _ = writeln!(out, "nothing to migrate");
+        }
+        for (i, step) in self.steps.iter().enumerate() {
+            let _ = write!(out, "{}. ", i + 1);
+            let _ = match step {
+                MigrationStep::Declare {
+                    queue,
+                    retry_queue,
+                    retry_interval_in_seconds,
+                    retry_tiers,
+                    parking_queue,
+                } => {
+                    let mut details = vec![
+                        format!("retry queue {}", retry_queue),
+                        format!("retried every {}s", retry_interval_in_seconds),
+                    ];
+                    if !retry_tiers.is_empty() {
+                        details.push(format!("later via {}", list(retry_tiers.iter())));
+                    }
+                    if let Some(parking_queue) = parking_queue {
+                        details.push(format!("parked in {}", parking_queue));
+                    }
+                    writeln!(out, "declare queue {} ({})", queue, details.join(", "))
+                }
+                MigrationStep::Move {
+                    from,
+                    from_retry_queue,
+                    from_retry_tiers,
+                    from_parking_queue,
+                    to,
+                    to_parking_queue,
+                } => {
+                    let from_queues = list(
+                        [from, from_retry_queue]
+                            .iter()
+                            .copied()
+                            .chain(from_retry_tiers),
+                    );
+                    match (from_parking_queue, to_parking_queue) {
+                        (Some(from_parking_queue), Some(to_parking_queue)) => writeln!(
+                            out,
+                            "move the messages in {} to {}, and the ones parked in {} to {}, \
+                             then delete them",
+                            from_queues, to, from_parking_queue, to_parking_queue
+                        ),
+                        (Some(from_parking_queue), None) => writeln!(
+                            out,
+                            "move the messages in {} to {}, then delete them and {}, \
+                             failing if messages are parked in it",
+                            from_queues, to, from_parking_queue
+                        ),
+                        _ => writeln!(
+                            out,
+                            "move the messages in {} to {}, then delete them",
+                            from_queues, to
+                        ),
+                    }
+                }
+                MigrationStep::Recreate { queue, reason, .. } => {
+                    writeln!(out, "recreate queue {}, keeping its messages: {}", queue, reason)
+                }
+                MigrationStep::DeclareRetryTiers { queue, retry_tiers } => writeln!(
+                    out,
+                    "declare {} for the backoff tiers added to {}",
+                    list(retry_tiers.iter()),
+                    queue
+                ),
+                MigrationStep::DeleteRetryTiers { queue, retry_tiers } => writeln!(
+                    out,
+                    "move the messages in {} of the backoff tiers removed from {} back to it, \
+                     then delete them",
+                    list(retry_tiers.iter()),
+                    queue
+                ),
+                MigrationStep::DeclareParkingQueue {
+                    queue,
+                    parking_queue,
+                } => writeln!(out, "declare parking queue {} of {}", parking_queue, queue),
+                MigrationStep::DeleteParkingQueue {
+                    queue,
+                    parking_queue,
+                } => writeln!(
+                    out,
+                    "delete parking queue {} of {}, failing if messages are parked in it",
+                    parking_queue, queue
+                ),
+                MigrationStep::DrainAndDelete {
+                    queue,
+                    retry_queue,
+                    retry_tiers,
+                    parking_queue,
+                } => {
+                    let queues = list([queue, retry_queue].iter().copied().chain(retry_tiers));
+                    match parking_queue {
+                        Some(parking_queue) => writeln!(
+                            out,
+                            "wait until {} are empty, then delete them and {}, \
+                             failing if messages are parked in it",
+                            queues, parking_queue
+                        ),
+                        None => {
+                            writeln!(out, "wait until {} are empty, then delete them", queues)
+                        }
+                    }
+                }
+                MigrationStep::DeleteExchange { exchange } => {
+                    writeln!(out, "delete exchange {}", exchange)
+                }
+            };
+        }
+        out
+    }
+}
+
+/// `a`, `a and b`, `a, b and c` etc.
+fn list<'a, I: Iterator<Item = &'a String>>(names: I) -> String {
+    let mut names: Vec<&str> = names.map(String::as_str).collect();
+    match names.pop() {
+        None => String::new(),
+        Some(last) if names.is_empty() => String::from(last),
+        Some(last) => format!("{} and {}", names.join(", "), last),
+    }
+}
+
+/// The names of the retry queues of the backoff tiers of `queue`.
+fn retry_tiers(queue: &TopologyQueue) -> Vec<String> {
+    queue
+        .retry_tiers
+        .iter()
+        .map(|tier| tier.retry_queue.clone())
+        .collect()
+}
+
+pub(crate) fn plan(previous: &Topology, current: &Topology) -> Result<MigrationPlan> {
+    if previous.format_version > topology::FORMAT_VERSION {
+        return Err(Error::UnsupportedFormatVersion {
+            format_version: previous.format_version,
+            supported: topology::FORMAT_VERSION,
+        });
+    }
+
+    let declared = |topology: &Topology| -> BTreeMap<String, TopologyQueue> {
+        topology
+            .queues
+            .iter()
+            .filter(|queue| queue.declared_by_init)
+            .map(|queue| (queue.name.clone(), queue.clone()))
+            .collect()
+    };
+    let old_queues = declared(previous);
+    let new_queues = declared(current);
+    // a queue still in the graph, but no longer declared by dge, is left alone
+    let in_current = |name: &str| current.queues.iter().any(|queue| queue.name == name);
+
+    let added: Vec<&TopologyQueue> = new_queues
+        .values()
+        .filter(|queue| !old_queues.contains_key(&queue.name))
+        .collect();
+    let removed: Vec<&TopologyQueue> = old_queues
+        .values()
+        .filter(|queue| !in_current(&queue.name))
+        .collect();
+
+    // a removed queue and an added queue are the same queue renamed,
+    // if they are the only ones consumed by the same node with the same message type
+    let same = |a: &TopologyQueue, b: &TopologyQueue| {
+        a.consumer == b.consumer && a.message_type == b.message_type
+    };
+    let renamed: BTreeMap<&str, &TopologyQueue> = removed
+        .iter()
+        .filter_map(|old| {
+            let mut candidates = added.iter().filter(|new| same(old, new));
+            match (candidates.next(), candidates.next()) {
+                (Some(new), None) if removed.iter().filter(|other| same(other, new)).count() == 1 => {
+                    Some((old.name.as_str(), *new))
+                }
+                _ => None,
+            }
+        })
+        .collect();
+
+    let mut steps = Vec::new();
+
+    for queue in added.iter() {
+        steps.push(MigrationStep::Declare {
+            queue: queue.name.clone(),
+            retry_queue: queue.retry_queue.clone(),
+            retry_interval_in_seconds: queue.retry_interval_in_seconds,
+            retry_tiers: retry_tiers(queue),
+            parking_queue: queue.parking_queue.clone(),
+        });
+    }
+
+    for old in removed.iter() {
+        if let Some(new) = renamed.get(old.name.as_str()) {
+            steps.push(MigrationStep::Move {
+                from: old.name.clone(),
+                from_retry_queue: old.retry_queue.clone(),
+                from_retry_tiers: retry_tiers(old),
+                from_parking_queue: old.parking_queue.clone(),
+                to: new.name.clone(),
+                to_parking_queue: new.parking_queue.clone(),
+            });
+        }
+    }
+
+    for (name, new) in new_queues.iter() {
+        let old = match old_queues.get(name) {
+            Some(old) => old,
+            None => continue,
+        };
+        let mut reasons = Vec::new();
+        if previous.exchanges != current.exchanges {
+            reasons.push(String::from("the exchanges change"));
+        }
+        if old.retry_queue != new.retry_queue {
+            reasons.push(format!(
+                "the retry queue changes from {} to {}",
+                old.retry_queue, new.retry_queue
+            ));
+        }
+        if old.retry_interval_in_seconds != new.retry_interval_in_seconds {
+            reasons.push(format!(
+                "the retry interval changes from {}s to {}s",
+                old.retry_interval_in_seconds, new.retry_interval_in_seconds
+            ));
+        }
+        let old_retry_tiers = retry_tiers(old);
+        let new_retry_tiers = retry_tiers(new);
+        if !reasons.is_empty() {
+            // the retry queues of the tiers dead letter to the work exchange,
+            // and are named after the retry queue, so they are recreated with it
+            steps.push(MigrationStep::Recreate {
+                queue: name.clone(),
+                old_retry_queue: old.retry_queue.clone(),
+                old_retry_tiers,
+                retry_queue: new.retry_queue.clone(),
+                retry_interval_in_seconds: new.retry_interval_in_seconds,
+                retry_tiers: new_retry_tiers,
+                reason: reasons.join(", "),
+            });
+        } else {
+            // only the number of tiers can change, the delays are set on the messages
+            let added_tiers: Vec<_> = new_retry_tiers
+                .iter()
+                .filter(|tier| !old_retry_tiers.contains(tier))
+                .cloned()
+                .collect();
+            if !added_tiers.is_empty() {
+                steps.push(MigrationStep::DeclareRetryTiers {
+                    queue: name.clone(),
+                    retry_tiers: added_tiers,
+                });
+            }
+            let removed_tiers: Vec<_> = old_retry_tiers
+                .iter()
+                .filter(|tier| !new_retry_tiers.contains(tier))
+                .cloned()
+                .collect();
+            if !removed_tiers.is_empty() {
+                steps.push(MigrationStep::DeleteRetryTiers {
+                    queue: name.clone(),
+                    retry_tiers: removed_tiers,
+                });
+            }
+        }
+
+        // the parking queue has no arguments, and is named after the work queue only
+        match (&old.parking_queue, &new.parking_queue) {
+            (None, Some(parking_queue)) => steps.push(MigrationStep::DeclareParkingQueue {
+                queue: name.clone(),
+                parking_queue: parking_queue.clone(),
+            }),
+            (Some(parking_queue), None) => steps.push(MigrationStep::DeleteParkingQueue {
+                queue: name.clone(),
+                parking_queue: parking_queue.clone(),
+            }),
+            _ => (),
+        }
+    }
+
+    for old in removed.iter() {
+        if !renamed.contains_key(old.name.as_str()) {
+            steps.push(MigrationStep::DrainAndDelete {
+                queue: old.name.clone(),
+                retry_queue: old.retry_queue.clone(),
+                retry_tiers: retry_tiers(old),
+                parking_queue: old.parking_queue.clone(),
+            });
+        }
+    }
+
+    let new_exchanges = [&current.exchanges.work, &current.exchanges.retry];
+    let mut old_exchanges = vec![&previous.exchanges.work, &previous.exchanges.retry];
+    old_exchanges.dedup();
+    for exchange in old_exchanges {
+        if !new_exchanges.contains(&exchange) {
+            steps.push(MigrationStep::DeleteExchange {
+                exchange: exchange.clone(),
+            });
+        }
+    }
+
+    Ok(MigrationPlan {
+        format_version: FORMAT_VERSION,
+        exchanges: current.exchanges.clone(),
+        steps,
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::topology::TopologyRetryTier;
+
+    fn queue(name: &str, consumer: &str, message_type: &str) -> TopologyQueue {
+        TopologyQueue {
+            name: String::from(name),
+            retry_queue: format!("retry_{}", name),
+            retry_interval_in_seconds: 10,
+            retry_tiers: vec![],
+            retry_jitter_percent: 0,
+            max_attempts: None,
+            parking_queue: None,
+            message_type: String::from(message_type),
+            producers: vec![String::from("start")],
+            consumer: String::from(consumer),
+            declared_by_init: true,
+        }
+    }
+
+    fn with_retry_tiers(mut queue: TopologyQueue, tiers: u32) -> TopologyQueue {
+        queue.retry_tiers = (1..=tiers)
+            .map(|tier| TopologyRetryTier {
+                retry_queue: format!("{}_{}", queue.retry_queue, tier),
+                delay_in_seconds: 10 * 2u32.pow(tier),
+            })
+            .collect();
+        queue
+    }
+
+    fn with_max_attempts(mut queue: TopologyQueue) -> TopologyQueue {
+        queue.max_attempts = Some(5);
+        queue.parking_queue = Some(format!("{}_parked", queue.name));
+        queue
+    }
+
+    fn topology(queues: Vec<TopologyQueue>) -> Topology {
+        Topology {
+            format_version: topology::FORMAT_VERSION,
+            accept_failure: String::from("app::accept_failure"),
+            type_error: String::from("app::Error"),
+            exchanges: Exchanges {
+                work: String::from("work"),
+                retry: String::from("retry"),
+            },
+            nodes: vec![],
+            queues,
+        }
+    }
+
+    fn steps(previous: Vec<TopologyQueue>, current: Vec<TopologyQueue>) -> Vec<MigrationStep> {
+        plan(&topology(previous), &topology(current)).unwrap().steps
+    }
+
+    fn strings(names: &[&str]) -> Vec<String> {
+        names.iter().map(|&name| String::from(name)).collect()
+    }
+
+    #[test]
+    fn nothing_to_do_for_the_same_topology() {
+        let queues = vec![
+            queue("input", "step", "app::Integer"),
+            with_max_attempts(with_retry_tiers(
+                queue("output", "terminate", "app::Integer"),
+                2,
+            )),
+        ];
+        assert!(plan(&topology(queues.clone()), &topology(queues))
+            .unwrap()
+            .is_empty());
+    }
+
+    #[test]
+    fn renamed_queue_is_moved_with_its_retry_tiers_and_parking_queue() {
+        assert_eq!(
+            steps(
+                vec![with_max_attempts(with_retry_tiers(
+                    queue("input", "step", "app::Integer"),
+                    2
+                ))],
+                vec![with_max_attempts(with_retry_tiers(
+                    queue("input_v2", "step", "app::Integer"),
+                    1
+                ))],
+            ),
+            vec![
+                MigrationStep::Declare {
+                    queue: String::from("input_v2"),
+                    retry_queue: String::from("retry_input_v2"),
+                    retry_interval_in_seconds: 10,
+                    retry_tiers: strings(&["retry_input_v2_1"]),
+                    parking_queue: Some(String::from("input_v2_parked")),
+                },
+                MigrationStep::Move {
+                    from: String::from("input"),
+                    from_retry_queue: String::from("retry_input"),
+                    from_retry_tiers: strings(&["retry_input_1", "retry_input_2"]),
+                    from_parking_queue: Some(String::from("input_parked")),
+                    to: String::from("input_v2"),
+                    to_parking_queue: Some(String::from("input_v2_parked")),
+                },
+            ]
+        );
+    }
+
+    #[test]
+    fn renamed_queue_losing_its_parking_queue_moves_nothing_parked() {
+        let moved = steps(
+            vec![with_max_attempts(queue("input", "step", "app::Integer"))],
+            vec![queue("input_v2", "step", "app::Integer")],
+        );
+        assert_eq!(
+            moved[1],
+            MigrationStep::Move {
+                from: String::from("input"),
+                from_retry_queue: String::from("retry_input"),
+                from_retry_tiers: vec![],
+                from_parking_queue: Some(String::from("input_parked")),
+                to: String::from("input_v2"),
+                to_parking_queue: None,
+            }
+        );
+    }
+
+    #[test]
+    fn queues_sharing_a_consumer_and_a_type_are_not_renamed() {
+        // which removed queue became which added queue cannot be told apart
+        assert_eq!(
+            steps(
+                vec![
+                    queue("left", "step", "app::Integer"),
+                    queue("right", "step", "app::Integer"),
+                ],
+                vec![
+                    queue("left_v2", "step", "app::Integer"),
+                    queue("right_v2", "step", "app::Integer"),
+                ],
+            ),
+            vec![
+                MigrationStep::Declare {
+                    queue: String::from("left_v2"),
+                    retry_queue: String::from("retry_left_v2"),
+                    retry_interval_in_seconds: 10,
+                    retry_tiers: vec![],
+                    parking_queue: None,
+                },
+                MigrationStep::Declare {
+                    queue: String::from("right_v2"),
+                    retry_queue: String::from("retry_right_v2"),
+                    retry_interval_in_seconds: 10,
+                    retry_tiers: vec![],
+                    parking_queue: None,
+                },
+                MigrationStep::DrainAndDelete {
+                    queue: String::from("left"),
+                    retry_queue: String::from("retry_left"),
+                    retry_tiers: vec![],
+                    parking_queue: None,
+                },
+                MigrationStep::DrainAndDelete {
+                    queue: String::from("right"),
+                    retry_queue: String::from("retry_right"),
+                    retry_tiers: vec![],
+                    parking_queue: None,
+                },
+            ]
+        );
+    }
+
+    #[test]
+    fn queue_with_two_candidates_is_not_renamed() {
+        let planned = steps(
+            vec![queue("input", "step", "app::Integer")],
+            vec![
+                queue("left", "step", "app::Integer"),
+                queue("right", "step", "app::Integer"),
+            ],
+        );
+        assert!(planned
+            .iter()
+            .all(|step| !matches!(step, MigrationStep::Move { .. })));
+        assert!(planned.contains(&MigrationStep::DrainAndDelete {
+            queue: String::from("input"),
+            retry_queue: String::from("retry_input"),
+            retry_tiers: vec![],
+            parking_queue: None,
+        }));
+    }
+
+    #[test]
+    fn queue_of_another_consumer_or_type_is_not_renamed() {
+        for added in [
+            queue("input_v2", "other_step", "app::Integer"),
+            queue("input_v2", "step", "app::Float"),
+        ]
+        .iter()
+        {
+            let planned = steps(
+                vec![queue("input", "step", "app::Integer")],
+                vec![added.clone()],
+            );
+            assert!(matches!(planned[1], MigrationStep::DrainAndDelete { .. }));
+        }
+    }
+
+    #[test]
+    fn changed_retry_interval_recreates_the_queue_and_its_retry_tiers() {
+        let mut new = with_retry_tiers(queue("input", "step", "app::Integer"), 1);
+        new.retry_interval_in_seconds = 20;
+        assert_eq!(
+            steps(
+                vec![with_retry_tiers(queue("input", "step", "app::Integer"), 2)],
+                vec![new]
+            ),
+            vec![MigrationStep::Recreate {
+                queue: String::from("input"),
+                old_retry_queue: String::from("retry_input"),
+                old_retry_tiers: strings(&["retry_input_1", "retry_input_2"]),
+                retry_queue: String::from("retry_input"),
+                retry_interval_in_seconds: 20,
+                retry_tiers: strings(&["retry_input_1"]),
+                reason: String::from("the retry interval changes from 10s to 20s"),
+            }]
+        );
+    }
+
+    #[test]
+    fn retry_tiers_added_or_removed_are_declared_or_deleted() {
+        let input = queue("input", "step", "app::Integer");
+        assert_eq!(
+            steps(
+                vec![input.clone()],
+                vec![with_retry_tiers(input.clone(), 2)]
+            ),
+            vec![MigrationStep::DeclareRetryTiers {
+                queue: String::from("input"),
+                retry_tiers: strings(&["retry_input_1", "retry_input_2"]),
+            }]
+        );
+        assert_eq!(
+            steps(
+                vec![with_retry_tiers(input.clone(), 3)],
+                vec![with_retry_tiers(input, 1)]
+            ),
+            vec![MigrationStep::DeleteRetryTiers {
+                queue: String::from("input"),
+                retry_tiers: strings(&["retry_input_2", "retry_input_3"]),
+            }]
+        );
+    }
+
+    #[test]
+    fn parking_queue_added_or_removed_is_declared_or_deleted() {
+        let input = queue("input", "step", "app::Integer");
+        let parking_queue = String::from("input_parked");
+        assert_eq!(
+            steps(vec![input.clone()], vec![with_max_attempts(input.clone())]),
+            vec![MigrationStep::DeclareParkingQueue {
+                queue: String::from("input"),
+                parking_queue: parking_queue.clone(),
+            }]
+        );
+        assert_eq!(
+            steps(vec![with_max_attempts(input.clone())], vec![input]),
+            vec![MigrationStep::DeleteParkingQueue {
+                queue: String::from("input"),
+                parking_queue,
+            }]
+        );
+    }
+
+    #[test]
+    fn removed_queue_is_drained_and_deleted_with_its_retry_tiers_and_parking_queue() {
+        assert_eq!(
+            steps(
+                vec![
+                    queue("input", "step", "app::Integer"),
+                    with_max_attempts(with_retry_tiers(queue("extra", "other", "app::Float"), 1)),
+                ],
+                vec![queue("input", "step", "app::Integer")],
+            ),
+            vec![MigrationStep::DrainAndDelete {
+                queue: String::from("extra"),
+                retry_queue: String::from("retry_extra"),
+                retry_tiers: strings(&["retry_extra_1"]),
+                parking_queue: Some(String::from("extra_parked")),
+            }]
+        );
+    }
+
+    #[test]
+    fn queue_no_longer_declared_by_init_is_left_alone() {
+        let mut input = queue("input", "step", "app::Integer");
+        let previous = vec![input.clone()];
+        input.declared_by_init = false;
+        assert_eq!(steps(previous, vec![input]), vec![]);
+    }
+
+    #[test]
+    fn changed_exchanges_recreate_every_queue_and_delete_the_old_exchanges() {
+        let previous = topology(vec![queue("input", "step", "app::Integer")]);
+        let mut current = previous.clone();
+        current.exchanges.work = String::from("work_v2");
+        let planned = plan(&previous, &current).unwrap().steps;
+        assert_eq!(planned.len(), 2);
+        assert!(matches!(
+            &planned[0],
+            MigrationStep::Recreate { reason, .. } if reason == "the exchanges change"
+        ));
+        assert_eq!(
+            planned[1],
+            MigrationStep::DeleteExchange {
+                exchange: String::from("work")
+            }
+        );
+    }
+
+    #[test]
+    fn newer_topology_is_refused() {
+        let mut previous = topology(vec![]);
+        previous.format_version = topology::FORMAT_VERSION + 1;
+        assert!(matches!(
+            plan(&previous, &topology(vec![])),
+            Err(Error::UnsupportedFormatVersion { .. })
+        ));
+    }
+}
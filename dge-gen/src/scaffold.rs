@@ -0,0 +1,445 @@
+use askama::Template;
+use log::info;
+use petgraph::Direction;
+use std::collections::BTreeMap;
+use std::collections::BTreeSet;
+use std::collections::HashSet;
+use std::path::Path;
+use std::path::PathBuf;
+
+use crate::graph::BehaviourKind;
+use crate::graph::Graph;
+use crate::graph::Node;
+use crate::Error;
+use crate::Result;
+
+#[derive(Template)]
+#[template(path = "scaffold_file.rs", escape = "none")]
+struct FileTemplate {
+    /// e.g. "node `double`", or "nodes `double` and `square`"
+    nodes: String,
+    /// deduplicated
+    imports: Vec<&'static str>,
+    items: Vec<String>,
+}
+
+#[derive(Template)]
+#[template(path = "scaffold_user_handler.rs", escape = "none")]
+struct UserHandlerTemplate {
+    node: String,
+    behaviour_is_type: bool,
+    /// the name of the type implementing the trait, if `behaviour_is_type`
+    type_name: String,
+    type_input: String,
+    type_output: String,
+    type_error: String,
+}
+
+#[derive(Template)]
+#[template(path = "scaffold_aggregate.rs", escape = "none")]
+struct AggregateTemplate {
+    node: String,
+    behaviour_is_type: bool,
+    /// the name of the type implementing the trait, if `behaviour_is_type`
+    type_name: String,
+    type_input: String,
+    type_output: String,
+    type_error: String,
+}
+
+#[derive(Template)]
+#[template(path = "scaffold_poll.rs", escape = "none")]
+struct PollTemplate {
+    node: String,
+    behaviour_is_type: bool,
+    /// the name of the type implementing the trait, if `behaviour_is_type`
+    type_name: String,
+    type_input: String,
+    type_output: String,
+    type_error: String,
+}
+
+/// What `Graph::scaffold` wrote, and what is left to the user.
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct Scaffolded {
+    /// The files written.
+    pub written: Vec<PathBuf>,
+    /// The `mod` declarations of the files written, and of the directories created for them,
+    /// as (the parent module, the declaration), e.g. `("crate::behaviour", "pub mod double;")`.
+    pub mod_declarations: Vec<(String, String)>,
+    /// The existing files not written to, with the behaviour types they are expected to define
+    /// but do not mention, e.g. a type added to the graph after its module was scaffolded.
+    pub missing_types: Vec<(PathBuf, Vec<String>)>,
+}
+
+/// The behaviour of a node to scaffold.
+struct Stub {
+    /// relative to the `src` directory of the crate, e.g. `behaviour/double.rs`
+    file: PathBuf,
+    node: String,
+    /// the name of the behaviour type, none for a behaviour module
+    type_name: Option<String>,
+    imports: Vec<&'static str>,
+    content: String,
+}
+
+/// Write a stub for each behaviour module not found in `src_dir`,
+/// the behaviour types defined in the same module are written to the same file.
+pub(crate) fn scaffold(graph: &Graph, src_dir: &Path) -> Result<Scaffolded> {
+    let mut files: BTreeMap<PathBuf, Vec<Stub>> = BTreeMap::new();
+    for stub in stubs(graph)? {
+        files.entry(stub.file.clone()).or_default().push(stub);
+    }
+
+    let mut scaffolded = Scaffolded::default();
+    let mut mod_declarations = BTreeSet::new();
+    for (file, stubs) in files {
+        let file_path = src_dir.join(&file);
+        if let Some(existing) = module_file_in(src_dir, &file.with_extension("")) {
+            let content = std::fs::read_to_string(&existing)?;
+            let missing_types: Vec<String> = stubs
+                .into_iter()
+                .filter_map(|stub| stub.type_name)
+                .filter(|type_name| !mentions(&content, type_name))
+                .collect();
+            if !missing_types.is_empty() {
+                scaffolded.missing_types.push((existing, missing_types));
+            }
+            continue;
+        }
+
+        // the modules of the directories created for the file are not declared either
+        let module: Vec<String> = file
+            .with_extension("")
+            .iter()
+            .map(|segment| segment.to_string_lossy().into_owned())
+            .collect();
+        for depth in (0..module.len()).rev() {
+            let declared = &module[..=depth];
+            let is_new = depth + 1 == module.len()
+                || module_file_in(src_dir, &declared.iter().collect::<PathBuf>()).is_none();
+            if !is_new {
+                break;
+            }
+            let parent = std::iter::once("crate")
+                .chain(module[..depth].iter().map(String::as_str))
+                .collect::<Vec<_>>()
+                .join("::");
+            mod_declarations.insert((parent, format!("pub mod {};", module[depth])));
+        }
+
+        let mut imports: Vec<&'static str> = Vec::new();
+        for import in stubs.iter().flat_map(|stub| stub.imports.iter().copied()) {
+            if !imports.contains(&import) {
+                imports.push(import);
+            }
+        }
+        let nodes: Vec<String> = stubs
+            .iter()
+            .map(|stub| format!("`{}`", stub.node))
+            .collect();
+        let content = FileTemplate {
+            nodes: match nodes.split_last() {
+                Some((last, [])) => format!("node {}", last),
+                Some((last, others)) => format!("nodes {} and {}", others.join(", "), last),
+                None => String::new(),
+            },
+            imports,
+            items: stubs.into_iter().map(|stub| stub.content).collect(),
+        }
+        .render()?;
+
+        if let Some(parent) = file_path.parent() {
+            std::fs::create_dir_all(parent)?;
+        }
+        info!("writing behaviour stub to {}", file_path.display());
+        std::fs::write(&file_path, format!("{}\n", content))?;
+        scaffolded.written.push(file_path);
+    }
+    scaffolded.mod_declarations = mod_declarations.into_iter().collect();
+    Ok(scaffolded)
+}
+
+/// The file defining `module` (e.g. `behaviour/double`) in `src_dir`, if any,
+/// `behaviour/double.rs` or `behaviour/double/mod.rs`.
+fn module_file_in(src_dir: &Path, module: &Path) -> Option<PathBuf> {
+    let file = src_dir.join(module).with_extension("rs");
+    let mod_rs = src_dir.join(module).join("mod.rs");
+    [file, mod_rs].iter().find(|path| path.exists()).cloned()
+}
+
+/// Whether `name` appears in `content` as a whole identifier.
+fn mentions(content: &str, name: &str) -> bool {
+    let is_identifier = |c: char| c.is_alphanumeric() || c == '_';
+    content.match_indices(name).any(|(i, _)| {
+        let before = content[..i].chars().next_back();
+        let after = content[i + name.len()..].chars().next();
+        !before.is_some_and(is_identifier) && !after.is_some_and(is_identifier)
+    })
+}
+
+fn stubs(graph: &Graph) -> Result<Vec<Stub>> {
+    graph.validate()?;
+
+    let g = &graph.g;
+    let mut seen = HashSet::new();
+    let mut stubs = Vec::new();
+    for node_i in g.node_indices() {
+        let node = &g[node_i];
+        let behaviour_module = match node.behaviour_module() {
+            Some(behaviour_module) => behaviour_module,
+            None => continue,
+        };
+        // nodes sharing a behaviour module are scaffolded once
+        if !seen.insert(behaviour_module) {
+            continue;
+        }
+
+        let behaviour_is_type = node.behaviour_kind() == Some(BehaviourKind::Type);
+        let cannot_scaffold = |expected: &str| Error::CannotScaffold {
+            node: node.name(),
+            reason: format!(
+                "behaviour {} should be a path starting with the name of its crate, followed by {}",
+                behaviour_module, expected
+            ),
+        };
+        // a type is written to the file of the module defining it
+        let (module, type_name) = if behaviour_is_type {
+            let (module, type_name) = behaviour_module
+                .rsplit_once("::")
+                .ok_or_else(|| cannot_scaffold("the module and the name of the type"))?;
+            (module, String::from(type_name))
+        } else {
+            (behaviour_module, String::new())
+        };
+        let (crate_name, file) =
+            module_file(module).ok_or_else(|| cannot_scaffold("the module"))?;
+        let relative = |path: &str| relative_to_crate(path, crate_name);
+
+        // the graph is valid, so every node with a behaviour module has an input
+        let type_input = g
+            .edges_directed(node_i, Direction::Incoming)
+            .next()
+            .map(|edge| relative(&edge.weight().msg_type))
+            .unwrap_or_default();
+        let type_output = relative(&node.type_output().unwrap_or_default());
+        let type_error = relative(&graph.type_error);
+        let node_name = node.name();
+
+        let (mut imports, content) = match node {
+            Node::UserHandler { .. } => (
+                vec![],
+                UserHandlerTemplate {
+                    node: node_name.clone(),
+                    behaviour_is_type,
+                    type_name: type_name.clone(),
+                    type_input,
+                    type_output,
+                    type_error,
+                }
+                .render()?,
+            ),
+            Node::Aggregate { .. } => (
+                vec!["dge_runtime::component::aggregate::AggregationStatus"],
+                AggregateTemplate {
+                    node: node_name.clone(),
+                    behaviour_is_type,
+                    type_name: type_name.clone(),
+                    type_input,
+                    type_output,
+                    type_error,
+                }
+                .render()?,
+            ),
+            Node::Poll { .. } => (
+                vec!["dge_runtime::component::poll::Capacity"],
+                PollTemplate {
+                    node: node_name.clone(),
+                    behaviour_is_type,
+                    type_name: type_name.clone(),
+                    type_input,
+                    type_output,
+                    type_error,
+                }
+                .render()?,
+            ),
+            Node::Start { .. } | Node::Terminate { .. } | Node::FanOut { .. } => continue,
+        };
+        if behaviour_is_type {
+            imports.push(match node {
+                Node::Aggregate { .. } => "dge_runtime::Aggregator",
+                Node::Poll { .. } => "dge_runtime::Poller",
+                _ => "dge_runtime::Handler",
+            });
+        }
+        stubs.push(Stub {
+            file,
+            node: node_name,
+            type_name: Some(type_name).filter(|_| behaviour_is_type),
+            imports,
+            content,
+        });
+    }
+    Ok(stubs)
+}
+
+/// Split `crate_name::a::b` into the crate name and the file `a/b.rs` of the module.
+fn module_file(behaviour_module: &str) -> Option<(&str, PathBuf)> {
+    let mut segments = behaviour_module.trim_start_matches("::").split("::");
+    let crate_name = segments.next()?;
+    let mut file: PathBuf = segments.collect();
+    if file.as_os_str().is_empty() {
+        return None;
+    }
+    file.set_extension("rs");
+    Some((crate_name, file))
+}
+
+/// Replace the paths starting with `crate_name::` with `crate::`,
+/// the crate cannot refer to itself by its name.
+fn relative_to_crate(path: &str, crate_name: &str) -> String {
+    let prefix = format!("{}::", crate_name);
+    let mut out = String::with_capacity(path.len());
+    let mut rest = path;
+    while let Some(i) = rest.find(&prefix) {
+        let before = &rest[..i];
+        let preceded_by_path = before.ends_with(|c: char| c.is_alphanumeric() || c == '_' || c == ':');
+        out.push_str(before);
+        if preceded_by_path {
+            out.push_str(&prefix);
+        } else {
+            out.push_str("crate::");
+        }
+        rest = &rest[i + prefix.len()..];
+    }
+    out.push_str(rest);
+    out
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// `start --input--> double --middle--> square --output--> terminate`,
+    /// both behaviours being types of the module `app::behaviour`
+    fn graph() -> Graph {
+        let mut graph = Graph::new("app::accept_failure", "app::Error");
+        let start = graph.start("start");
+        let double = graph.process(
+            start,
+            "input",
+            "app::Integer",
+            "double",
+            "app::behaviour::Double",
+            10,
+            "app::Integer",
+        );
+        let square = graph.process(
+            double,
+            "middle",
+            "app::Integer",
+            "square",
+            "app::behaviour::Square",
+            10,
+            "app::Integer",
+        );
+        graph.terminate(square, "output", "app::Integer", "terminate", 10);
+        for node in [double, square].iter() {
+            graph
+                .set_behaviour_kind(*node, BehaviourKind::Type)
+                .unwrap();
+        }
+        graph
+    }
+
+    fn src_dir(test: &str) -> PathBuf {
+        let src_dir =
+            std::env::temp_dir().join(format!("dge-scaffold-{}-{}", test, std::process::id()));
+        let _ = std::fs::remove_dir_all(&src_dir);
+        src_dir
+    }
+
+    #[test]
+    fn types_of_the_same_module_are_written_to_one_file() {
+        let src_dir = src_dir("one-file");
+        let scaffolded = scaffold(&graph(), &src_dir).unwrap();
+
+        let file = src_dir.join("behaviour.rs");
+        assert_eq!(scaffolded.written, vec![file.clone()]);
+        assert_eq!(
+            scaffolded.mod_declarations,
+            vec![(String::from("crate"), String::from("pub mod behaviour;"))]
+        );
+        assert_eq!(scaffolded.missing_types, vec![]);
+        let content = std::fs::read_to_string(&file).unwrap();
+        assert!(content.starts_with("// Behaviour of nodes `double` and `square`"));
+        assert_eq!(content.matches("use dge_runtime::Handler;").count(), 1);
+        assert!(content.contains("impl Handler for Double {"));
+        assert!(content.contains("impl Handler for Square {"));
+
+        std::fs::remove_dir_all(&src_dir).unwrap();
+    }
+
+    #[test]
+    fn types_missing_from_an_existing_file_are_reported() {
+        let src_dir = src_dir("existing");
+        std::fs::create_dir_all(src_dir.join("behaviour")).unwrap();
+        let file = src_dir.join("behaviour").join("mod.rs");
+        std::fs::write(&file, "pub struct Double;\npub struct Squared;\n").unwrap();
+
+        let scaffolded = scaffold(&graph(), &src_dir).unwrap();
+        assert_eq!(scaffolded.written, Vec::<PathBuf>::new());
+        assert_eq!(scaffolded.mod_declarations, vec![]);
+        assert_eq!(
+            scaffolded.missing_types,
+            vec![(file, vec![String::from("Square")])]
+        );
+
+        std::fs::remove_dir_all(&src_dir).unwrap();
+    }
+
+    #[test]
+    fn new_parent_modules_are_declared_too() {
+        let src_dir = src_dir("parents");
+        std::fs::create_dir_all(&src_dir).unwrap();
+        std::fs::write(src_dir.join("nested.rs"), "").unwrap();
+        let mut graph = Graph::new("app::accept_failure", "app::Error");
+        let start = graph.start("start");
+        let double = graph.process(
+            start,
+            "input",
+            "app::Integer",
+            "double",
+            "app::nested::deep::double",
+            10,
+            "app::Integer",
+        );
+        graph.terminate(double, "output", "app::Integer", "terminate", 10);
+
+        let scaffolded = scaffold(&graph, &src_dir).unwrap();
+        assert_eq!(
+            scaffolded.written,
+            vec![src_dir.join("nested").join("deep").join("double.rs")]
+        );
+        assert_eq!(
+            scaffolded.mod_declarations,
+            vec![
+                (String::from("crate::nested"), String::from("pub mod deep;")),
+                (
+                    String::from("crate::nested::deep"),
+                    String::from("pub mod double;")
+                ),
+            ]
+        );
+
+        std::fs::remove_dir_all(&src_dir).unwrap();
+    }
+
+    #[test]
+    fn mentions_whole_identifiers_only() {
+        let content = "pub struct Squared;\nimpl Handler for Double {}";
+        assert!(mentions(content, "Double"));
+        assert!(!mentions(content, "Square"));
+        assert!(!mentions(content, "Handle"));
+    }
+}
@@ -0,0 +1,210 @@
+use petgraph::visit::EdgeRef;
+use serde::Deserialize;
+use serde::Serialize;
+use std::collections::HashMap;
+use std::path::Path;
+
+use crate::error::ValidationError;
+use crate::graph::Edge;
+use crate::graph::GenerateOptions;
+use crate::graph::Graph;
+use crate::graph::Node;
+use crate::graph::RetryBackoff;
+use crate::Error;
+use crate::Result;
+
+/// Format of a graph spec file.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum SpecFormat {
+    Json,
+    Yaml,
+    Toml,
+}
+
+impl SpecFormat {
+    /// Guess the format from the extension of `path`.
+    pub fn from_path<P: AsRef<Path>>(path: P) -> Result<SpecFormat> {
+        let path = path.as_ref();
+        let extension = path
+            .extension()
+            .and_then(|e| e.to_str())
+            .map(|e| e.to_ascii_lowercase());
+        match extension.as_deref() {
+            Some("json") => Ok(SpecFormat::Json),
+            Some("yaml") | Some("yml") => Ok(SpecFormat::Yaml),
+            Some("toml") => Ok(SpecFormat::Toml),
+            _ => Err(Error::UnknownSpecFormat(path.display().to_string())),
+        }
+    }
+}
+
+/// The on-disk representation of a graph.
+///
+/// Nodes are referred to by their names in the edges,
+/// so node names must be unique.
+#[derive(Serialize, Deserialize)]
+#[serde(deny_unknown_fields)]
+struct GraphSpec {
+    accept_failure: String,
+    type_error: String,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    options: Option<GenerateOptions>,
+    #[serde(default)]
+    nodes: Vec<Node>,
+    #[serde(default)]
+    edges: Vec<EdgeSpec>,
+}
+
+#[derive(Serialize, Deserialize)]
+#[serde(deny_unknown_fields)]
+struct EdgeSpec {
+    from: String,
+    to: String,
+    queue: String,
+    msg_type: String,
+    retry_interval_in_seconds: u32,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    max_attempts: Option<u32>,
+    // a table, TOML requires it to come after the plain values
+    #[serde(default, skip_serializing_if = "RetryBackoff::is_default")]
+    retry_backoff: RetryBackoff,
+}
+
+pub(crate) fn from_str(s: &str, format: SpecFormat) -> Result<Graph> {
+    let spec: GraphSpec = match format {
+        SpecFormat::Json => serde_json::from_str(s)?,
+        SpecFormat::Yaml => serde_yaml::from_str(s)?,
+        SpecFormat::Toml => toml::from_str(s)?,
+    };
+    from_spec(spec)
+}
+
+pub(crate) fn to_string(graph: &Graph, format: SpecFormat) -> Result<String> {
+    let spec = to_spec(graph);
+    let s = match format {
+        SpecFormat::Json => serde_json::to_string_pretty(&spec)?,
+        SpecFormat::Yaml => serde_yaml::to_string(&spec)?,
+        SpecFormat::Toml => toml::to_string(&spec)?,
+    };
+    Ok(s)
+}
+
+fn from_spec(spec: GraphSpec) -> Result<Graph> {
+    let mut graph = Graph::new(spec.accept_failure, spec.type_error);
+    graph.options = spec.options;
+
+    let mut indices = HashMap::new();
+    let mut errors = Vec::new();
+    for node in spec.nodes {
+        let name = node.name();
+        let node_i = graph.g.add_node(node);
+        if indices.insert(name.clone(), node_i).is_some() {
+            errors.push(ValidationError::DuplicateNodeName { name });
+        }
+    }
+    if !errors.is_empty() {
+        return Err(Error::InvalidGraph { errors });
+    }
+
+    for edge in spec.edges {
+        let from = *indices
+            .get(&edge.from)
+            .ok_or_else(|| Error::UnknownNode(edge.from.clone()))?;
+        let to = *indices
+            .get(&edge.to)
+            .ok_or_else(|| Error::UnknownNode(edge.to.clone()))?;
+        graph.g.add_edge(
+            from,
+            to,
+            Edge {
+                queue: edge.queue,
+                msg_type: edge.msg_type,
+                retry_interval_in_seconds: edge.retry_interval_in_seconds,
+                max_attempts: edge.max_attempts,
+                retry_backoff: edge.retry_backoff,
+            },
+        );
+    }
+
+    Ok(graph)
+}
+
+fn to_spec(graph: &Graph) -> GraphSpec {
+    let g = &graph.g;
+    let nodes = g.node_weights().cloned().collect();
+    let edges = g
+        .edge_references()
+        .map(|e| EdgeSpec {
+            from: g[e.source()].name(),
+            to: g[e.target()].name(),
+            queue: e.weight().queue.clone(),
+            msg_type: e.weight().msg_type.clone(),
+            retry_interval_in_seconds: e.weight().retry_interval_in_seconds,
+            max_attempts: e.weight().max_attempts,
+            retry_backoff: e.weight().retry_backoff.clone(),
+        })
+        .collect();
+
+    GraphSpec {
+        accept_failure: graph.accept_failure.clone(),
+        type_error: graph.type_error.clone(),
+        options: graph.options.clone(),
+        nodes,
+        edges,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::graph::NodeOptions;
+
+    fn graph_with_retries_and_node_options() -> Graph {
+        let mut graph = Graph::new("app::accept_failure", "app::Error");
+        let start = graph.start("start");
+        let double = graph.process(
+            start,
+            "input",
+            "app::Integer",
+            "double",
+            "app::double",
+            10,
+            "app::Integer",
+        );
+        graph
+            .set_node_options(double, NodeOptions::new().prefetch_count(8).max_in_flight(4))
+            .unwrap();
+        graph.terminate(double, "output", "app::Integer", "terminate", 10);
+        graph
+            .set_retry_backoff("input", RetryBackoff::new(vec![30, 120]).jitter_percent(20))
+            .unwrap();
+        graph.set_max_attempts("input", 5).unwrap();
+        graph
+    }
+
+    #[test]
+    fn saved_graph_loads_back_in_every_format() {
+        let graph = graph_with_retries_and_node_options();
+        for &format in &[SpecFormat::Json, SpecFormat::Yaml, SpecFormat::Toml] {
+            let saved = to_string(&graph, format).unwrap();
+            let loaded = from_str(&saved, format).unwrap();
+            assert_eq!(to_string(&loaded, format).unwrap(), saved, "{:?}", format);
+
+            let input = loaded.g.edge_weights().find(|e| e.queue == "input").unwrap();
+            assert_eq!(input.max_attempts, Some(5), "{:?}", format);
+            assert_eq!(
+                input.retry_backoff,
+                RetryBackoff::new(vec![30, 120]).jitter_percent(20),
+                "{:?}",
+                format
+            );
+            let double = loaded.g.node_weights().find(|n| n.name() == "double").unwrap();
+            assert_eq!(
+                double.node_options(),
+                Some(&NodeOptions::new().prefetch_count(8).max_in_flight(4)),
+                "{:?}",
+                format
+            );
+        }
+    }
+}
@@ -4,19 +4,26 @@
 
 type HandlerState = ();
 
-#[rustfmt::skip]
-#[tokio::main(worker_threads = 2)]
-pub(crate) async fn main() -> Result<()> {
-    let rmq_uri = {{ rmq_options.get_rmq_uri }}();
+{% include "part_node_main.rs" %}
+
+{% include "part_retry_policy.rs" %}
 
+#[rustfmt::skip]
+pub(crate) async fn run(
+    broker: dge_runtime::broker::Broker,
+    settings: dge_runtime::NodeSettings,
+) -> Result<()> {
+    let instance = dge_runtime::NodeInstance::start(NODE);
     let handler_state = ();
 
     let () = dge_runtime::rmq::consume_forever(
-        &rmq_uri,
+        &broker,
+        instance,
         {{ input_queue }},
+        RETRY_POLICY,
         handler,
         handler_state,
-        {{ prefetch_count }},
+        settings,
     ).await;
 
     Ok(())
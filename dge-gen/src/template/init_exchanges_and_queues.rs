@@ -25,6 +25,33 @@ pub(crate) async fn main() -> Result<()> {
         all_queues,
     ).await?;
 
+    // the retry queues of the backoff tiers
+    // (work_queue, retry_queue_of_tier)
+    let all_retry_tiers: Vec<(&str, &str)> = vec![
+        {%- for t in all_retry_tiers %}
+        ("{{t.0}}", "{{t.1}}"),
+        {%- endfor %}
+    ];
+
+    let () = rmq_init::init_retry_tiers(
+        rmq_uri.as_ref(),
+        "{{ rmq_options.work_exchange }}",
+        "{{ rmq_options.retry_exchange }}",
+        all_retry_tiers,
+    ).await?;
+
+    // the queues the messages failing too many times are parked in
+    let all_parking_queues: Vec<&str> = vec![
+        {%- for q in all_parking_queues %}
+        "{{q}}",
+        {%- endfor %}
+    ];
+
+    let () = rmq_init::init_parking_queues(
+        rmq_uri.as_ref(),
+        all_parking_queues,
+    ).await?;
+
     info!("all necessary exchanges and queues initialized");
 
     Ok(())
@@ -4,27 +4,115 @@ use structopt::StructOpt;
 use dge_runtime::Result;
 
 // these are the codes for each node
+{%- match include_dir %}
+{%- when Some with (include_dir) %}
+{%- for command in commands %}
+mod {{ command.module }} { include!(concat!(env!("OUT_DIR"), "/{{ include_dir }}/{{ command.module }}.rs")); }
+{%- endfor %}
+{%- when None %}
 {%- for command in commands %}
 mod {{ command.module }};
 {%- endfor %}
+{%- endmatch %}
+
+#[rustfmt::skip]
+#[derive(Debug, StructOpt)]
+struct Options {
+    /// Prefixed to the names of all the exchanges and queues, e.g. staging.input for queue input
+    #[structopt(long, global = true, env = dge_runtime::namespace::NAMESPACE_ENV)]
+    namespace: Option<String>,
+
+    #[structopt(subcommand)]
+    command: Command,
+}
 
 #[rustfmt::skip]
 #[derive(Debug, StructOpt)]
 enum Command {
     {%- for command in commands %}
+    {%- if command.runs_node %}
+    /// Run node {{ command.module }}
+    {{ command.variant }}(dge_runtime::NodeOptions),
+    {%- else %}
+    /// Declare the exchanges and queues used by the graph
     {{ command.variant }},
+    {%- endif %}
     {%- endfor %}
+    /// Run all the nodes in this process, for development
+    RunAll(dge_runtime::RunAllOptions),
+    /// Migrate the queues and exchanges of a previous version of the graph, as planned by `dge plan-migration`
+    Migrate(dge_runtime::MigrateOptions),
+    /// Inspect, replay and purge the messages parked after failing too many times
+    Parked(dge_runtime::ParkedCommand),
 }
 
 #[rustfmt::skip]
-fn main() -> Result<()> {
+pub fn main() -> Result<()> {
     {{ setup_logger }}();
 
-    let command = Command::from_args();
+    let options = Options::from_args();
+    if let Some(namespace) = &options.namespace {
+        dge_runtime::namespace::set_namespace(namespace)?;
+    }
 
-    match command {
+    match options.command {
         {%- for command in commands %}
+        {%- if command.runs_node %}
+        Command::{{ command.variant }}(options) => {{ command.module }}::main(options),
+        {%- else %}
         Command::{{ command.variant }} => {{ command.module }}::main(),
+        {%- endif %}
         {%- endfor %}
+        Command::RunAll(options) => run_all(options),
+        Command::Migrate(options) => migrate(options),
+        Command::Parked(command) => parked(command),
     }
+}
+
+#[rustfmt::skip]
+fn run_all(options: dge_runtime::RunAllOptions) -> Result<()> {
+    options.check_nodes(&[
+        {%- for command in commands %}
+        {%- if command.runs_node %}
+        "{{ command.module }}",
+        {%- endif %}
+        {%- endfor %}
+    ])?;
+
+    let _telemetry = dge_runtime::telemetry::init("run-all")?;
+    let broker = dge_runtime::broker::Broker::new({{ get_rmq_uri }}(), options.connection.clone());
+    let nodes = async {
+        let mut nodes = Vec::new();
+        {%- for command in commands %}
+        {%- if command.runs_node %}
+        for _ in 0..options.instances_of("{{ command.module }}") {
+            let settings = {{ command.module }}::settings(Default::default());
+            nodes.push(tokio::spawn({{ command.module }}::run(broker.clone(), settings)));
+        }
+        {%- endif %}
+        {%- endfor %}
+        dge_runtime::run_all::wait_all(nodes).await
+    };
+    let nodes = dge_runtime::shutdown::on_signal(options.shutdown.clone(), broker.clone(), nodes);
+    options.block_on(dge_runtime::http::serving(options.http.clone(), nodes))
+}
+
+#[rustfmt::skip]
+#[tokio::main]
+async fn migrate(options: dge_runtime::MigrateOptions) -> Result<()> {
+    let broker = dge_runtime::broker::Broker::new({{ get_rmq_uri }}(), Default::default());
+    dge_runtime::rmq_migrate::migrate(&broker, &options).await
+}
+
+#[rustfmt::skip]
+#[tokio::main]
+async fn parked(command: dge_runtime::ParkedCommand) -> Result<()> {
+    let broker = dge_runtime::broker::Broker::new({{ get_rmq_uri }}(), Default::default());
+    // (work_queue, parking_queue)
+    let parking_queues: &[(&str, &str)] = &[
+        {%- for q in parking_queues %}
+        ("{{ q.0 }}", "{{ q.1 }}"),
+        {%- endfor %}
+    ];
+    dge_runtime::rmq_parked::parked(&broker, "{{ work_exchange }}", parking_queues, &command).await
 }
\ No newline at end of file
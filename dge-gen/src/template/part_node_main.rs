@@ -0,0 +1,23 @@
+const NODE: &str = "{{ node }}";
+
+// The defaults are declared in the graph, and can be overridden by the command line.
+#[rustfmt::skip]
+pub(crate) fn settings(options: dge_runtime::NodeOptions) -> dge_runtime::NodeSettings {
+    options.or(dge_runtime::NodeSettings {
+        prefetch_count: {{ node_settings.prefetch_count }},
+        worker_threads: {{ node_settings.worker_threads }},
+        max_in_flight: {{ node_settings.max_in_flight }},
+    })
+}
+
+#[rustfmt::skip]
+pub(crate) fn main(options: dge_runtime::NodeOptions) -> Result<()> {
+    let connection = options.connection.clone();
+    let http = options.http.clone();
+    let shutdown = options.shutdown.clone();
+    let settings = settings(options);
+    let _telemetry = dge_runtime::telemetry::init(NODE)?;
+    let broker = dge_runtime::broker::Broker::new({{ rmq_options.get_rmq_uri }}(), connection);
+    let node = dge_runtime::shutdown::on_signal(shutdown, broker.clone(), run(broker, settings));
+    settings.block_on(dge_runtime::http::serving(http, node))
+}
@@ -0,0 +1,19 @@
+// How the messages rejected from the input queue are retried, declared in the graph.
+#[rustfmt::skip]
+const RETRY_POLICY: dge_runtime::retry::RetryPolicy = dge_runtime::retry::RetryPolicy {
+    retry_exchange: "{{ rmq_options.retry_exchange }}",
+    retry_queue: "{{ retry_policy.retry_queue }}",
+    retry_interval_in_seconds: {{ retry_policy.retry_interval_in_seconds }},
+    {%- if retry_policy.tiers.is_empty() %}
+    tiers: &[],
+    {%- else %}
+    tiers: &[
+        {%- for tier in retry_policy.tiers %}
+        ("{{ tier.0 }}", {{ tier.1 }}),
+        {%- endfor %}
+    ],
+    {%- endif %}
+    jitter_percent: {{ retry_policy.jitter_percent }},
+    max_attempts: {{ retry_policy.max_attempts }},
+    parking_queue: "{{ retry_policy.parking_queue }}",
+};
@@ -8,35 +8,50 @@ use tokio::sync::RwLock;
 use dge_runtime::component::poll::Jobs;
 use dge_runtime::component::poll::new_job;
 use dge_runtime::component::poll::poll_forever;
+use dge_runtime::Poller;
+
+{% include "part_node_main.rs" %}
+
+{% include "part_retry_policy.rs" %}
 
 #[rustfmt::skip]
-#[tokio::main(worker_threads = 10)]
-pub(crate) async fn main() -> Result<()> {
+pub(crate) async fn run(
+    broker: dge_runtime::broker::Broker,
+    settings: dge_runtime::NodeSettings,
+) -> Result<()> {
+    let instance = dge_runtime::NodeInstance::start(NODE);
+
     // load existing jobs
     let jobs = load_jobs().await?;
 
     // start a thread to poll the jobs
-    tokio::spawn(poll_forever(
-        {{ behaviour_module }}::get_capacity(),
+    let polling = tokio::spawn(poll_forever(
+        NODE,
+        Behaviour::get_capacity(),
         jobs.clone(),
 
         // these are used when do the actual checking
-        {{ behaviour_module }}::check,
+        check,
         {{ accept_failure }},
-        {{ rmq_options.get_rmq_uri }},
-        "{{ rmq_options.work_exchange }}",
-        {{ output_queue }},
+        dge_runtime::component::poll::Output {
+            broker: broker.clone(),
+            work_exchange: "{{ rmq_options.work_exchange }}",
+            queue: {{ output_queue }},
+        },
     ));
 
-    let rmq_uri = {{ rmq_options.get_rmq_uri }}();
     let () = dge_runtime::rmq::consume_forever(
-        &rmq_uri,
+        &broker,
+        instance,
         {{ input_queue }},
+        RETRY_POLICY,
         handler,
         jobs,
-        {{ prefetch_count }},
+        settings,
     ).await;
 
+    // shutting down, the running checks are waited for
+    polling.await.map_err(|e| dge_runtime::Error::NodePanicked(e.to_string()))?;
     Ok(())
 }
 
@@ -47,7 +62,7 @@ async fn load_jobs() -> Result<Jobs<{{ type_input }}>> {
     let mut write_jobs = write_jobs.write().await;
 
     info!("loading messages");
-    let msgs = {{ behaviour_module }}::init().await;
+    let msgs = Behaviour::init().await;
 
     info!("loaded {} messages, adding them to the job queue", msgs.len());
     for msg in msgs {
@@ -70,6 +85,51 @@ async fn handler(
     dge_runtime::add_to_jobs!(
         jobs = jobs,
         msg = msg,
-        save_msg = {{ behaviour_module }}::save_msg,
+        save_msg = Behaviour::save_msg,
     )
+}
+
+#[rustfmt::skip]
+async fn check(msg: {{ type_input }}) -> std::result::Result<Option<{{ type_output }}>, {{ type_error }}> {
+    Behaviour::check(msg).await
+}
+
+{% if behaviour_is_type -%}
+type Behaviour = {{ behaviour_module }};
+{%- else -%}
+// Binds the behaviour module to `dge_runtime::Poller`.
+struct Behaviour;
+
+#[rustfmt::skip]
+#[dge_runtime::async_trait]
+impl Poller for Behaviour {
+    type Input = {{ type_input }};
+    type Output = {{ type_output }};
+    type Error = {{ type_error }};
+
+    async fn init() -> Vec<Self::Input> {
+        {{ behaviour_module }}::init().await
+    }
+
+    async fn save_msg(msg: Self::Input) -> std::result::Result<(), Self::Error> {
+        {{ behaviour_module }}::save_msg(msg).await
+    }
+
+    fn get_capacity() -> dge_runtime::component::poll::Capacity {
+        {{ behaviour_module }}::get_capacity()
+    }
+
+    async fn check(msg: Self::Input) -> std::result::Result<Option<Self::Output>, Self::Error> {
+        {{ behaviour_module }}::check(msg).await
+    }
+}
+{%- endif %}
+
+// Never called, this only asks the compiler to check that
+// the behaviour consumes and produces the message types declared in the graph.
+#[rustfmt::skip]
+#[allow(dead_code)]
+fn assert_message_types() {
+    fn assert_poller<B: Poller<Input = {{ type_input }}, Output = {{ type_output }}, Error = {{ type_error }}>>() {}
+    assert_poller::<Behaviour>();
 }
\ No newline at end of file
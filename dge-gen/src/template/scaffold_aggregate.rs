@@ -0,0 +1,35 @@
+{%- if behaviour_is_type -%}
+pub struct {{ type_name }};
+
+#[dge_runtime::async_trait]
+impl Aggregator for {{ type_name }} {
+    type Input = {{ type_input }};
+    type Output = {{ type_output }};
+    /// Shared by all the messages handled by the node, it is cloned for each message,
+    /// so the messages seen so far are usually kept behind an `Arc<Mutex<...>>`.
+    type State = ();
+    type Error = {{ type_error }};
+
+    async fn init() -> Self::State {}
+
+    /// Return `AggregationStatus::Aggregated` once all the messages to aggregate have arrived,
+    /// and `AggregationStatus::Ignore` before that.
+    async fn aggregate(_state: Self::State, _msg: &Self::Input) -> Result<AggregationStatus<Self::Output>, Self::Error> {
+        todo!("aggregate the message of node {{ node }}")
+    }
+}
+{%- else -%}
+/// Shared by all the messages handled by the node, it is cloned for each message,
+/// so the messages seen so far are usually kept behind an `Arc<Mutex<...>>`.
+pub type State = ();
+
+pub async fn init() -> State {
+    Default::default()
+}
+
+/// Return `AggregationStatus::Aggregated` once all the messages to aggregate have arrived,
+/// and `AggregationStatus::Ignore` before that.
+pub async fn aggregate(_state: State, _msg: &{{ type_input }}) -> Result<AggregationStatus<{{ type_output }}>, {{ type_error }}> {
+    todo!("aggregate the message of node {{ node }}")
+}
+{%- endif %}
@@ -0,0 +1,13 @@
+// Behaviour of {{ nodes }}, scaffolded by dge-gen.
+//
+// This file is written only once, when it does not exist, and is yours to edit from then on.
+// The signatures below are the ones the generated code calls.
+{%- if !imports.is_empty() %}
+{% for import in imports %}
+use {{ import }};
+{%- endfor %}
+{%- endif %}
+{%- for item in items %}
+
+{{ item }}
+{%- endfor %}
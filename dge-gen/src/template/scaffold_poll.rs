@@ -0,0 +1,48 @@
+{%- if behaviour_is_type -%}
+pub struct {{ type_name }};
+
+#[dge_runtime::async_trait]
+impl Poller for {{ type_name }} {
+    type Input = {{ type_input }};
+    type Output = {{ type_output }};
+    type Error = {{ type_error }};
+
+    /// The jobs to resume when the node starts, e.g. the ones saved by `save_msg` and not finished yet.
+    async fn init() -> Vec<Self::Input> {
+        vec![]
+    }
+
+    /// Called for each new job, before it is acknowledged.
+    async fn save_msg(_msg: Self::Input) -> Result<(), Self::Error> {
+        Ok(())
+    }
+
+    fn get_capacity() -> Capacity {
+        Capacity::default()
+    }
+
+    /// Return `Ok(None)` while the job is not finished, it will be checked again later.
+    async fn check(_msg: Self::Input) -> Result<Option<Self::Output>, Self::Error> {
+        todo!("check the job of node {{ node }}")
+    }
+}
+{%- else -%}
+/// The jobs to resume when the node starts, e.g. the ones saved by `save_msg` and not finished yet.
+pub async fn init() -> Vec<{{ type_input }}> {
+    vec![]
+}
+
+/// Called for each new job, before it is acknowledged.
+pub async fn save_msg(_msg: {{ type_input }}) -> Result<(), {{ type_error }}> {
+    Ok(())
+}
+
+pub fn get_capacity() -> Capacity {
+    Capacity::default()
+}
+
+/// Return `Ok(None)` while the job is not finished, it will be checked again later.
+pub async fn check(_msg: {{ type_input }}) -> Result<Option<{{ type_output }}>, {{ type_error }}> {
+    todo!("check the job of node {{ node }}")
+}
+{%- endif %}
@@ -0,0 +1,29 @@
+{%- if behaviour_is_type -%}
+pub struct {{ type_name }};
+
+#[dge_runtime::async_trait]
+impl Handler for {{ type_name }} {
+    type Input = {{ type_input }};
+    type Output = {{ type_output }};
+    /// Shared by all the messages handled by the node, it is cloned for each message.
+    type State = ();
+    type Error = {{ type_error }};
+
+    async fn init() -> Self::State {}
+
+    async fn handle(_state: Self::State, _msg: &Self::Input) -> Result<Self::Output, Self::Error> {
+        todo!("handle the message of node {{ node }}")
+    }
+}
+{%- else -%}
+/// Shared by all the messages handled by the node, it is cloned for each message.
+pub type State = ();
+
+pub async fn init() -> State {
+    Default::default()
+}
+
+pub async fn handle(_state: State, _msg: &{{ type_input }}) -> Result<{{ type_output }}, {{ type_error }}> {
+    todo!("handle the message of node {{ node }}")
+}
+{%- endif %}
@@ -2,19 +2,28 @@
 
 {% include "part_common_import.rs" %}
 
-#[rustfmt::skip]
-#[tokio::main(worker_threads = 2)]
-pub(crate) async fn main() -> Result<()> {
-    let rmq_uri = {{ rmq_options.get_rmq_uri }}();
+use dge_runtime::Handler;
+
+{% include "part_node_main.rs" %}
 
-    let handler_state = {{ behaviour_module }}::init().await;
+{% include "part_retry_policy.rs" %}
+
+#[rustfmt::skip]
+pub(crate) async fn run(
+    broker: dge_runtime::broker::Broker,
+    settings: dge_runtime::NodeSettings,
+) -> Result<()> {
+    let instance = dge_runtime::NodeInstance::start(NODE);
+    let handler_state = Behaviour::init().await;
 
     let () = dge_runtime::rmq::consume_forever(
-        &rmq_uri,
+        &broker,
+        instance,
         {{ input_queue }},
+        RETRY_POLICY,
         handler,
         handler_state,
-        {{ prefetch_count }},
+        settings,
     ).await;
 
     Ok(())
@@ -23,7 +32,7 @@ pub(crate) async fn main() -> Result<()> {
 
 #[rustfmt::skip]
 async fn handler(
-    state: {{ behaviour_module }}::State,
+    state: <Behaviour as Handler>::State,
     channel: Channel,
     msg: {{ type_input }},
 ) -> Result<Responsibility>
@@ -32,9 +41,42 @@ async fn handler(
         state = state,
         channel = channel,
         msg = msg,
-        user_handler = {{ behaviour_module }}::handle,
+        user_handler = Behaviour::handle,
         accept_failure = {{ accept_failure }},
         output_queue = {{ output_queue }},
         exchange = "{{ rmq_options.work_exchange }}",
     )
+}
+
+{% if behaviour_is_type -%}
+type Behaviour = {{ behaviour_module }};
+{%- else -%}
+// Binds the behaviour module to `dge_runtime::Handler`.
+struct Behaviour;
+
+#[rustfmt::skip]
+#[dge_runtime::async_trait]
+impl Handler for Behaviour {
+    type Input = {{ type_input }};
+    type Output = {{ type_output }};
+    type State = {{ behaviour_module }}::State;
+    type Error = {{ type_error }};
+
+    async fn init() -> Self::State {
+        {{ behaviour_module }}::init().await
+    }
+
+    async fn handle(state: Self::State, msg: &Self::Input) -> std::result::Result<Self::Output, Self::Error> {
+        {{ behaviour_module }}::handle(state, msg).await
+    }
+}
+{%- endif %}
+
+// Never called, this only asks the compiler to check that
+// the behaviour consumes and produces the message types declared in the graph.
+#[rustfmt::skip]
+#[allow(dead_code)]
+fn assert_message_types() {
+    fn assert_handler<B: Handler<Input = {{ type_input }}, Output = {{ type_output }}, Error = {{ type_error }}>>() {}
+    assert_handler::<Behaviour>();
 }
\ No newline at end of file
@@ -0,0 +1,188 @@
+//! A machine-readable description of the graph and the RabbitMQ entities backing it,
+//! for tools other than dge.
+//!
+//! The document is versioned by `Topology::format_version`,
+//! fields are only added (never renamed or removed) within the same version.
+
+use petgraph::visit::EdgeRef;
+use petgraph::Direction;
+use serde::Deserialize;
+use serde::Serialize;
+use std::collections::BTreeMap;
+
+use crate::generate::graph::retry_tiers;
+use crate::generate::graph::RmqOptions;
+use crate::graph::BehaviourKind;
+use crate::graph::Graph;
+use crate::graph::Node;
+use crate::GenerateOptions;
+
+/// The current version of the topology document.
+pub const FORMAT_VERSION: u32 = 1;
+
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Topology {
+    pub format_version: u32,
+    pub accept_failure: String,
+    pub type_error: String,
+    pub exchanges: Exchanges,
+    /// in the order they are added to the graph
+    pub nodes: Vec<TopologyNode>,
+    /// sorted by the name of the queue
+    pub queues: Vec<TopologyQueue>,
+}
+
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Exchanges {
+    /// the direct exchange delivering the messages to the work queues
+    pub work: String,
+    /// the direct exchange delivering the messages to the retry queues
+    pub retry: String,
+}
+
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub struct TopologyNode {
+    pub name: String,
+    /// one of `start`, `terminate`, `aggregate`, `fan_out`, `user_handler` and `poll`,
+    /// the same as in the spec files
+    pub kind: String,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub behaviour_module: Option<String>,
+    /// whether `behaviour_module` names a module or a type, see `dge_gen::BehaviourKind`
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub behaviour_kind: Option<BehaviourKind>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub type_output: Option<String>,
+    /// the queues this node consumes
+    pub inputs: Vec<String>,
+    /// the queues this node publishes to
+    pub outputs: Vec<String>,
+}
+
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub struct TopologyQueue {
+    pub name: String,
+    /// failed messages wait in this queue before being dead lettered back to the work queue
+    pub retry_queue: String,
+    pub retry_interval_in_seconds: u32,
+    /// the retry queues of the later retries, see `dge_gen::RetryBackoff`
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub retry_tiers: Vec<TopologyRetryTier>,
+    #[serde(default, skip_serializing_if = "is_zero")]
+    pub retry_jitter_percent: u8,
+    /// after failing this many times, a message is moved to `parking_queue`
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub max_attempts: Option<u32>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub parking_queue: Option<String>,
+    pub message_type: String,
+    pub producers: Vec<String>,
+    pub consumer: String,
+    /// whether the queue is declared by the `init-exchanges-and-queues` subcommand
+    pub declared_by_init: bool,
+}
+
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub struct TopologyRetryTier {
+    pub retry_queue: String,
+    pub delay_in_seconds: u32,
+}
+
+fn is_zero(n: &u8) -> bool {
+    *n == 0
+}
+
+pub(crate) fn topology(graph: &Graph, options: &GenerateOptions) -> Topology {
+    let g = &graph.g;
+    let rmq_options = RmqOptions::from(options);
+
+    let nodes = g
+        .node_indices()
+        .map(|node_i| {
+            let node = &g[node_i];
+            let queues = |direction| {
+                let mut queues: Vec<_> = g
+                    .edges_directed(node_i, direction)
+                    .map(|e| e.weight().queue.clone())
+                    .collect();
+                queues.sort();
+                queues.dedup();
+                queues
+            };
+            TopologyNode {
+                name: node.name(),
+                kind: String::from(kind(node)),
+                behaviour_module: node.behaviour_module().map(String::from),
+                behaviour_kind: node.behaviour_kind(),
+                type_output: node.type_output(),
+                inputs: queues(Direction::Incoming),
+                outputs: queues(Direction::Outgoing),
+            }
+        })
+        .collect();
+
+    let mut queues: BTreeMap<String, TopologyQueue> = BTreeMap::new();
+    for edge in g.edge_references() {
+        let weight = edge.weight();
+        let producer = &g[edge.source()];
+        let consumer = &g[edge.target()];
+        let queue = queues
+            .entry(weight.queue.clone())
+            .or_insert_with(|| TopologyQueue {
+                name: weight.queue.clone(),
+                retry_queue: rmq_options.retry_queue(&weight.queue),
+                retry_interval_in_seconds: weight.retry_interval_in_seconds,
+                retry_tiers: retry_tiers(&rmq_options, weight)
+                    .into_iter()
+                    .map(|(retry_queue, delay_in_seconds)| TopologyRetryTier {
+                        retry_queue,
+                        delay_in_seconds,
+                    })
+                    .collect(),
+                retry_jitter_percent: weight.retry_backoff.jitter_percent,
+                max_attempts: weight.max_attempts,
+                parking_queue: weight
+                    .max_attempts
+                    .map(|_| rmq_options.parking_queue(&weight.queue)),
+                message_type: weight.msg_type.clone(),
+                producers: Vec::new(),
+                consumer: consumer.name(),
+                declared_by_init: true,
+            });
+        queue.producers.push(producer.name());
+        match (producer, consumer) {
+            (Node::Start { .. }, _) if !options.init_input_queue => queue.declared_by_init = false,
+            (_, Node::Terminate { .. }) if !options.init_output_queue => {
+                queue.declared_by_init = false
+            }
+            _ => (),
+        }
+    }
+    for queue in queues.values_mut() {
+        queue.producers.sort();
+    }
+
+    Topology {
+        format_version: FORMAT_VERSION,
+        accept_failure: graph.accept_failure.clone(),
+        type_error: graph.type_error.clone(),
+        exchanges: Exchanges {
+            work: options.work_exchange.clone(),
+            retry: options.retry_exchange.clone(),
+        },
+        nodes,
+        queues: queues.into_values().collect(),
+    }
+}
+
+/// The kind of the node, as in the spec files.
+fn kind(node: &Node) -> &'static str {
+    match node {
+        Node::Start { .. } => "start",
+        Node::Terminate { .. } => "terminate",
+        Node::Aggregate { .. } => "aggregate",
+        Node::FanOut { .. } => "fan_out",
+        Node::UserHandler { .. } => "user_handler",
+        Node::Poll { .. } => "poll",
+    }
+}
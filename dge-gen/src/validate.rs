@@ -0,0 +1,652 @@
+use petgraph::visit::Dfs;
+use petgraph::visit::EdgeRef;
+use petgraph::Direction;
+use std::collections::BTreeMap;
+use std::collections::BTreeSet;
+use std::collections::HashSet;
+
+use crate::error::ValidationError;
+use crate::generate::graph::retry_tiers;
+use crate::generate::graph::RmqOptions;
+use crate::graph::GenerateOptions;
+use crate::graph::Graph;
+use crate::graph::Node;
+use crate::graph::NodeIndex;
+use crate::graph::PetGraph;
+
+/// Names of the files generated alongside the node files,
+/// a node with one of these names would overwrite them.
+const RESERVED_NODE_NAMES: [&str; 5] = [
+    "main",
+    "init_exchanges_and_queues",
+    "run_all",
+    "migrate",
+    "parked",
+];
+
+const RUST_KEYWORDS: [&str; 51] = [
+    "as", "break", "const", "continue", "crate", "else", "enum", "extern", "false", "fn", "for",
+    "if", "impl", "in", "let", "loop", "match", "mod", "move", "mut", "pub", "ref", "return",
+    "self", "Self", "static", "struct", "super", "trait", "true", "type", "unsafe", "use",
+    "where", "while", "async", "await", "dyn", "abstract", "become", "box", "do", "final",
+    "macro", "override", "priv", "typeof", "unsized", "virtual", "yield", "try",
+];
+
+/// Check the graph, and return all the problems found.
+///
+/// The names of the retry and parking queues are only checked when `options` are given,
+/// they depend on the prefix and suffix of the retry queues.
+/// An empty result means the graph is valid.
+pub(crate) fn validate(graph: &Graph, options: Option<&GenerateOptions>) -> Vec<ValidationError> {
+    let g = &graph.g;
+    let mut errors = Vec::new();
+
+    check_node_names(g, &mut errors);
+    check_queue_names(g, &mut errors);
+    if let Some(options) = options {
+        check_derived_queue_names(g, &RmqOptions::from(options), &mut errors);
+    }
+    check_cycles(g, &mut errors);
+    check_queue_consumers(g, &mut errors);
+    check_edge_types(g, &mut errors);
+    check_arity(g, &mut errors);
+    check_reachability(g, &mut errors);
+    check_node_options(g, &mut errors);
+    check_retry_policies(g, &mut errors);
+
+    errors
+}
+
+fn check_node_names(g: &PetGraph, errors: &mut Vec<ValidationError>) {
+    let mut seen = HashSet::new();
+    let mut duplicates = BTreeSet::new();
+
+    for node in g.node_weights() {
+        let name = node.name();
+
+        if !seen.insert(name.clone()) {
+            duplicates.insert(name.clone());
+        }
+
+        if !is_rust_identifier(&name) {
+            errors.push(ValidationError::InvalidNodeName { name: name.clone() });
+        } else if RESERVED_NODE_NAMES.contains(&name.as_str()) {
+            errors.push(ValidationError::ReservedNodeName { name: name.clone() });
+        }
+
+        match node {
+            Node::Aggregate { behaviour_module, .. }
+            | Node::UserHandler { behaviour_module, .. }
+            | Node::Poll { behaviour_module, .. } => {
+                if !is_rust_path(behaviour_module) {
+                    errors.push(ValidationError::InvalidBehaviourModule {
+                        node: name,
+                        behaviour_module: behaviour_module.clone(),
+                    });
+                }
+            }
+            Node::Start { .. } | Node::Terminate { .. } | Node::FanOut { .. } => (),
+        }
+    }
+
+    for name in duplicates {
+        errors.push(ValidationError::DuplicateNodeName { name });
+    }
+}
+
+fn check_queue_names(g: &PetGraph, errors: &mut Vec<ValidationError>) {
+    let queues: BTreeSet<_> = g.edge_weights().map(|e| e.queue.clone()).collect();
+    for queue in queues {
+        if let Some(reason) = invalid_queue_name(&queue) {
+            errors.push(ValidationError::InvalidQueueName {
+                queue,
+                reason: reason.into(),
+            });
+        }
+    }
+}
+
+/// Why `queue` cannot be the name of a queue, if it cannot.
+fn invalid_queue_name(queue: &str) -> Option<&'static str> {
+    // the queue name is embedded in the generated code as a string literal
+    if queue.is_empty() {
+        Some("it is empty")
+    } else if queue.len() > 255 {
+        Some("it is longer than 255 bytes")
+    } else if queue.starts_with("amq.") {
+        Some("names starting with \"amq.\" are reserved by RabbitMQ")
+    } else if queue.chars().any(|c| c == '"' || c == '\\' || c.is_control()) {
+        Some("it contains quotes, backslashes or control characters")
+    } else {
+        None
+    }
+}
+
+/// The retry queues, the retry queues of the backoff tiers and the parking queues
+/// are named after their work queues, each name must be valid,
+/// and must not be the name of another queue.
+fn check_derived_queue_names(
+    g: &PetGraph,
+    rmq_options: &RmqOptions,
+    errors: &mut Vec<ValidationError>,
+) {
+    let edges: BTreeMap<_, _> = g.edge_weights().map(|edge| (&edge.queue, edge)).collect();
+    // what each queue is for, by name
+    let mut uses: BTreeMap<String, Vec<String>> = BTreeMap::new();
+    for (queue, edge) in edges {
+        uses.entry(queue.clone())
+            .or_default()
+            .push(String::from("work queue"));
+
+        let mut derived = vec![(rmq_options.retry_queue(queue), String::from("retry queue"))];
+        for (i, (tier_queue, _)) in retry_tiers(rmq_options, edge).into_iter().enumerate() {
+            derived.push((tier_queue, format!("retry queue of tier {}", i + 1)));
+        }
+        if edge.max_attempts.is_some() {
+            derived.push((rmq_options.parking_queue(queue), String::from("parking queue")));
+        }
+
+        for (name, what) in derived {
+            // an invalid work queue is already reported, and so would be all its derived queues
+            if invalid_queue_name(queue).is_none() {
+                if let Some(reason) = invalid_queue_name(&name) {
+                    errors.push(ValidationError::InvalidQueueName {
+                        queue: name.clone(),
+                        reason: format!("{}, it is the {} of {}", reason, what, queue),
+                    });
+                }
+            }
+            uses.entry(name)
+                .or_default()
+                .push(format!("{} of {}", what, queue));
+        }
+    }
+
+    for (queue, uses) in uses {
+        if uses.len() > 1 {
+            errors.push(ValidationError::QueueNameCollision { queue, uses });
+        }
+    }
+}
+
+fn check_cycles(g: &PetGraph, errors: &mut Vec<ValidationError>) {
+    for scc in petgraph::algo::tarjan_scc(g) {
+        let is_cycle = scc.len() > 1 || g.find_edge(scc[0], scc[0]).is_some();
+        if is_cycle {
+            let mut nodes: Vec<_> = scc.iter().map(|i| g[*i].name()).collect();
+            nodes.sort();
+            errors.push(ValidationError::Cycle { nodes });
+        }
+    }
+}
+
+/// Every queue must be consumed by exactly one node,
+/// and only an aggregation node can have multiple (identical) edges backed by the same queue.
+fn check_queue_consumers(g: &PetGraph, errors: &mut Vec<ValidationError>) {
+    let mut by_queue: BTreeMap<String, Vec<_>> = BTreeMap::new();
+    for edge in g.edge_references() {
+        by_queue
+            .entry(edge.weight().queue.clone())
+            .or_default()
+            .push(edge);
+    }
+
+    for (queue, edges) in by_queue {
+        let consumers: BTreeSet<NodeIndex> = edges.iter().map(|e| e.target()).collect();
+        if consumers.len() > 1 {
+            errors.push(ValidationError::QueueWithMultipleConsumers {
+                queue,
+                consumers: consumers.iter().map(|i| g[*i].name()).collect(),
+            });
+            continue;
+        }
+
+        if edges.len() > 1 {
+            let consumer = edges[0].target();
+            let is_aggregate = matches!(g[consumer], Node::Aggregate { .. });
+            let all_identical = edges.iter().all(|e| e.weight() == edges[0].weight());
+            if !(is_aggregate && all_identical) {
+                errors.push(ValidationError::DuplicateQueue {
+                    queue,
+                    edges: edges
+                        .iter()
+                        .map(|e| (g[e.source()].name(), g[e.target()].name()))
+                        .collect(),
+                });
+            }
+        }
+    }
+}
+
+/// The message type of every edge must be the output type of the node producing it.
+fn check_edge_types(g: &PetGraph, errors: &mut Vec<ValidationError>) {
+    for edge in g.edge_references() {
+        let producer = &g[edge.source()];
+        let type_output = match producer {
+            // a fan out node sends its input as is
+            Node::FanOut { .. } => g
+                .edges_directed(edge.source(), Direction::Incoming)
+                .next()
+                .map(|e| e.weight().msg_type.clone()),
+            _ => producer.type_output(),
+        };
+
+        if let Some(type_output) = type_output {
+            if type_output != edge.weight().msg_type {
+                errors.push(ValidationError::MismatchedEdgeType {
+                    queue: edge.weight().queue.clone(),
+                    producer: producer.name(),
+                    expected: type_output,
+                    found: edge.weight().msg_type.clone(),
+                });
+            }
+        }
+    }
+}
+
+/// Check the number of inputs and outputs of each node.
+fn check_arity(g: &PetGraph, errors: &mut Vec<ValidationError>) {
+    for node_i in g.node_indices() {
+        let node = &g[node_i];
+        let name = node.name();
+
+        let input_queues: BTreeSet<_> = g
+            .edges_directed(node_i, Direction::Incoming)
+            .map(|e| e.weight().queue.clone())
+            .collect();
+        let output_queues: Vec<_> = g
+            .edges_directed(node_i, Direction::Outgoing)
+            .map(|e| e.weight().queue.clone())
+            .collect();
+
+        let expect_one_input = match node {
+            Node::Start { .. } => false,
+            Node::Terminate { .. }
+            | Node::Aggregate { .. }
+            | Node::FanOut { .. }
+            | Node::UserHandler { .. }
+            | Node::Poll { .. } => true,
+        };
+        let expected_inputs = if expect_one_input { 1 } else { 0 };
+        if input_queues.len() != expected_inputs {
+            errors.push(ValidationError::UnexpectedInputs {
+                node: name.clone(),
+                expected: expected_inputs,
+                found: input_queues.len(),
+            });
+        }
+
+        match node {
+            Node::Start { .. } => {
+                if output_queues.is_empty() {
+                    errors.push(ValidationError::StartWithoutOutput { start: name });
+                } else if output_queues.len() > 1 {
+                    errors.push(ValidationError::TooManyOutputs {
+                        node: name,
+                        queues: output_queues,
+                    });
+                }
+            }
+            Node::Aggregate { .. } | Node::UserHandler { .. } | Node::Poll { .. } => {
+                if output_queues.len() > 1 {
+                    errors.push(ValidationError::TooManyOutputs {
+                        node: name,
+                        queues: output_queues,
+                    });
+                }
+            }
+            Node::Terminate { .. } => {
+                if !output_queues.is_empty() {
+                    errors.push(ValidationError::TooManyOutputs {
+                        node: name,
+                        queues: output_queues,
+                    });
+                }
+            }
+            // fan out can have arbitrary many outputs
+            Node::FanOut { .. } => (),
+        }
+    }
+}
+
+fn check_reachability(g: &PetGraph, errors: &mut Vec<ValidationError>) {
+    let mut reachable = HashSet::new();
+    for node_i in g.node_indices() {
+        if let Node::Start { .. } = g[node_i] {
+            let mut dfs = Dfs::new(g, node_i);
+            while let Some(i) = dfs.next(g) {
+                reachable.insert(i);
+            }
+        }
+    }
+
+    for node_i in g.node_indices() {
+        if !reachable.contains(&node_i) {
+            errors.push(ValidationError::UnreachableNode {
+                node: g[node_i].name(),
+            });
+        }
+    }
+}
+
+fn is_rust_identifier(s: &str) -> bool {
+    let mut chars = s.chars();
+    let valid_chars = match chars.next() {
+        None => false,
+        Some(first) => {
+            (first.is_ascii_alphabetic() || first == '_')
+                && chars.all(|c| c.is_ascii_alphanumeric() || c == '_')
+        }
+    };
+    valid_chars && s != "_" && !RUST_KEYWORDS.contains(&s)
+}
+
+fn is_rust_path(s: &str) -> bool {
+    let s = s.strip_prefix("::").unwrap_or(s);
+    s.split("::").enumerate().all(|(i, segment)| {
+        // `crate`, `self` and `super` are only allowed at the beginning of a path
+        let is_path_root = i == 0 && ["crate", "self", "super"].contains(&segment);
+        is_path_root || is_rust_identifier(segment)
+    })
+}
+
+fn check_node_options(g: &PetGraph, errors: &mut Vec<ValidationError>) {
+    for node in g.node_weights() {
+        let options = match node.node_options() {
+            None => continue,
+            Some(options) => options,
+        };
+        if options.worker_threads == Some(0) {
+            errors.push(ValidationError::InvalidNodeOptions {
+                node: node.name(),
+                reason: String::from("worker_threads must be greater than 0"),
+            });
+        }
+        if options.max_in_flight == Some(0) {
+            errors.push(ValidationError::InvalidNodeOptions {
+                node: node.name(),
+                reason: String::from("max_in_flight must be greater than 0"),
+            });
+        }
+    }
+}
+
+fn check_retry_policies(g: &PetGraph, errors: &mut Vec<ValidationError>) {
+    let edges: BTreeMap<_, _> = g.edge_weights().map(|edge| (&edge.queue, edge)).collect();
+    for (queue, edge) in edges {
+        let backoff = &edge.retry_backoff;
+        if backoff.tiers_in_seconds.contains(&0) {
+            errors.push(ValidationError::InvalidRetryPolicy {
+                queue: queue.clone(),
+                reason: String::from("the delays of the tiers must be greater than 0"),
+            });
+        }
+        if backoff.jitter_percent > 100 {
+            errors.push(ValidationError::InvalidRetryPolicy {
+                queue: queue.clone(),
+                reason: String::from("jitter_percent must be at most 100"),
+            });
+        }
+        if edge.max_attempts == Some(0) {
+            errors.push(ValidationError::InvalidRetryPolicy {
+                queue: queue.clone(),
+                reason: String::from("max_attempts must be greater than 0"),
+            });
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::diagram::DiagramBackend;
+    use crate::graph::NodeOptions;
+    use crate::graph::RetryBackoff;
+
+    /// `start --queues[0]--> step_1 --queues[1]--> ... --queues[n - 1]--> terminate`
+    fn pipeline(queues: &[&str]) -> Graph {
+        let mut graph = Graph::new("app::accept_failure", "app::Error");
+        let mut node = graph.start("start");
+        let (last, queues) = queues.split_last().unwrap();
+        for (i, &queue) in queues.iter().enumerate() {
+            node = graph.process(
+                node,
+                queue,
+                "app::Integer",
+                format!("step_{}", i + 1).as_str(),
+                "app::step",
+                10,
+                "app::Integer",
+            );
+        }
+        graph.terminate(node, *last, "app::Integer", "terminate", 10);
+        graph
+    }
+
+    fn options(retry_queue_prefix: &str, retry_queue_suffix: &str) -> GenerateOptions {
+        GenerateOptions {
+            get_rmq_uri: String::from("app::rmq_uri"),
+            work_exchange: String::from("work"),
+            retry_exchange: String::from("retry"),
+            retry_queue_prefix: String::from(retry_queue_prefix),
+            retry_queue_suffix: String::from(retry_queue_suffix),
+            init_input_queue: true,
+            init_output_queue: true,
+            main_init: String::from("app::init"),
+            diagram: DiagramBackend::default(),
+        }
+    }
+
+    #[test]
+    fn valid_pipeline() {
+        let graph = pipeline(&["input", "middle", "output"]);
+        assert_eq!(validate(&graph, Some(&options("retry_", ""))), vec![]);
+    }
+
+    #[test]
+    fn node_names_must_be_identifiers_and_not_reserved() {
+        let mut graph = Graph::new("app::accept_failure", "app::Error");
+        let start = graph.start("start");
+        let main = graph.process(
+            start,
+            "input",
+            "app::Integer",
+            "main",
+            "app::step",
+            10,
+            "app::Integer",
+        );
+        let keyword = graph.process(
+            main,
+            "middle",
+            "app::Integer",
+            "fn",
+            "app::step",
+            10,
+            "app::Integer",
+        );
+        graph.terminate(keyword, "output", "app::Integer", "terminate", 10);
+
+        assert_eq!(
+            validate(&graph, None),
+            vec![
+                ValidationError::ReservedNodeName {
+                    name: String::from("main")
+                },
+                ValidationError::InvalidNodeName {
+                    name: String::from("fn")
+                },
+            ]
+        );
+    }
+
+    #[test]
+    fn edge_type_must_be_the_output_of_its_producer() {
+        let mut graph = Graph::new("app::accept_failure", "app::Error");
+        let start = graph.start("start");
+        let to_text = graph.process(
+            start,
+            "input",
+            "app::Integer",
+            "to_text",
+            "app::to_text",
+            10,
+            "app::Text",
+        );
+        graph.terminate(to_text, "output", "app::Integer", "terminate", 10);
+
+        assert_eq!(
+            validate(&graph, None),
+            vec![ValidationError::MismatchedEdgeType {
+                queue: String::from("output"),
+                producer: String::from("to_text"),
+                expected: String::from("app::Text"),
+                found: String::from("app::Integer"),
+            }]
+        );
+    }
+
+    #[test]
+    fn queue_must_have_one_consumer() {
+        let mut graph = Graph::new("app::accept_failure", "app::Error");
+        let start = graph.start("start");
+        let a = graph.process(
+            start,
+            "input",
+            "app::Integer",
+            "a",
+            "app::a",
+            10,
+            "app::Integer",
+        );
+        let b = graph.process(
+            start,
+            "input",
+            "app::Integer",
+            "b",
+            "app::b",
+            10,
+            "app::Integer",
+        );
+        graph.terminate(a, "output_a", "app::Integer", "terminate_a", 10);
+        graph.terminate(b, "output_b", "app::Integer", "terminate_b", 10);
+
+        let errors = validate(&graph, None);
+        assert!(
+            errors.contains(&ValidationError::QueueWithMultipleConsumers {
+                queue: String::from("input"),
+                consumers: vec![String::from("a"), String::from("b")],
+            })
+        );
+    }
+
+    #[test]
+    fn retry_queues_must_not_be_named_as_work_queues() {
+        let graph = pipeline(&["input", "output"]);
+        let collision = |queue: &str| ValidationError::QueueNameCollision {
+            queue: String::from(queue),
+            uses: vec![
+                String::from("work queue"),
+                format!("retry queue of {}", queue),
+            ],
+        };
+
+        // without a prefix or a suffix, the retry queues are the work queues
+        assert_eq!(
+            validate(&graph, Some(&options("", ""))),
+            vec![collision("input"), collision("output")]
+        );
+        // the names of the retry queues are only known with the options
+        assert_eq!(validate(&graph, None), vec![]);
+    }
+
+    #[test]
+    fn tier_and_parking_queues_must_not_be_named_as_work_queues() {
+        let mut graph = pipeline(&["input", "retry_input_1", "input_parked"]);
+        graph
+            .set_retry_backoff("input", RetryBackoff::new(vec![30]))
+            .unwrap();
+        graph.set_max_attempts("input", 5).unwrap();
+
+        assert_eq!(
+            validate(&graph, Some(&options("retry_", ""))),
+            vec![
+                ValidationError::QueueNameCollision {
+                    queue: String::from("input_parked"),
+                    uses: vec![
+                        String::from("parking queue of input"),
+                        String::from("work queue")
+                    ],
+                },
+                ValidationError::QueueNameCollision {
+                    queue: String::from("retry_input_1"),
+                    uses: vec![
+                        String::from("retry queue of tier 1 of input"),
+                        String::from("work queue"),
+                    ],
+                },
+            ]
+        );
+    }
+
+    #[test]
+    fn derived_queue_names_must_fit_in_255_bytes() {
+        let queue = "q".repeat(250);
+        let mut graph = pipeline(&[&queue, "output"]);
+        graph.set_max_attempts(&queue, 5).unwrap();
+
+        assert_eq!(
+            validate(&graph, Some(&options("retry_", ""))),
+            vec![
+                ValidationError::InvalidQueueName {
+                    queue: format!("retry_{}", queue),
+                    reason: format!(
+                        "it is longer than 255 bytes, it is the retry queue of {}",
+                        queue
+                    ),
+                },
+                ValidationError::InvalidQueueName {
+                    queue: format!("{}_parked", queue),
+                    reason: format!(
+                        "it is longer than 255 bytes, it is the parking queue of {}",
+                        queue
+                    ),
+                },
+            ]
+        );
+    }
+
+    #[test]
+    fn retry_policy_and_node_options_must_be_positive() {
+        let mut graph = pipeline(&["input", "output"]);
+        graph
+            .set_retry_backoff("input", RetryBackoff::new(vec![30, 0]).jitter_percent(101))
+            .unwrap();
+        graph.set_max_attempts("input", 0).unwrap();
+        let step = graph
+            .g
+            .node_indices()
+            .find(|i| graph.g[*i].name() == "step_1")
+            .unwrap();
+        graph
+            .set_node_options(step, NodeOptions::new().worker_threads(0))
+            .unwrap();
+
+        let invalid_retry_policy = |reason: &str| ValidationError::InvalidRetryPolicy {
+            queue: String::from("input"),
+            reason: String::from(reason),
+        };
+        assert_eq!(
+            validate(&graph, None),
+            vec![
+                ValidationError::InvalidNodeOptions {
+                    node: String::from("step_1"),
+                    reason: String::from("worker_threads must be greater than 0"),
+                },
+                invalid_retry_policy("the delays of the tiers must be greater than 0"),
+                invalid_retry_policy("jitter_percent must be at most 100"),
+                invalid_retry_policy("max_attempts must be greater than 0"),
+            ]
+        );
+    }
+}
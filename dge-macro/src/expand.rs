@@ -0,0 +1,193 @@
+use proc_macro2::Ident;
+use proc_macro2::Span;
+use proc_macro2::TokenStream;
+use quote::quote;
+use quote::ToTokens;
+
+use crate::parse::GraphDef;
+use crate::parse::NodeDef;
+use crate::parse::NodeKind;
+
+pub(crate) fn expand(graph_def: &GraphDef) -> TokenStream {
+    // not visible to the code inside the macro, so a node can be named `graph`
+    let graph = Ident::new("graph", Span::mixed_site());
+
+    let accept_failure = &graph_def.accept_failure;
+    let type_error = &graph_def.type_error;
+    let accept_failure_str = to_string(accept_failure);
+    let type_error_str = to_string(type_error);
+
+    let mut checks = vec![
+        quote! { let _ = #accept_failure; },
+        check_type(type_error),
+    ];
+    let mut nodes = Vec::new();
+    for node in graph_def.nodes.iter() {
+        checks.extend(check_node(node));
+        nodes.push(expand_node(&graph, node));
+    }
+
+    quote! {
+        {
+            // these do nothing, they only ask the compiler to check that
+            // the paths and types in the graph exist
+            {
+                #(#checks)*
+            }
+
+            let mut #graph = ::dge_gen::Graph::new(#accept_failure_str, #type_error_str);
+            #(#nodes)*
+            #graph
+        }
+    }
+}
+
+fn expand_node(graph: &Ident, node: &NodeDef) -> TokenStream {
+    let name = &node.name;
+    let name_str = name.to_string();
+    let inputs = &node.inputs;
+    let queue = &node.queue;
+    let type_input = node.type_input.as_ref().map(to_string);
+    let type_output = node.type_output.as_ref().map(to_string);
+    let behaviour_module = node
+        .behaviour_module
+        .as_ref()
+        .map(to_string)
+        .or_else(|| node.behaviour.as_ref().map(to_string));
+    let retry = node.retry_interval_in_seconds;
+
+    let call = match node.kind {
+        NodeKind::Start => quote! { #graph.start(#name_str) },
+        NodeKind::FanOut => quote! {
+            #graph.fan_out(#(#inputs)*, #queue, #type_input, #name_str, #retry)
+        },
+        NodeKind::Process => quote! {
+            #graph.process(#(#inputs)*, #queue, #type_input, #name_str, #behaviour_module, #retry, #type_output)
+        },
+        NodeKind::Aggregate => quote! {
+            #graph.aggregate(vec![#(#inputs),*], #queue, #type_input, #name_str, #behaviour_module, #retry, #type_output)
+        },
+        NodeKind::Poll => quote! {
+            #graph.poll(#(#inputs)*, #queue, #type_input, #name_str, #behaviour_module, #retry, #type_output)
+        },
+        NodeKind::Terminate => quote! {
+            #graph.terminate(#(#inputs)*, #queue, #type_input, #name_str, #retry)
+        },
+    };
+
+    let behaviour_kind = node.behaviour.as_ref().map(|_| {
+        quote! {
+            #graph
+                .set_behaviour_kind(#name, ::dge_gen::BehaviourKind::Type)
+                .expect("the node has a behaviour");
+        }
+    });
+
+    let retry_backoff = retry_backoff(node).map(|backoff| {
+        quote! {
+            #graph
+                .set_retry_backoff(#queue, #backoff)
+                .expect("the node has an input queue");
+        }
+    });
+
+    let node_options = node_options(node).map(|options| {
+        quote! {
+            #graph
+                .set_node_options(#name, #options)
+                .expect("the node runs as a subcommand");
+        }
+    });
+
+    let max_attempts = node.max_attempts.map(|n| {
+        quote! {
+            #graph
+                .set_max_attempts(#queue, #n)
+                .expect("the node has an input queue");
+        }
+    });
+
+    quote! {
+        #[allow(unused_variables)]
+        let #name = #call;
+        #behaviour_kind
+        #node_options
+        #retry_backoff
+        #max_attempts
+    }
+}
+
+fn retry_backoff(node: &NodeDef) -> Option<TokenStream> {
+    if node.retry_backoff_in_seconds.is_none() && node.retry_jitter_percent.is_none() {
+        return None;
+    }
+    let tiers = node.retry_backoff_in_seconds.clone().unwrap_or_default();
+    let mut backoff = quote! { ::dge_gen::RetryBackoff::new(vec![#(#tiers),*]) };
+    if let Some(percent) = node.retry_jitter_percent {
+        backoff = quote! { #backoff.jitter_percent(#percent) };
+    }
+    Some(backoff)
+}
+
+fn node_options(node: &NodeDef) -> Option<TokenStream> {
+    if node.prefetch_count.is_none() && node.worker_threads.is_none() && node.max_in_flight.is_none() {
+        return None;
+    }
+    let mut options = quote! { ::dge_gen::NodeOptions::new() };
+    if let Some(n) = node.prefetch_count {
+        options = quote! { #options.prefetch_count(#n) };
+    }
+    if let Some(n) = node.worker_threads {
+        options = quote! { #options.worker_threads(#n) };
+    }
+    if let Some(n) = node.max_in_flight {
+        options = quote! { #options.max_in_flight(#n) };
+    }
+    Some(options)
+}
+
+fn check_node(node: &NodeDef) -> Vec<TokenStream> {
+    let mut checks = Vec::new();
+    if let Some(type_input) = &node.type_input {
+        checks.push(check_type(type_input));
+    }
+    if let Some(type_output) = &node.type_output {
+        checks.push(check_type(type_output));
+    }
+    if let Some(behaviour_module) = &node.behaviour_module {
+        // the function the generated code calls for each message
+        let f = match node.kind {
+            NodeKind::Process => quote! { handle },
+            NodeKind::Aggregate => quote! { aggregate },
+            NodeKind::Poll => quote! { check },
+            _ => unreachable!("only these kinds of node have a behaviour module"),
+        };
+        checks.push(quote! { let _ = #behaviour_module::#f; });
+    }
+    if let Some(behaviour) = &node.behaviour {
+        checks.push(check_type(behaviour));
+    }
+    checks
+}
+
+fn check_type<T: ToTokens>(ty: &T) -> TokenStream {
+    quote! { let _ = ::core::marker::PhantomData::<#ty>; }
+}
+
+/// Turn a path or a type into the string form used by `Graph`,
+/// i.e. without the spaces `to_string` puts between the tokens.
+fn to_string<T: ToTokens>(tokens: &T) -> String {
+    let spaced = tokens.to_token_stream().to_string();
+    let chars: Vec<char> = spaced.chars().collect();
+    let is_word = |c: Option<&char>| c.is_some_and(|c| c.is_alphanumeric() || *c == '_');
+
+    let mut s = String::with_capacity(chars.len());
+    for (i, c) in chars.iter().enumerate() {
+        // keep the spaces separating words, e.g. `dyn Trait`, `&'a mut T`
+        if *c == ' ' && !(is_word(s.chars().last().as_ref()) && is_word(chars.get(i + 1))) {
+            continue;
+        }
+        s.push(*c);
+    }
+    s
+}
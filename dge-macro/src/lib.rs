@@ -0,0 +1,66 @@
+//! The `dge_graph!` macro, use it via `dge_gen::dge_graph`.
+
+use proc_macro::TokenStream;
+use syn::parse_macro_input;
+
+mod expand;
+mod parse;
+
+/// Declare a computational graph with real Rust paths and types.
+///
+/// The macro evaluates to a `dge_gen::Graph`, the same one built by calling
+/// `Graph::start`, `Graph::process` etc. by hand,
+/// but the paths and types are checked by the compiler where the graph is declared,
+/// instead of when the generated code is compiled:
+///
+/// ```ignore
+/// let graph = dge_gen::dge_graph! {
+///     accept_failure = dge_example::behaviour::accept_failure::accept_failure;
+///     type_error = dge_example::behaviour::error::Error;
+///
+///     let start = start();
+///     let double = process(
+///         start,
+///         queue = "input",
+///         type_input = dge_example::behaviour::data::Integer,
+///         type_output = dge_example::behaviour::data::Integer,
+///         behaviour_module = dge_example::behaviour::double,
+///         retry_interval_in_seconds = 11,
+///     );
+///     let terminate = terminate(
+///         double,
+///         queue = "result",
+///         type_input = dge_example::behaviour::data::Integer,
+///         retry_interval_in_seconds = 1,
+///     );
+/// };
+/// ```
+///
+/// Each `let` declares a node, named after the binding,
+/// and the binding is the input of the nodes declared after it.
+/// The node kinds and their arguments are:
+///
+/// - `start()`
+/// - `fan_out(input, queue, type_input, retry_interval_in_seconds)`
+/// - `process(input, queue, type_input, type_output, behaviour_module, retry_interval_in_seconds)`
+/// - `aggregate([inputs], queue, type_input, type_output, behaviour_module, retry_interval_in_seconds)`
+/// - `poll(input, queue, type_input, type_output, behaviour_module, retry_interval_in_seconds)`
+/// - `terminate(input, queue, type_input, retry_interval_in_seconds)`
+///
+/// Instead of `behaviour_module`, `process`, `aggregate` and `poll` accept `behaviour`,
+/// a type implementing `dge_runtime::Handler`, `dge_runtime::Aggregator` or `dge_runtime::Poller`
+/// respectively, see `dge_gen::BehaviourKind`.
+///
+/// `fan_out`, `process`, `aggregate` and `poll` also accept the optional
+/// `prefetch_count`, `worker_threads` and `max_in_flight`, see `dge_gen::NodeOptions`,
+/// and `retry_backoff_in_seconds = [10, 60, 600]` and `retry_jitter_percent`
+/// for their input queue, see `dge_gen::RetryBackoff`,
+/// and `max_attempts`, see `Graph::set_max_attempts`.
+///
+/// The paths and types end up in the generated code verbatim,
+/// so they should be absolute (i.e. start with the name of the crate defining them).
+#[proc_macro]
+pub fn dge_graph(input: TokenStream) -> TokenStream {
+    let graph = parse_macro_input!(input as parse::GraphDef);
+    expand::expand(&graph).into()
+}
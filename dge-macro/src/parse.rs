@@ -0,0 +1,346 @@
+use proc_macro2::Span;
+use syn::bracketed;
+use syn::parenthesized;
+use syn::parse::Parse;
+use syn::parse::ParseStream;
+use syn::punctuated::Punctuated;
+use syn::Ident;
+use syn::LitInt;
+use syn::LitStr;
+use syn::Path;
+use syn::Token;
+use syn::Type;
+
+/// The whole input of `dge_graph!`.
+pub(crate) struct GraphDef {
+    pub accept_failure: Path,
+    pub type_error: Type,
+    pub nodes: Vec<NodeDef>,
+}
+
+/// `let name = kind(inputs, key = value, ...);`
+pub(crate) struct NodeDef {
+    pub name: Ident,
+    pub kind: NodeKind,
+    pub inputs: Vec<Ident>,
+    pub queue: Option<LitStr>,
+    pub type_input: Option<Type>,
+    pub type_output: Option<Type>,
+    pub behaviour_module: Option<Path>,
+    /// a type implementing the trait of the node kind, instead of `behaviour_module`
+    pub behaviour: Option<Type>,
+    pub retry_interval_in_seconds: Option<u32>,
+    pub prefetch_count: Option<u16>,
+    pub worker_threads: Option<usize>,
+    pub max_in_flight: Option<usize>,
+    /// the tiers of `dge_gen::RetryBackoff`
+    pub retry_backoff_in_seconds: Option<Vec<u32>>,
+    pub retry_jitter_percent: Option<u8>,
+    pub max_attempts: Option<u32>,
+}
+
+#[derive(Clone, Copy, PartialEq, Eq)]
+pub(crate) enum NodeKind {
+    Start,
+    FanOut,
+    Process,
+    Aggregate,
+    Poll,
+    Terminate,
+}
+
+impl NodeKind {
+    fn from_ident(ident: &Ident) -> syn::Result<NodeKind> {
+        match ident.to_string().as_str() {
+            "start" => Ok(NodeKind::Start),
+            "fan_out" => Ok(NodeKind::FanOut),
+            "process" => Ok(NodeKind::Process),
+            "aggregate" => Ok(NodeKind::Aggregate),
+            "poll" => Ok(NodeKind::Poll),
+            "terminate" => Ok(NodeKind::Terminate),
+            _ => Err(syn::Error::new(
+                ident.span(),
+                "unknown node kind, expecting one of: start, fan_out, process, aggregate, poll, terminate",
+            )),
+        }
+    }
+
+    /// The keys the node kind requires.
+    fn required_keys(self) -> &'static [&'static str] {
+        match self {
+            NodeKind::Start => &[],
+            NodeKind::FanOut | NodeKind::Terminate => {
+                &["queue", "type_input", "retry_interval_in_seconds"]
+            }
+            NodeKind::Process | NodeKind::Aggregate | NodeKind::Poll => &[
+                "queue",
+                "type_input",
+                "type_output",
+                "behaviour_module",
+                "retry_interval_in_seconds",
+            ],
+        }
+    }
+
+    /// The keys the node kind accepts but does not require,
+    /// i.e. `behaviour` (which stands in for `behaviour_module`), the runtime options of the node
+    /// and the retry backoff and maximum attempts of its input queue.
+    fn optional_keys(self) -> &'static [&'static str] {
+        match self {
+            NodeKind::Start | NodeKind::Terminate => &[],
+            NodeKind::FanOut => &[
+                "prefetch_count",
+                "worker_threads",
+                "max_in_flight",
+                "retry_backoff_in_seconds",
+                "retry_jitter_percent",
+                "max_attempts",
+            ],
+            NodeKind::Process | NodeKind::Aggregate | NodeKind::Poll => &[
+                "behaviour",
+                "prefetch_count",
+                "worker_threads",
+                "max_in_flight",
+                "retry_backoff_in_seconds",
+                "retry_jitter_percent",
+                "max_attempts",
+            ],
+        }
+    }
+
+    fn accepts(self, key: &str) -> bool {
+        self.required_keys().contains(&key) || self.optional_keys().contains(&key)
+    }
+}
+
+impl Parse for GraphDef {
+    fn parse(input: ParseStream) -> syn::Result<Self> {
+        let mut accept_failure = None;
+        let mut type_error = None;
+        let mut nodes = Vec::new();
+
+        while !input.is_empty() {
+            if input.peek(Token![let]) {
+                nodes.push(input.parse()?);
+                continue;
+            }
+
+            let key: Ident = input.parse()?;
+            input.parse::<Token![=]>()?;
+            match key.to_string().as_str() {
+                "accept_failure" if accept_failure.is_none() => {
+                    accept_failure = Some(input.parse()?);
+                }
+                "type_error" if type_error.is_none() => {
+                    type_error = Some(input.parse()?);
+                }
+                "accept_failure" | "type_error" => {
+                    return Err(syn::Error::new(key.span(), "duplicated key"));
+                }
+                _ => {
+                    return Err(syn::Error::new(
+                        key.span(),
+                        "expecting `accept_failure = ...;`, `type_error = ...;` or `let node = ...;`",
+                    ))
+                }
+            }
+            input.parse::<Token![;]>()?;
+        }
+
+        let accept_failure = accept_failure.ok_or_else(|| {
+            syn::Error::new(Span::call_site(), "missing `accept_failure = ...;`")
+        })?;
+        let type_error = type_error
+            .ok_or_else(|| syn::Error::new(Span::call_site(), "missing `type_error = ...;`"))?;
+
+        Ok(GraphDef {
+            accept_failure,
+            type_error,
+            nodes,
+        })
+    }
+}
+
+impl Parse for NodeDef {
+    fn parse(input: ParseStream) -> syn::Result<Self> {
+        input.parse::<Token![let]>()?;
+        let name: Ident = input.parse()?;
+        input.parse::<Token![=]>()?;
+        let kind_ident: Ident = input.parse()?;
+        let kind = NodeKind::from_ident(&kind_ident)?;
+
+        let content;
+        parenthesized!(content in input);
+        input.parse::<Token![;]>()?;
+
+        let mut node = NodeDef {
+            name,
+            kind,
+            inputs: Vec::new(),
+            queue: None,
+            type_input: None,
+            type_output: None,
+            behaviour_module: None,
+            behaviour: None,
+            retry_interval_in_seconds: None,
+            prefetch_count: None,
+            worker_threads: None,
+            max_in_flight: None,
+            retry_backoff_in_seconds: None,
+            retry_jitter_percent: None,
+            max_attempts: None,
+        };
+
+        // the input node(s) come first
+        match kind {
+            NodeKind::Start => (),
+            NodeKind::Aggregate => {
+                let inputs;
+                bracketed!(inputs in content);
+                let inputs: Punctuated<Ident, Token![,]> =
+                    inputs.parse_terminated(Ident::parse)?;
+                node.inputs = inputs.into_iter().collect();
+                if node.inputs.is_empty() {
+                    return Err(syn::Error::new(
+                        kind_ident.span(),
+                        "aggregate needs at least one input",
+                    ));
+                }
+            }
+            _ => node.inputs.push(content.parse()?),
+        }
+
+        // then the keyed arguments
+        while !content.is_empty() {
+            if kind != NodeKind::Start {
+                content.parse::<Token![,]>()?;
+                if content.is_empty() {
+                    break;
+                }
+            }
+
+            let key: Ident = content.parse()?;
+            let key_name = key.to_string();
+            if !kind.accepts(&key_name) {
+                let accepted: Vec<_> = kind
+                    .required_keys()
+                    .iter()
+                    .chain(kind.optional_keys())
+                    .copied()
+                    .collect();
+                return Err(syn::Error::new(
+                    key.span(),
+                    format!("unexpected key, `{}` accepts: {}", kind_ident, accepted.join(", ")),
+                ));
+            }
+            content.parse::<Token![=]>()?;
+
+            let duplicated = match key_name.as_str() {
+                "queue" => node.queue.replace(content.parse()?).is_some(),
+                "type_input" => node.type_input.replace(content.parse()?).is_some(),
+                "type_output" => node.type_output.replace(content.parse()?).is_some(),
+                "behaviour_module" => node.behaviour_module.replace(content.parse()?).is_some(),
+                "behaviour" => node.behaviour.replace(content.parse()?).is_some(),
+                "retry_interval_in_seconds" => {
+                    let retry: LitInt = content.parse()?;
+                    node.retry_interval_in_seconds
+                        .replace(retry.base10_parse()?)
+                        .is_some()
+                }
+                "prefetch_count" => {
+                    let n: LitInt = content.parse()?;
+                    node.prefetch_count.replace(n.base10_parse()?).is_some()
+                }
+                "worker_threads" => {
+                    let n = parse_positive(&content)?;
+                    node.worker_threads.replace(n).is_some()
+                }
+                "max_in_flight" => {
+                    let n = parse_positive(&content)?;
+                    node.max_in_flight.replace(n).is_some()
+                }
+                "retry_backoff_in_seconds" => {
+                    let tiers;
+                    bracketed!(tiers in content);
+                    let tiers: Punctuated<LitInt, Token![,]> =
+                        tiers.parse_terminated(|input| input.parse())?;
+                    let tiers = tiers
+                        .iter()
+                        .map(|tier| match tier.base10_parse()? {
+                            0 => Err(syn::Error::new(tier.span(), "must be greater than 0")),
+                            delay => Ok(delay),
+                        })
+                        .collect::<syn::Result<Vec<u32>>>()?;
+                    node.retry_backoff_in_seconds.replace(tiers).is_some()
+                }
+                "retry_jitter_percent" => {
+                    let n: LitInt = content.parse()?;
+                    let percent = n.base10_parse()?;
+                    if percent > 100 {
+                        return Err(syn::Error::new(n.span(), "must be at most 100"));
+                    }
+                    node.retry_jitter_percent.replace(percent).is_some()
+                }
+                "max_attempts" => {
+                    let n = parse_positive(&content)?;
+                    node.max_attempts.replace(n).is_some()
+                }
+                _ => unreachable!("keys are checked above"),
+            };
+            if duplicated {
+                return Err(syn::Error::new(key.span(), "duplicated key"));
+            }
+            if node.behaviour_module.is_some() && node.behaviour.is_some() {
+                return Err(syn::Error::new(
+                    key.span(),
+                    "`behaviour_module` and `behaviour` cannot be given together",
+                ));
+            }
+        }
+
+        let missing: Vec<_> = kind
+            .required_keys()
+            .iter()
+            .filter(|key| !node.has_key(key))
+            .map(|key| match *key {
+                "behaviour_module" => "behaviour_module (or behaviour)",
+                key => key,
+            })
+            .collect();
+        if !missing.is_empty() {
+            return Err(syn::Error::new(
+                kind_ident.span(),
+                format!("missing key(s): {}", missing.join(", ")),
+            ));
+        }
+
+        Ok(node)
+    }
+}
+
+impl NodeDef {
+    fn has_key(&self, key: &str) -> bool {
+        match key {
+            "queue" => self.queue.is_some(),
+            "type_input" => self.type_input.is_some(),
+            "type_output" => self.type_output.is_some(),
+            "behaviour_module" => self.behaviour_module.is_some() || self.behaviour.is_some(),
+            "retry_interval_in_seconds" => self.retry_interval_in_seconds.is_some(),
+            _ => false,
+        }
+    }
+}
+
+fn parse_positive<N>(input: ParseStream) -> syn::Result<N>
+where
+    N: std::str::FromStr + PartialEq + From<u8>,
+    N::Err: std::fmt::Display,
+{
+    let n: LitInt = input.parse()?;
+    let value: N = n.base10_parse()?;
+    if value == N::from(0) {
+        Err(syn::Error::new(n.span(), "must be greater than 0"))
+    } else {
+        Ok(value)
+    }
+}
@@ -0,0 +1,90 @@
+//! The contracts between the generated code and the user-provided behaviour of the nodes.
+//!
+//! The generated code of a node calls its behaviour through one of these traits,
+//! so a behaviour not matching the graph is reported as an unsatisfied trait bound,
+//! naming the mismatched associated type.
+//!
+//! A behaviour can be a type implementing the trait, e.g.:
+//!
+//! ```ignore
+//! pub struct Double;
+//!
+//! #[dge_runtime::async_trait]
+//! impl dge_runtime::Handler for Double {
+//!     type Input = Integer;
+//!     type Output = Integer;
+//!     type State = ();
+//!     type Error = Error;
+//!
+//!     async fn init() -> Self::State {}
+//!
+//!     async fn handle(_state: Self::State, msg: &Integer) -> Result<Integer, Error> {
+//!         Ok(Integer { integer: msg.integer * 2 })
+//!     }
+//! }
+//! ```
+//!
+//! or a module defining the same items as free functions and a `State` type alias,
+//! the generated code then implements the trait by forwarding to the module.
+
+use async_trait::async_trait;
+
+use crate::component::aggregate::AggregationStatus;
+use crate::component::poll::Capacity;
+
+/// Transforms each input message into an output message.
+#[async_trait]
+pub trait Handler {
+    type Input: Send + Sync;
+    type Output: Send;
+    /// Shared by all the messages handled by an instance of the node, it is cloned for each message.
+    type State: Clone + Send + 'static;
+    /// Failures are handed over to the `accept_failure` function of the graph.
+    type Error: Send;
+
+    /// Create the state, called once when the node starts.
+    async fn init() -> Self::State;
+
+    async fn handle(state: Self::State, msg: &Self::Input) -> Result<Self::Output, Self::Error>;
+}
+
+/// Merges multiple input messages (usually of the same run) into one output message.
+#[async_trait]
+pub trait Aggregator {
+    type Input: Send + Sync;
+    type Output: Send;
+    /// Shared by all the messages handled by an instance of the node, it is cloned for each message,
+    /// so the messages seen so far are usually kept behind an `Arc<Mutex<...>>`.
+    type State: Clone + Send + 'static;
+    /// The message is retried when an error is returned.
+    type Error: Send;
+
+    /// Create the state, called once when the node starts.
+    async fn init() -> Self::State;
+
+    /// Return `AggregationStatus::Aggregated` once all the messages to merge have arrived,
+    /// and `AggregationStatus::Ignore` before that.
+    async fn aggregate(
+        state: Self::State,
+        msg: &Self::Input,
+    ) -> Result<AggregationStatus<Self::Output>, Self::Error>;
+}
+
+/// Checks some external system repeatedly, until the job described by the input message is done.
+#[async_trait]
+pub trait Poller {
+    type Input: Send;
+    type Output: Send;
+    type Error: Send;
+
+    /// The jobs to resume when the node starts, e.g. the ones saved by `save_msg` and not done yet.
+    async fn init() -> Vec<Self::Input>;
+
+    /// Called for each new job before it is acknowledged, the message is retried on error.
+    async fn save_msg(msg: Self::Input) -> Result<(), Self::Error>;
+
+    fn get_capacity() -> Capacity;
+
+    /// Return `Ok(None)` while the job is not done, it will be checked again later.
+    async fn check(msg: Self::Input) -> Result<Option<Self::Output>, Self::Error>;
+}
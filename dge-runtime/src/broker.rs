@@ -0,0 +1,388 @@
+//! The connections of this process to RabbitMQ.
+//!
+//! A `Broker` keeps two long-lived connections, shared by its clones, i.e. by all the nodes
+//! running in the process: the consumers are on one, and the messages are published on the other,
+//! so RabbitMQ blocking the publishers (e.g. on a memory alarm) does not hold back the acks.
+//! The channels publishing the outputs of the handlers are pooled, see `Broker::publish_channel`.
+//!
+//! A lost connection is re-established by the next channel created on it.
+//! The attempts failing in a row are spaced by delays doubling from `--reconnect-delay-ms`
+//! up to `--max-reconnect-delay-seconds`, each shortened by a random amount up to half of it,
+//! so the instances of a graph do not all reconnect at the same time.
+//! The consumers attach themselves again once their connection is back,
+//! see `dge_runtime::rmq::consume_forever`.
+
+use lapin::Channel;
+use lapin::Connection;
+use lapin::ConnectionProperties;
+use log::info;
+use log::warn;
+use rand::Rng;
+use std::future::Future;
+use std::ops::Deref;
+use std::sync::Arc;
+use std::time::Duration;
+use structopt::StructOpt;
+use tokio::sync::Mutex;
+use tokio::time::Instant;
+use tokio_amqp::*;
+
+use crate::rmq_primitive::close_channel;
+use crate::rmq_primitive::constant::RMQ_CONFIRM_SELECT_OPTIONS;
+use crate::shutdown;
+use crate::Result;
+
+/// How the connections to RabbitMQ are managed.
+#[derive(Clone, Debug, StructOpt)]
+pub struct ConnectionOptions {
+    /// The delay before reconnecting after a failed attempt to connect to RabbitMQ,
+    /// doubled after each attempt failing in a row
+    #[structopt(long, default_value = "500")]
+    pub reconnect_delay_ms: u64,
+
+    /// The longest delay between two attempts to connect to RabbitMQ
+    #[structopt(long, default_value = "30")]
+    pub max_reconnect_delay_seconds: u64,
+
+    /// The number of idle publishing channels kept open for reuse
+    #[structopt(long, default_value = "16")]
+    pub max_idle_channels: usize,
+}
+
+impl Default for ConnectionOptions {
+    fn default() -> Self {
+        ConnectionOptions {
+            reconnect_delay_ms: 500,
+            max_reconnect_delay_seconds: 30,
+            max_idle_channels: 16,
+        }
+    }
+}
+
+/// The delays between the attempts failing in a row, doubling up to a maximum, with jitter.
+#[derive(Clone, Debug)]
+pub(crate) struct Backoff {
+    first: Duration,
+    max: Duration,
+    failures: u32,
+}
+
+impl Backoff {
+    pub(crate) fn new(options: &ConnectionOptions) -> Backoff {
+        Backoff {
+            first: Duration::from_millis(options.reconnect_delay_ms),
+            max: Duration::from_secs(options.max_reconnect_delay_seconds),
+            failures: 0,
+        }
+    }
+
+    /// The delay before the next attempt, after one more failure.
+    pub(crate) fn next(&mut self) -> Duration {
+        let delay = self
+            .first
+            .checked_mul(2u32.saturating_pow(self.failures))
+            .unwrap_or(self.max)
+            .min(self.max);
+        self.failures = self.failures.saturating_add(1);
+        let max_jitter = delay.as_millis() as u64 / 2;
+        delay - Duration::from_millis(rand::thread_rng().gen_range(0..=max_jitter))
+    }
+
+    /// The last attempt succeeded.
+    pub(crate) fn reset(&mut self) {
+        self.failures = 0;
+    }
+}
+
+/// Creates the channels to the RabbitMQ server at `rmq_uri`, on the connections it manages.
+///
+/// The clones of a broker share its connections and its pool of channels.
+#[derive(Clone)]
+pub struct Broker {
+    inner: Arc<Inner>,
+}
+
+struct Inner {
+    rmq_uri: String,
+    options: ConnectionOptions,
+    consuming: Managed,
+    publishing: Managed,
+    /// The idle channels of the publishing connection, in confirm mode.
+    idle_channels: std::sync::Mutex<Vec<Channel>>,
+}
+
+/// A connection re-established on demand.
+struct Managed {
+    name: &'static str,
+    state: Mutex<ManagedState>,
+}
+
+struct ManagedState {
+    connection: Option<Connection>,
+    backoff: Backoff,
+    /// When the next attempt to connect can be made, after the last one failed.
+    retry_at: Option<Instant>,
+}
+
+impl Managed {
+    fn new(name: &'static str, options: &ConnectionOptions) -> Managed {
+        Managed {
+            name,
+            state: Mutex::new(ManagedState {
+                connection: None,
+                backoff: Backoff::new(options),
+                retry_at: None,
+            }),
+        }
+    }
+
+    /// A new channel in confirm mode, connecting first if the connection is lost.
+    async fn create_channel(&self, rmq_uri: &str) -> Result<Channel> {
+        let mut state = self.state.lock().await;
+        let connected = match &state.connection {
+            Some(connection) => connection.status().connected(),
+            None => false,
+        };
+        if !connected {
+            if let Some(retry_at) = state.retry_at {
+                // not waiting any longer once shutting down, the attempt is the last one anyway
+                tokio::select! {
+                    _ = tokio::time::sleep_until(retry_at) => (),
+                    _ = shutdown::requested() => (),
+                }
+            }
+            info!("creating {} connection", self.name);
+            match Connection::connect(rmq_uri, ConnectionProperties::default().with_tokio()).await
+            {
+                Ok(connection) => {
+                    let name = self.name;
+                    connection.on_error(move |e| warn!("{} connection is lost: {}", name, e));
+                    state.backoff.reset();
+                    state.retry_at = None;
+                    state.connection = Some(connection);
+                }
+                Err(e) => {
+                    let delay = state.backoff.next();
+                    warn!(
+                        "failed to create {} connection, will retry in {} ms: {}",
+                        self.name,
+                        delay.as_millis(),
+                        e
+                    );
+                    state.retry_at = Some(Instant::now() + delay);
+                    return Err(e.into());
+                }
+            }
+        }
+
+        let channel = state
+            .connection
+            .as_ref()
+            .expect("the connection is created above")
+            .create_channel()
+            .await?;
+        channel.confirm_select(RMQ_CONFIRM_SELECT_OPTIONS).await?;
+        Ok(channel)
+    }
+
+    async fn close(&self) -> Result<()> {
+        if let Some(connection) = self.state.lock().await.connection.take() {
+            if connection.status().connected() {
+                info!("closing {} connection", self.name);
+                connection
+                    .close(
+                        lapin::protocol::constants::REPLY_SUCCESS as u16,
+                        "shutting down",
+                    )
+                    .await?;
+            }
+        }
+        Ok(())
+    }
+}
+
+impl Broker {
+    pub fn new<S: Into<String>>(rmq_uri: S, options: ConnectionOptions) -> Broker {
+        Broker {
+            inner: Arc::new(Inner {
+                rmq_uri: rmq_uri.into(),
+                consuming: Managed::new("consuming", &options),
+                publishing: Managed::new("publishing", &options),
+                idle_channels: std::sync::Mutex::new(Vec::new()),
+                options,
+            }),
+        }
+    }
+
+    pub fn rmq_uri(&self) -> &str {
+        &self.inner.rmq_uri
+    }
+
+    pub fn options(&self) -> &ConnectionOptions {
+        &self.inner.options
+    }
+
+    /// A new channel in confirm mode on the consuming connection, for a consumer,
+    /// which acks, rejects, and publishes the rejected messages to the retry queues on it.
+    pub async fn consume_channel(&self) -> Result<Channel> {
+        self.inner.consuming.create_channel(&self.inner.rmq_uri).await
+    }
+
+    /// A channel in confirm mode taken from the pool of the publishing connection,
+    /// given back to the pool once dropped.
+    pub async fn publish_channel(&self) -> Result<PooledChannel> {
+        let idle = {
+            let mut idle_channels = self.inner.idle_channels.lock().unwrap();
+            // the channels of a lost connection are closed, and dropped here
+            std::iter::from_fn(|| idle_channels.pop()).find(|channel| channel.status().connected())
+        };
+        let channel = match idle {
+            Some(channel) => channel,
+            None => self.create_channel().await?,
+        };
+        Ok(PooledChannel {
+            channel,
+            broker: self.clone(),
+        })
+    }
+
+    /// A new channel in confirm mode on the publishing connection, not pooled,
+    /// for declaring, moving or deleting queues, which may close the channel on an error.
+    pub async fn create_channel(&self) -> Result<Channel> {
+        self.inner.publishing.create_channel(&self.inner.rmq_uri).await
+    }
+
+    /// Run `f` with a new channel, as given by `create_channel`,
+    /// and close the channel once `f` is done, whether it succeeds or not.
+    pub async fn with_channel<T, F, Fut>(&self, f: F) -> Result<T>
+    where
+        F: FnOnce(Channel) -> Fut,
+        Fut: Future<Output = Result<T>>,
+    {
+        let channel = self.create_channel().await?;
+        let result = f(channel.clone()).await;
+        if let Err(e) = close_channel(&channel).await {
+            warn!("failed to close channel: {}", e);
+        }
+        result
+    }
+
+    /// Close the pooled channels and the connections,
+    /// they are opened again if a channel is created after this.
+    pub async fn close(&self) -> Result<()> {
+        let idle_channels = std::mem::take(&mut *self.inner.idle_channels.lock().unwrap());
+        for channel in idle_channels {
+            if channel.status().connected() {
+                let _ = channel
+                    .close(
+                        lapin::protocol::constants::REPLY_SUCCESS as u16,
+                        "shutting down",
+                    )
+                    .await;
+            }
+        }
+        self.inner.consuming.close().await?;
+        self.inner.publishing.close().await
+    }
+
+    fn give_back(&self, channel: Channel) {
+        if !channel.status().connected() {
+            return;
+        }
+        let mut idle_channels = self.inner.idle_channels.lock().unwrap();
+        if idle_channels.len() < self.inner.options.max_idle_channels {
+            idle_channels.push(channel);
+        } else {
+            drop(idle_channels);
+            // the frame is sent right away, no need to wait for the reply
+            drop(channel.close(
+                lapin::protocol::constants::REPLY_SUCCESS as u16,
+                "too many idle channels",
+            ));
+        }
+    }
+}
+
+/// A channel taken from the pool of a `Broker`, see `Broker::publish_channel`.
+pub struct PooledChannel {
+    channel: Channel,
+    broker: Broker,
+}
+
+impl PooledChannel {
+    /// The channel, e.g. to be given to `rmq_primitive::publish`,
+    /// it should not be used once this is dropped.
+    pub fn channel(&self) -> Channel {
+        self.channel.clone()
+    }
+}
+
+impl Deref for PooledChannel {
+    type Target = Channel;
+
+    fn deref(&self) -> &Channel {
+        &self.channel
+    }
+}
+
+impl Drop for PooledChannel {
+    fn drop(&mut self) {
+        self.broker.give_back(self.channel.clone());
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn backoff(reconnect_delay_ms: u64, max_reconnect_delay_seconds: u64) -> Backoff {
+        Backoff::new(&ConnectionOptions {
+            reconnect_delay_ms,
+            max_reconnect_delay_seconds,
+            ..ConnectionOptions::default()
+        })
+    }
+
+    /// Whether `delay` is `expected` shortened by half of it at most.
+    fn jittered(delay: Duration, expected: Duration) -> bool {
+        delay <= expected && delay >= expected / 2
+    }
+
+    #[test]
+    fn delays_double_up_to_the_max() {
+        let mut backoff = backoff(500, 3);
+        for expected_ms in [500, 1000, 2000, 3000, 3000].iter() {
+            let delay = backoff.next();
+            let expected = Duration::from_millis(*expected_ms);
+            assert!(jittered(delay, expected), "{:?} for {:?}", delay, expected);
+        }
+    }
+
+    #[test]
+    fn delays_stay_at_the_max_after_many_failures() {
+        let mut backoff = backoff(500, 30);
+        for _ in 0..100 {
+            backoff.next();
+        }
+        let delay = backoff.next();
+        assert!(jittered(delay, Duration::from_secs(30)), "{:?}", delay);
+    }
+
+    #[test]
+    fn reset_starts_over_from_the_first_delay() {
+        let mut backoff = backoff(500, 30);
+        for _ in 0..5 {
+            backoff.next();
+        }
+        backoff.reset();
+        let delay = backoff.next();
+        assert!(jittered(delay, Duration::from_millis(500)), "{:?}", delay);
+    }
+
+    #[test]
+    fn zero_delay_stays_zero() {
+        let mut backoff = backoff(0, 0);
+        assert_eq!(backoff.next(), Duration::from_millis(0));
+        assert_eq!(backoff.next(), Duration::from_millis(0));
+    }
+}
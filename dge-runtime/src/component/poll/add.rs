@@ -14,6 +14,7 @@ use tokio::sync::RwLock;
 use tokio::sync::Semaphore;
 use tokio::sync::SemaphorePermit;
 
+use crate::envelope::MessageContext;
 use crate::error::Result;
 use crate::rmq_primitive;
 
@@ -25,6 +26,7 @@ pub fn new_job<InputMsg>(msg: InputMsg) -> Arc<RwLock<Job<InputMsg>>> {
         done: false,
         ticket: Arc::new(Semaphore::new(1)),
         msg,
+        context: MessageContext::current(),
     }))
 }
 
@@ -36,6 +38,12 @@ macro_rules! add_to_jobs {
     save_msg=$save_msg:path
     $(,)?
 ) => {{
+    if $crate::shutdown::is_requested() {
+        // no more jobs are checked, so it is left to another instance,
+        // requeued as it is, this is not a failed attempt
+        debug!("shutting down, requeuing msg {:?}", &$msg);
+        return Ok(rmq_primitive::Responsibility::Requeue);
+    }
     debug!("adding job for msg {:?} to the job queue", &$msg);
     match $save_msg($msg.clone()).await {
         Err(user_error) => {
@@ -14,20 +14,21 @@ use tokio::sync::RwLock;
 use tokio::sync::Semaphore;
 use tokio::sync::SemaphorePermit;
 
+use crate::metrics;
 use crate::rmq_primitive;
+use crate::shutdown;
 use super::data::*;
 
 
 pub async fn poll_forever<InputMsg, OutputMsg, UserError, Context, CheckResult, AcceptFailureResult>(
+    node: &'static str,
     capacity: Capacity,
     jobs: Jobs<InputMsg>,
 
     // these are used when do the actual checking
     check: fn(InputMsg) -> CheckResult,
     accept_failure: fn(Context, UserError) -> AcceptFailureResult,
-    get_rmq_uri: fn() -> String,
-    work_exchange: &'static str,
-    output_queue: Option<&'static str>,
+    output: Output,
 )
     where
         InputMsg: Clone + Debug + Send + Sync + 'static,
@@ -40,29 +41,43 @@ pub async fn poll_forever<InputMsg, OutputMsg, UserError, Context, CheckResult,
     // the maximum number of running jobs
     let slots = Arc::new(Semaphore::new(capacity.max_running_jobs as usize));
 
-    loop {
+    while !shutdown::is_requested() {
         debug!(
             "there are {} available slots when this pass started",
             slots.available_permits()
         );
 
         let sleep_time = sweep_once(
+            node,
             capacity.clone(),
             jobs.clone(),
             slots.clone(),
             check,
             accept_failure,
-            get_rmq_uri,
-            work_exchange,
-            output_queue,
+            output.clone(),
         ).await;
 
         debug!("this pass is done, sleeping for {} seconds before the next sleep", sleep_time);
-        tokio::time::sleep(std::time::Duration::from_secs(sleep_time as u64)).await;
+        tokio::select! {
+            _ = tokio::time::sleep(std::time::Duration::from_secs(sleep_time as u64)) => (),
+            _ = shutdown::requested() => (),
+        }
     }
+
+    // no more checks are dispatched, wait for the running ones to be done
+    info!(
+        "stopped dispatching checks, waiting for the {} running ones",
+        capacity.max_running_jobs as usize - slots.available_permits()
+    );
+    let _all_slots = slots
+        .acquire_many(capacity.max_running_jobs)
+        .await
+        .expect("the semaphore is never closed");
+    info!("stopped polling");
 }
 
 async fn sweep_once<InputMsg, OutputMsg, UserError, Context, CheckResult, AcceptFailureResult>(
+    node: &'static str,
     capacity: Capacity,
     jobs: Jobs<InputMsg>,
     slots: Arc<Semaphore>,
@@ -70,9 +85,7 @@ async fn sweep_once<InputMsg, OutputMsg, UserError, Context, CheckResult, Accept
     // these are used when do the actual checking
     check: fn(InputMsg) -> CheckResult,
     accept_failure: fn(Context, UserError) -> AcceptFailureResult,
-    get_rmq_uri: fn() -> String,
-    work_exchange: &'static str,
-    output_queue: Option<&'static str>,
+    output: Output,
 ) -> u32
     where
         InputMsg: Clone + Debug + Send + Sync + 'static,
@@ -123,15 +136,13 @@ async fn sweep_once<InputMsg, OutputMsg, UserError, Context, CheckResult, Accept
                         sleep_time,
                         check,
                         accept_failure,
-                        get_rmq_uri,
-                        work_exchange,
-                        output_queue,
+                        output.clone(),
                     ).await;
                     sleep_time = new_sleep_time;
 
                     match job_status {
                         // the job is done, discard it by not pushing it back
-                        JobStatus::JobDone => (),
+                        JobStatus::JobDone => metrics::poll_job_done(node),
 
                         // the job is still in progress, put it back for later examination
                         JobStatus::AlreadyRunning
@@ -143,6 +154,14 @@ async fn sweep_once<InputMsg, OutputMsg, UserError, Context, CheckResult, Accept
         }
     }
 
+    let available_slots = slots.available_permits();
+    metrics::poll_pass(
+        node,
+        jobs.len(),
+        (capacity.max_running_jobs as usize).saturating_sub(available_slots),
+        available_slots,
+    );
+
     sleep_time
 }
 
@@ -162,9 +181,7 @@ async fn schedule_one<InputMsg, OutputMsg, UserError, Context, CheckResult, Acce
     // these are used when do the actual checking
     check: fn(InputMsg) -> CheckResult,
     accept_failure: fn(Context, UserError) -> AcceptFailureResult,
-    get_rmq_uri: fn() -> String,
-    work_exchange: &'static str,
-    output_queue: Option<&'static str>,
+    output: Output,
 ) -> (JobStatus, u32)
     where
         InputMsg: Clone + Debug + Send + Sync + 'static,
@@ -196,7 +213,7 @@ async fn schedule_one<InputMsg, OutputMsg, UserError, Context, CheckResult, Acce
                     job.last_scheduled = now;
                     tokio::spawn(do_check(
                         job_clone, slot, ticket,
-                        check, accept_failure, get_rmq_uri, work_exchange, output_queue
+                        check, accept_failure, output
                     ));
                     (JobStatus::Dispatched, sleep_time)
                 } else {
@@ -270,9 +287,7 @@ async fn do_check<InputMsg, OutputMsg, UserError, Context, CheckResult, AcceptFa
     // these are used when do the actual checking
     check: fn(InputMsg) -> CheckResult,
     accept_failure: fn(Context, UserError) -> AcceptFailureResult,
-    get_rmq_uri: fn() -> String,
-    work_exchange: &'static str,
-    output_queue: Option<&'static str>,
+    output: Output,
 )
     where
         InputMsg: Clone + Debug + Send + Sync + 'static,
@@ -282,7 +297,10 @@ async fn do_check<InputMsg, OutputMsg, UserError, Context, CheckResult, AcceptFa
         CheckResult: Future<Output=Result<Option<OutputMsg>, UserError>> + Send + 'static,
         AcceptFailureResult: Future<Output=Result<(), UserError>> + Send + 'static,
 {
-    let msg = job.read().await.msg.clone();
+    let (msg, context) = {
+        let job = job.read().await;
+        (job.msg.clone(), job.context.clone())
+    };
 
     // run the check
     let check_result = check(msg.clone()).await;
@@ -308,7 +326,7 @@ async fn do_check<InputMsg, OutputMsg, UserError, Context, CheckResult, AcceptFa
             // as guarded by the ticket)
             let mut write_job = job.write().await;
             if !write_job.done {
-                match output_queue {
+                match output.queue {
                     None => (),
                     Some(output_queue) => {
                         debug!("serializing output");
@@ -321,9 +339,8 @@ async fn do_check<InputMsg, OutputMsg, UserError, Context, CheckResult, AcceptFa
                             accept_failure = accept_failure,
                             input_msg = msg_clone,
                         );
-                        let rmq_uri = get_rmq_uri();
                         debug!("sending output to queue {}", output_queue);
-                        let channel = match rmq_primitive::create_channel(rmq_uri).await {
+                        let channel = match output.broker.publish_channel().await {
                             Err(e) => {
                                 // return to leave the job status unchanged
                                 warn!("failed to create RabbitMQ channel, error is {:?}", e);
@@ -331,12 +348,18 @@ async fn do_check<InputMsg, OutputMsg, UserError, Context, CheckResult, AcceptFa
                             }
                             Ok(channel) => channel
                         };
-                        let () = match rmq_primitive::publish(
-                            channel,
-                            work_exchange,
+                        let publishing = rmq_primitive::publish(
+                            channel.channel(),
+                            output.work_exchange,
                             output_queue,
                             output_msg_vec,
-                        ).await {
+                        );
+                        // in the context of the input message, so the output is in the same run
+                        let published = match context {
+                            Some(context) => context.scope(publishing).await,
+                            None => publishing.await,
+                        };
+                        let () = match published {
                             Err(e) => {
                                 // return to leave the job status unchanged
                                 warn!("failed to publish message, error is {:?}", e);
@@ -8,6 +8,9 @@ use tokio::sync::RwLock;
 use tokio::sync::Semaphore;
 use tokio::sync::SemaphorePermit;
 
+use crate::broker::Broker;
+use crate::envelope::MessageContext;
+
 
 pub struct Job<T> {
     pub last_scheduled: i64,
@@ -17,12 +20,25 @@ pub struct Job<T> {
 
     // the initiating message
     pub msg: T,
+    // the context the job is added in, the output is published in it,
+    // none for the jobs loaded when the node starts
+    pub context: Option<MessageContext>,
 }
 
 
 pub type Jobs<T> = Arc<RwLock<VecDeque<Arc<RwLock<Job<T>>>>>>;
 
 
+// where the outputs of the done jobs are published
+#[derive(Clone)]
+pub struct Output {
+    pub broker: Broker,
+    pub work_exchange: &'static str,
+    // none if the node has no output
+    pub queue: Option<&'static str>,
+}
+
+
 #[derive(Clone)]
 pub struct Capacity {
     pub max_running_jobs: u32,
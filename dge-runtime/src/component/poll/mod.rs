@@ -5,5 +5,6 @@ mod add;
 pub use data::Capacity;
 pub use data::Job;
 pub use data::Jobs;
+pub use data::Output;
 pub use check::poll_forever;
 pub use add::new_job;
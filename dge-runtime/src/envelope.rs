@@ -0,0 +1,186 @@
+//! The envelope of the messages: the AMQP headers telling which run a message belongs to,
+//! and where in the run it comes from.
+//!
+//! A run starts with a message published to an input queue of the graph, by anything outside it,
+//! and every message published by the nodes while handling it (or its descendants) belongs to it.
+//! The runtime sets the envelope on every message it publishes, as a child of the envelope of the
+//! message being handled, which is available to the behaviours via `MessageContext::current`.
+//!
+//! A message without an envelope starts a new run, named after its AMQP `message_id` if it has one,
+//! so that its retries stay in the same run.
+
+use chrono::Utc;
+use lapin::types::AMQPValue;
+use lapin::types::FieldTable;
+use lapin::types::LongLongInt;
+use lapin::types::LongString;
+use lapin::types::ShortString;
+use lapin::BasicProperties;
+use std::convert::TryFrom;
+use std::future::Future;
+
+use crate::telemetry::TraceContext;
+
+/// The run the message belongs to.
+pub const RUN_ID_HEADER: &str = "x-dge-run-id";
+
+/// The message handled when this message is published, absent for the first message of a run.
+pub const PARENT_ID_HEADER: &str = "x-dge-parent-id";
+
+/// The node publishing the message, absent for the first message of a run.
+pub const NODE_HEADER: &str = "x-dge-node";
+
+/// The number of nodes the run went through before this message, 0 for the first message of a run.
+pub const HOPS_HEADER: &str = "x-dge-hops";
+
+/// When the message is published (RFC 3339).
+pub const CREATED_AT_HEADER: &str = "x-dge-created-at";
+
+/// Where a message comes from, read from and written to its AMQP properties.
+///
+/// The id of the message is its AMQP `message_id`, the rest are in the headers above.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Envelope {
+    pub run_id: String,
+    pub message_id: String,
+    pub parent_id: Option<String>,
+    pub node: Option<String>,
+    pub hops: u32,
+    pub created_at: String,
+}
+
+impl Envelope {
+    /// The envelope of the first message of a new run.
+    pub fn new_run() -> Envelope {
+        let message_id = new_id();
+        Envelope {
+            run_id: message_id.clone(),
+            message_id,
+            parent_id: None,
+            node: None,
+            hops: 0,
+            created_at: Utc::now().to_rfc3339(),
+        }
+    }
+
+    /// The envelope of a delivered message, a message without one starts a new run.
+    pub fn of(properties: &BasicProperties) -> Envelope {
+        let headers = properties.headers().as_ref().map(FieldTable::inner);
+        let header = |name: &str| match headers.and_then(|headers| headers.get(name)) {
+            Some(AMQPValue::LongString(value)) => Some(value.to_string()),
+            _ => None,
+        };
+        let message_id = properties.message_id().as_ref().map(|id| id.to_string());
+
+        let run_id = match header(RUN_ID_HEADER) {
+            Some(run_id) => run_id,
+            None => {
+                let mut envelope = Envelope::new_run();
+                if let Some(message_id) = message_id {
+                    envelope.run_id = message_id.clone();
+                    envelope.message_id = message_id;
+                }
+                return envelope;
+            }
+        };
+        let hops = match headers.and_then(|headers| headers.get(HOPS_HEADER)) {
+            Some(AMQPValue::LongLongInt(hops)) => u32::try_from(*hops).unwrap_or(0),
+            _ => 0,
+        };
+        Envelope {
+            run_id,
+            message_id: message_id.unwrap_or_else(new_id),
+            parent_id: header(PARENT_ID_HEADER),
+            node: header(NODE_HEADER),
+            hops,
+            created_at: header(CREATED_AT_HEADER).unwrap_or_default(),
+        }
+    }
+
+    /// The envelope of a message published by `node` while handling the message of this envelope.
+    pub fn child(&self, node: &str) -> Envelope {
+        Envelope {
+            run_id: self.run_id.clone(),
+            message_id: new_id(),
+            parent_id: Some(self.message_id.clone()),
+            node: Some(String::from(node)),
+            hops: self.hops.saturating_add(1),
+            created_at: Utc::now().to_rfc3339(),
+        }
+    }
+
+    /// `properties` with the envelope set, the other headers are kept.
+    pub fn apply(&self, properties: BasicProperties) -> BasicProperties {
+        let mut headers = properties.headers().clone().unwrap_or_default();
+        let mut insert = |name: &str, value: AMQPValue| headers.insert(ShortString::from(name), value);
+        let string = |value: &str| AMQPValue::LongString(LongString::from(value));
+
+        insert(RUN_ID_HEADER, string(&self.run_id));
+        if let Some(parent_id) = &self.parent_id {
+            insert(PARENT_ID_HEADER, string(parent_id));
+        }
+        if let Some(node) = &self.node {
+            insert(NODE_HEADER, string(node));
+        }
+        insert(HOPS_HEADER, AMQPValue::LongLongInt(LongLongInt::from(self.hops)));
+        insert(CREATED_AT_HEADER, string(&self.created_at));
+
+        properties
+            .with_message_id(ShortString::from(self.message_id.as_str()))
+            .with_headers(headers)
+    }
+}
+
+tokio::task_local! {
+    static CONTEXT: MessageContext;
+}
+
+/// The message being handled by a node, available to the behaviours while they handle it.
+///
+/// ```ignore
+/// pub async fn handle(_state: State, msg: &Integer) -> Result<Integer, Error> {
+///     let context = dge_runtime::MessageContext::current().expect("called by dge_runtime");
+///     info!("handling a message of run {}", context.envelope.run_id);
+///     // ...
+/// }
+/// ```
+#[derive(Clone, Debug)]
+pub struct MessageContext {
+    /// The node handling the message.
+    pub node: &'static str,
+    /// The envelope of the message being handled.
+    pub envelope: Envelope,
+    /// The span handling the message, see `dge_runtime::telemetry`.
+    pub(crate) trace: TraceContext,
+}
+
+impl MessageContext {
+    /// The context of the message being handled by the current task,
+    /// `None` outside of the handling of a message, e.g. in `init`.
+    pub fn current() -> Option<MessageContext> {
+        CONTEXT.try_with(MessageContext::clone).ok()
+    }
+
+    /// Run `future` with this as the current context, in the span handling the message.
+    pub async fn scope<F: Future>(self, future: F) -> F::Output {
+        let trace = self.trace.clone();
+        CONTEXT.scope(self, trace.scope(future)).await
+    }
+
+    /// The envelope of a message published while handling this message.
+    pub fn outgoing(&self) -> Envelope {
+        self.envelope.child(self.node)
+    }
+}
+
+/// The envelope of a message about to be published by the current task.
+pub(crate) fn outgoing() -> Envelope {
+    MessageContext::current()
+        .map(|context| context.outgoing())
+        .unwrap_or_else(Envelope::new_run)
+}
+
+/// A random 128-bit id, in hex.
+fn new_id() -> String {
+    format!("{:032x}", rand::random::<u128>())
+}
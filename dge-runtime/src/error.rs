@@ -1,4 +1,5 @@
 use lapin;
+use std::net::SocketAddr;
 use thiserror;
 
 pub type Result<T> = std::result::Result<T, Error>;
@@ -12,6 +13,49 @@ pub enum Error {
     #[error(transparent)]
     RabbitMQError(#[from] lapin::Error),
 
+    #[error(transparent)]
+    IoError(#[from] std::io::Error),
+
+    #[error(transparent)]
+    JsonError(#[from] serde_json::Error),
+
+    #[error("Unknown node {}, the nodes are: {}", .node, .known)]
+    UnknownNode { node: String, known: String },
+
+    #[error("Queue {} has no parking queue, the queues with one are: {}", .queue, .known)]
+    UnknownParkingQueue { queue: String, known: String },
+
+    #[error("Namespace {:?} is not valid: {}", .namespace, .reason)]
+    InvalidNamespace { namespace: String, reason: String },
+
+    #[error("Failed to serve HTTP at {}: {}", .addr, .error)]
+    FailedToServeHttp { addr: SocketAddr, error: String },
+
+    #[error("Failed to initialize OpenTelemetry: {}", .0)]
+    FailedToInitTelemetry(String),
+
+    #[error("A node panicked: {}", .0)]
+    NodePanicked(String),
+
+    #[error(
+        "The migration plan is of format version {}, only versions up to {} are supported",
+        .format_version, .supported
+    )]
+    UnsupportedMigrationPlan { format_version: u32, supported: u32 },
+
+    #[error("Queue {} still has {} messages, it is not drained in time", .queue, .messages)]
+    QueueNotDrained { queue: String, messages: u32 },
+
+    #[error(
+        "{} messages of queue {} are parked in {}, replay or purge them before deleting it",
+        .messages, .queue, .parking_queue
+    )]
+    MessagesParked {
+        queue: String,
+        parking_queue: String,
+        messages: u32,
+    },
+
     // errors returned by user functions
     #[error("User error: {}", .error)]
     UserError { error: String },
@@ -0,0 +1,155 @@
+//! The health of the nodes running in this process,
+//! served at `/health/live` and `/health/ready` when the node is given `--http-addr`,
+//! see `dge_runtime::http`.
+//!
+//! Each instance of a node reports whether its behaviour is initialized,
+//! whether its consumer is attached to its queue, and when a message was last delivered to it.
+//! The process is ready when every instance is initialized and attached,
+//! and live unless an instance has been detached for longer than `--max-detached-seconds`,
+//! or, given `--max-idle-seconds`, has had no message delivered for longer than that.
+//! An instance still initializing is live, however long its `init` takes.
+
+use once_cell::sync::Lazy;
+use serde::Serialize;
+use std::sync::Arc;
+use std::sync::Mutex;
+use std::time::Duration;
+use std::time::Instant;
+
+static INSTANCES: Lazy<Mutex<Vec<NodeInstance>>> = Lazy::new(|| Mutex::new(Vec::new()));
+
+/// An instance of a node running in this process, reporting its health.
+#[derive(Clone, Debug)]
+pub struct NodeInstance {
+    node: &'static str,
+    state: Arc<Mutex<State>>,
+}
+
+#[derive(Debug)]
+struct State {
+    initialized: bool,
+    attached: bool,
+    /// When `attached` last changed, or the instance started.
+    since: Instant,
+    last_delivery: Option<Instant>,
+}
+
+/// The health of a node instance, as reported by the endpoints.
+#[derive(Clone, Debug, Serialize)]
+pub struct InstanceHealth {
+    pub node: &'static str,
+    pub initialized: bool,
+    pub attached: bool,
+    /// How long the consumer has been attached or detached, in seconds.
+    pub seconds_since_change: u64,
+    pub seconds_since_last_delivery: Option<u64>,
+    /// Why the instance is not live, if it is not.
+    pub not_live: Option<String>,
+}
+
+/// The health of the process, as reported by the endpoints.
+#[derive(Clone, Debug, Serialize)]
+pub struct Health {
+    pub live: bool,
+    pub ready: bool,
+    pub instances: Vec<InstanceHealth>,
+}
+
+impl NodeInstance {
+    /// Start reporting the health of an instance of `node`, before its behaviour is initialized.
+    pub fn start(node: &'static str) -> NodeInstance {
+        let instance = NodeInstance {
+            node,
+            state: Arc::new(Mutex::new(State {
+                initialized: false,
+                attached: false,
+                since: Instant::now(),
+                last_delivery: None,
+            })),
+        };
+        INSTANCES.lock().unwrap().push(instance.clone());
+        instance
+    }
+
+    pub fn node(&self) -> &'static str {
+        self.node
+    }
+
+    /// The behaviour is initialized, the consumer is about to be attached.
+    pub(crate) fn initialized(&self) {
+        let mut state = self.state.lock().unwrap();
+        if !state.initialized {
+            state.initialized = true;
+            state.since = Instant::now();
+        }
+    }
+
+    pub(crate) fn attached(&self, attached: bool) {
+        let mut state = self.state.lock().unwrap();
+        if state.attached != attached {
+            state.attached = attached;
+            state.since = Instant::now();
+        }
+    }
+
+    pub(crate) fn is_attached(&self) -> bool {
+        self.state.lock().unwrap().attached
+    }
+
+    pub(crate) fn delivered(&self) {
+        self.state.lock().unwrap().last_delivery = Some(Instant::now());
+    }
+
+    fn health(&self, max_detached: Duration, max_idle: Option<Duration>) -> InstanceHealth {
+        let state = self.state.lock().unwrap();
+        let since_change = state.since.elapsed();
+        let since_last_delivery = state.last_delivery.map(|at| at.elapsed());
+
+        let not_live = if !state.initialized {
+            None
+        } else if !state.attached && since_change > max_detached {
+            Some(format!(
+                "the consumer has been detached for {} seconds",
+                since_change.as_secs()
+            ))
+        } else {
+            // idle since the last delivery, or since the consumer is attached if none
+            let idle = since_last_delivery.map_or(since_change, |since| since.min(since_change));
+            match max_idle {
+                Some(max_idle) if state.attached && idle > max_idle => Some(format!(
+                    "no message has been delivered for {} seconds",
+                    idle.as_secs()
+                )),
+                _ => None,
+            }
+        };
+
+        InstanceHealth {
+            node: self.node,
+            initialized: state.initialized,
+            attached: state.attached,
+            seconds_since_change: since_change.as_secs(),
+            seconds_since_last_delivery: since_last_delivery.map(|since| since.as_secs()),
+            not_live,
+        }
+    }
+}
+
+/// The health of the node instances of this process,
+/// which is not ready before the first instance starts.
+pub fn health(max_detached: Duration, max_idle: Option<Duration>) -> Health {
+    let instances: Vec<InstanceHealth> = INSTANCES
+        .lock()
+        .unwrap()
+        .iter()
+        .map(|instance| instance.health(max_detached, max_idle))
+        .collect();
+    Health {
+        live: instances.iter().all(|instance| instance.not_live.is_none()),
+        ready: !instances.is_empty()
+            && instances
+                .iter()
+                .all(|instance| instance.initialized && instance.attached),
+        instances,
+    }
+}
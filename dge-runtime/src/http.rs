@@ -0,0 +1,134 @@
+//! The HTTP endpoint of a node process, enabled by `--http-addr`,
+//! serving the metrics at `/metrics`, see `dge_runtime::metrics`,
+//! and the health at `/health/live` and `/health/ready`, see `dge_runtime::health`.
+//!
+//! The health endpoints respond 200 when the process is live (or ready), 503 otherwise,
+//! with the health of each node instance as JSON.
+
+use futures::Future;
+use hyper::service::make_service_fn;
+use hyper::service::service_fn;
+use hyper::Body;
+use hyper::Method;
+use hyper::Request;
+use hyper::Response;
+use hyper::Server;
+use hyper::StatusCode;
+use log::info;
+use std::convert::Infallible;
+use std::net::SocketAddr;
+use std::time::Duration;
+use structopt::StructOpt;
+
+use crate::health;
+use crate::metrics;
+use crate::Error;
+use crate::Result;
+
+/// The HTTP endpoint of the process.
+#[derive(Clone, Debug, StructOpt)]
+pub struct HttpOptions {
+    /// Serve the metrics and the health of the nodes at this address, e.g. 0.0.0.0:9100
+    #[structopt(long)]
+    pub http_addr: Option<SocketAddr>,
+
+    /// Report the process as not live when a consumer has been detached from RabbitMQ for this long
+    #[structopt(long, default_value = "60")]
+    pub max_detached_seconds: u64,
+
+    /// Report the process as not live when no message has been delivered to a node for this long,
+    /// for queues which never stay empty that long
+    #[structopt(long)]
+    pub max_idle_seconds: Option<u64>,
+}
+
+impl Default for HttpOptions {
+    fn default() -> Self {
+        HttpOptions {
+            http_addr: None,
+            max_detached_seconds: 60,
+            max_idle_seconds: None,
+        }
+    }
+}
+
+/// Run `future`, serving the HTTP endpoint at `options.http_addr` (if given) until it completes.
+///
+/// If the endpoint stops serving, `future` is stopped with the error.
+pub async fn serving<F>(options: HttpOptions, future: F) -> Result<()>
+where
+    F: Future<Output = Result<()>>,
+{
+    let http_addr = match options.http_addr {
+        None => return future.await,
+        Some(http_addr) => http_addr,
+    };
+
+    let failed = |e: hyper::Error| Error::FailedToServeHttp {
+        addr: http_addr,
+        error: e.to_string(),
+    };
+    let server = Server::try_bind(&http_addr)
+        .map_err(failed)?
+        .serve(make_service_fn(move |_| {
+            let options = options.clone();
+            async move {
+                Ok::<_, Infallible>(service_fn(move |request| respond(options.clone(), request)))
+            }
+        }));
+    info!(
+        "serving the metrics at http://{0}/metrics, and the health at http://{0}/health/live and /health/ready",
+        http_addr
+    );
+
+    tokio::select! {
+        result = future => result,
+        served = server => served.map_err(failed),
+    }
+}
+
+async fn respond(
+    options: HttpOptions,
+    request: Request<Body>,
+) -> std::result::Result<Response<Body>, Infallible> {
+    let health = || {
+        health::health(
+            Duration::from_secs(options.max_detached_seconds),
+            options.max_idle_seconds.map(Duration::from_secs),
+        )
+    };
+    let response = match (request.method(), request.uri().path()) {
+        (&Method::GET, "/metrics") => Response::builder()
+            .header("Content-Type", "text/plain; version=0.0.4")
+            .body(Body::from(metrics::gather())),
+        (&Method::GET, "/health/live") => {
+            let health = health();
+            health_response(health.live, &health)
+        }
+        (&Method::GET, "/health/ready") => {
+            let health = health();
+            health_response(health.ready, &health)
+        }
+        _ => Response::builder()
+            .status(StatusCode::NOT_FOUND)
+            .body(Body::empty()),
+    };
+    Ok(response.expect("the responses are valid"))
+}
+
+fn health_response(
+    healthy: bool,
+    health: &health::Health,
+) -> std::result::Result<Response<Body>, hyper::http::Error> {
+    let status = if healthy {
+        StatusCode::OK
+    } else {
+        StatusCode::SERVICE_UNAVAILABLE
+    };
+    Response::builder()
+        .status(status)
+        .header("Content-Type", "application/json")
+        .body(Body::from(
+            serde_json::to_string(health).expect("the health can be serialized"),
+        ))
+}
@@ -1,11 +1,39 @@
 mod helper_macro;
 
+pub mod behaviour;
+pub mod broker;
 pub mod component;
+pub mod envelope;
+pub mod health;
+pub mod http;
+pub mod metrics;
+pub mod namespace;
+pub mod node_options;
+pub mod retry;
 pub mod rmq;
 pub mod rmq_init;
+pub mod rmq_migrate;
+pub mod rmq_parked;
 pub mod rmq_primitive;
+pub mod run_all;
+pub mod shutdown;
+pub mod telemetry;
 
 mod error;
 
+pub use async_trait::async_trait;
+pub use behaviour::Aggregator;
+pub use behaviour::Handler;
+pub use behaviour::Poller;
+pub use broker::ConnectionOptions;
+pub use envelope::MessageContext;
+pub use health::NodeInstance;
+pub use http::HttpOptions;
 pub use error::Error;
 pub use error::Result;
+pub use node_options::NodeOptions;
+pub use node_options::NodeSettings;
+pub use rmq_migrate::MigrateOptions;
+pub use rmq_parked::ParkedCommand;
+pub use run_all::RunAllOptions;
+pub use shutdown::ShutdownOptions;
@@ -0,0 +1,168 @@
+//! The Prometheus metrics of the nodes running in this process,
+//! served at `/metrics` when the node is given `--http-addr`, see `dge_runtime::http`.
+//!
+//! The metrics of the messages and of the `poll` jobs are labelled with the node,
+//! so the nodes run by `run-all` can be told apart, the ones of publishing with the queue published to.
+//! The gauges of the `poll` jobs are updated at the end of each pass over the jobs.
+
+use once_cell::sync::Lazy;
+use prometheus::Encoder;
+use prometheus::HistogramOpts;
+use prometheus::HistogramVec;
+use prometheus::IntCounterVec;
+use prometheus::IntGaugeVec;
+use prometheus::Opts;
+use prometheus::Registry;
+use prometheus::TextEncoder;
+use std::time::Duration;
+
+static REGISTRY: Lazy<Registry> = Lazy::new(Registry::new);
+
+static DELIVERED: Lazy<IntCounterVec> = Lazy::new(|| {
+    register(IntCounterVec::new(
+        Opts::new("dge_messages_delivered_total", "Messages delivered to the node"),
+        &["node"],
+    ))
+});
+
+static ACKED: Lazy<IntCounterVec> = Lazy::new(|| {
+    register(IntCounterVec::new(
+        Opts::new("dge_messages_acked_total", "Messages accepted by the handler of the node"),
+        &["node"],
+    ))
+});
+
+static REJECTED: Lazy<IntCounterVec> = Lazy::new(|| {
+    register(IntCounterVec::new(
+        Opts::new(
+            "dge_messages_rejected_total",
+            "Messages rejected by the handler of the node, because it failed (error) or on purpose (rejected)",
+        ),
+        &["node", "reason"],
+    ))
+});
+
+static UNPARSABLE: Lazy<IntCounterVec> = Lazy::new(|| {
+    register(IntCounterVec::new(
+        Opts::new(
+            "dge_messages_unparsable_total",
+            "Messages dropped by the node because they are not valid JSON of its input type",
+        ),
+        &["node"],
+    ))
+});
+
+static HANDLER_DURATION: Lazy<HistogramVec> = Lazy::new(|| {
+    register(HistogramVec::new(
+        HistogramOpts::new(
+            "dge_handler_duration_seconds",
+            "Time taken by the handler of the node, publishing its outputs included",
+        ),
+        &["node"],
+    ))
+});
+
+static PUBLISH_DURATION: Lazy<HistogramVec> = Lazy::new(|| {
+    register(HistogramVec::new(
+        HistogramOpts::new(
+            "dge_publish_duration_seconds",
+            "Time taken to publish a message and have it confirmed by RabbitMQ",
+        ),
+        &["queue"],
+    ))
+});
+
+static POLL_JOBS_QUEUED: Lazy<IntGaugeVec> = Lazy::new(|| {
+    register(IntGaugeVec::new(
+        Opts::new("dge_poll_jobs_queued", "Jobs of the poll node not done yet"),
+        &["node"],
+    ))
+});
+
+static POLL_JOBS_RUNNING: Lazy<IntGaugeVec> = Lazy::new(|| {
+    register(IntGaugeVec::new(
+        Opts::new("dge_poll_jobs_running", "Jobs of the poll node being checked"),
+        &["node"],
+    ))
+});
+
+static POLL_JOBS_DONE: Lazy<IntCounterVec> = Lazy::new(|| {
+    register(IntCounterVec::new(
+        Opts::new("dge_poll_jobs_done_total", "Jobs of the poll node done"),
+        &["node"],
+    ))
+});
+
+static POLL_AVAILABLE_SLOTS: Lazy<IntGaugeVec> = Lazy::new(|| {
+    register(IntGaugeVec::new(
+        Opts::new(
+            "dge_poll_available_slots",
+            "How many more jobs the poll node can check at the same time",
+        ),
+        &["node"],
+    ))
+});
+
+fn register<M>(metric: prometheus::Result<M>) -> M
+where
+    M: prometheus::core::Collector + Clone + 'static,
+{
+    let metric = metric.expect("the metrics are valid");
+    REGISTRY
+        .register(Box::new(metric.clone()))
+        .expect("each metric is registered once");
+    metric
+}
+
+/// The metrics, in the Prometheus text format.
+pub fn gather() -> String {
+    let mut buffer = Vec::new();
+    TextEncoder::new()
+        .encode(&REGISTRY.gather(), &mut buffer)
+        .expect("the metrics can be encoded");
+    String::from_utf8(buffer).expect("the text format is UTF-8")
+}
+
+pub(crate) fn delivered(node: &str) {
+    DELIVERED.with_label_values(&[node]).inc();
+}
+
+pub(crate) fn acked(node: &str) {
+    ACKED.with_label_values(&[node]).inc();
+}
+
+/// `failed` tells whether the handler returned an error, rather than rejecting the message.
+pub(crate) fn rejected(node: &str, failed: bool) {
+    let reason = if failed { "error" } else { "rejected" };
+    REJECTED.with_label_values(&[node, reason]).inc();
+}
+
+pub(crate) fn unparsable(node: &str) {
+    UNPARSABLE.with_label_values(&[node]).inc();
+}
+
+pub(crate) fn handled(node: &str, duration: Duration) {
+    HANDLER_DURATION
+        .with_label_values(&[node])
+        .observe(duration.as_secs_f64());
+}
+
+pub(crate) fn published(queue: &str, duration: Duration) {
+    PUBLISH_DURATION
+        .with_label_values(&[queue])
+        .observe(duration.as_secs_f64());
+}
+
+/// The state of the jobs of a poll node at the end of a pass over them.
+pub(crate) fn poll_pass(node: &str, queued: usize, running: usize, available_slots: usize) {
+    let gauge = |gauge: &IntGaugeVec, value: usize| {
+        gauge.with_label_values(&[node]).set(value as i64);
+    };
+    gauge(&POLL_JOBS_QUEUED, queued);
+    gauge(&POLL_JOBS_RUNNING, running);
+    gauge(&POLL_AVAILABLE_SLOTS, available_slots);
+}
+
+pub(crate) fn poll_job_done(node: &str) {
+    POLL_JOBS_DONE.with_label_values(&[node]).inc();
+}
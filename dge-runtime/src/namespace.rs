@@ -0,0 +1,64 @@
+//! The namespace of the exchanges and queues, so that multiple copies of a graph
+//! (e.g. staging and dev), or graphs using the same queue names, can share a RabbitMQ vhost.
+//!
+//! The namespace is prepended to the names of the exchanges, work queues and retry queues
+//! declared by `rmq_init`, consumed by `rmq::consume_forever` and published to by `rmq_primitive::publish`,
+//! e.g. queue `input` is named `staging.input` in namespace `staging`.
+//! The graph and the generated code always use the names without the namespace.
+
+use once_cell::sync::OnceCell;
+
+use crate::Error;
+use crate::Result;
+
+/// The environment variable the generated executable reads the namespace from,
+/// unless `--namespace` is given.
+pub const NAMESPACE_ENV: &str = "DGE_NAMESPACE";
+
+const SEPARATOR: char = '.';
+
+static NAMESPACE: OnceCell<String> = OnceCell::new();
+
+/// Set the namespace of this process, before any exchange or queue is used,
+/// an empty namespace leaves the names as they are.
+pub fn set_namespace(namespace: &str) -> Result<()> {
+    let invalid = |reason: &str| Error::InvalidNamespace {
+        namespace: String::from(namespace),
+        reason: String::from(reason),
+    };
+    if !namespace
+        .chars()
+        .all(|c| c.is_ascii_alphanumeric() || c == '-' || c == '_' || c == SEPARATOR)
+    {
+        return Err(invalid(
+            "only ASCII letters, digits, '-', '_' and '.' are allowed",
+        ));
+    }
+    if namespace == "amq" || namespace.starts_with("amq.") {
+        return Err(invalid("names starting with amq. are reserved by RabbitMQ"));
+    }
+
+    NAMESPACE
+        .set(String::from(namespace))
+        .or_else(|_| match NAMESPACE.get() {
+            Some(existing) if existing == namespace => Ok(()),
+            _ => Err(invalid("a different namespace is set already")),
+        })
+}
+
+/// The namespace of this process, empty if none is set.
+pub fn namespace() -> &'static str {
+    NAMESPACE.get().map(String::as_str).unwrap_or("")
+}
+
+/// The name of the exchange or queue `name` on RabbitMQ.
+///
+/// The default exchange (named by the empty string) is never namespaced.
+pub fn namespaced(name: &str) -> String {
+    let namespace = namespace();
+    if namespace.is_empty() || name.is_empty() {
+        String::from(name)
+    } else {
+        format!("{}{}{}", namespace, SEPARATOR, name)
+    }
+}
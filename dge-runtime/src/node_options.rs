@@ -0,0 +1,76 @@
+use futures::Future;
+use structopt::StructOpt;
+
+use crate::broker::ConnectionOptions;
+use crate::http::HttpOptions;
+use crate::shutdown::ShutdownOptions;
+use crate::Result;
+
+/// Runtime options of the node, given in the command line to override the defaults
+/// declared in the graph.
+#[derive(Clone, Debug, Default, StructOpt)]
+pub struct NodeOptions {
+    /// The number of unacknowledged messages RabbitMQ delivers to this instance
+    #[structopt(long)]
+    pub prefetch_count: Option<u16>,
+
+    /// The number of worker threads of the tokio runtime
+    #[structopt(long, parse(try_from_str = parse_positive))]
+    pub worker_threads: Option<usize>,
+
+    /// The maximum number of messages handled at the same time
+    #[structopt(long, parse(try_from_str = parse_positive))]
+    pub max_in_flight: Option<usize>,
+
+    #[structopt(flatten)]
+    pub connection: ConnectionOptions,
+
+    #[structopt(flatten)]
+    pub http: HttpOptions,
+
+    #[structopt(flatten)]
+    pub shutdown: ShutdownOptions,
+}
+
+/// The effective runtime options of a node.
+#[derive(Clone, Copy, Debug)]
+pub struct NodeSettings {
+    pub prefetch_count: u16,
+    pub worker_threads: usize,
+    /// `None` for no limit
+    pub max_in_flight: Option<usize>,
+}
+
+impl NodeOptions {
+    /// Use `defaults` for the options not given in the command line.
+    pub fn or(self, defaults: NodeSettings) -> NodeSettings {
+        NodeSettings {
+            prefetch_count: self.prefetch_count.unwrap_or(defaults.prefetch_count),
+            worker_threads: self.worker_threads.unwrap_or(defaults.worker_threads),
+            max_in_flight: self.max_in_flight.or(defaults.max_in_flight),
+        }
+    }
+}
+
+impl NodeSettings {
+    /// Run `future` to completion on a multi-threaded tokio runtime
+    /// with `worker_threads` worker threads.
+    pub fn block_on<F>(&self, future: F) -> Result<()>
+    where
+        F: Future<Output = Result<()>>,
+    {
+        let runtime = tokio::runtime::Builder::new_multi_thread()
+            .worker_threads(self.worker_threads)
+            .enable_all()
+            .build()?;
+        runtime.block_on(future)
+    }
+}
+
+pub(crate) fn parse_positive(s: &str) -> std::result::Result<usize, String> {
+    match s.parse::<usize>() {
+        Ok(0) => Err(String::from("must be greater than 0")),
+        Ok(n) => Ok(n),
+        Err(e) => Err(e.to_string()),
+    }
+}
@@ -0,0 +1,504 @@
+//! Retrying the rejected messages with increasing delays.
+//!
+//! A rejected message is dead-lettered by RabbitMQ to the retry queue of its work queue,
+//! which dead-letters it back to the work queue after `retry_interval_in_seconds`.
+//!
+//! When the work queue has backoff tiers (or jitter), a rejected message is instead published
+//! to the retry queue of the tier selected by the number of times it has been retried,
+//! with a per-message expiration, and the original is acknowledged.
+//! The retry queues of the tiers dead-letter the expired messages back to the work queue as well.
+//!
+//! When the work queue has a maximum number of attempts, a message failing that many times is
+//! published to the parking queue of the work queue instead, with the errors of its attempts
+//! in its headers, and the original is acknowledged.
+//! The parked messages stay there until they are replayed or purged, see `dge_runtime::rmq_parked`.
+
+use chrono::Utc;
+use lapin::message::Delivery;
+use lapin::types::AMQPValue;
+use lapin::types::FieldArray;
+use lapin::types::FieldTable;
+use lapin::types::LongLongInt;
+use lapin::types::LongString;
+use lapin::types::ShortString;
+use lapin::BasicProperties;
+use lapin::Channel;
+use log::debug;
+use log::warn;
+use rand::Rng;
+use std::convert::TryFrom;
+
+use crate::namespace::namespaced;
+use crate::rmq_primitive::confirmed;
+use crate::rmq_primitive::constant::RMQ_BASIC_PUBLISH_OPTIONS;
+use crate::rmq_primitive::unreliable_ack_or_reject;
+use crate::rmq_primitive::AckType;
+use crate::Result;
+
+/// Set by the runtime to the number of retries when it publishes a message to a retry queue,
+/// in addition to the `x-death` header maintained by RabbitMQ.
+pub const RETRIES_HEADER: &str = "x-dge-retries";
+
+/// Set by the runtime to the errors of the last attempts, oldest first,
+/// when it publishes a message to a retry queue or a parking queue.
+pub const ERRORS_HEADER: &str = "x-dge-errors";
+
+/// Set by the runtime to the work queue a parked message is parked from, as named on RabbitMQ.
+pub const PARKED_FROM_HEADER: &str = "x-dge-parked-from";
+
+/// Set by the runtime to the time (RFC 3339) a message is parked.
+pub const PARKED_AT_HEADER: &str = "x-dge-parked-at";
+
+/// How many errors are kept in `ERRORS_HEADER`.
+const MAX_ERRORS_KEPT: usize = 10;
+
+/// How the messages rejected from a work queue are retried.
+#[derive(Clone, Copy, Debug)]
+pub struct RetryPolicy {
+    pub retry_exchange: &'static str,
+    /// The retry queue of the first retry.
+    pub retry_queue: &'static str,
+    pub retry_interval_in_seconds: u32,
+    /// The retry queues of the later retries, with their delays in seconds,
+    /// the last one is used for all the retries after it.
+    pub tiers: &'static [(&'static str, u32)],
+    /// Each delay is shortened by a random amount up to this percentage of it.
+    pub jitter_percent: u8,
+    /// After failing this many times, a message is moved to `parking_queue`,
+    /// `None` retries it forever.
+    pub max_attempts: Option<u32>,
+    pub parking_queue: &'static str,
+}
+
+impl RetryPolicy {
+    /// Whether a plain rejection retries the message as the policy says,
+    /// i.e. there are no tiers, no jitter and no maximum number of attempts.
+    pub fn is_fixed(&self) -> bool {
+        self.tiers.is_empty() && self.jitter_percent == 0 && self.max_attempts.is_none()
+    }
+
+    /// Whether the message is parked after failing, having been retried `retries` times before.
+    pub fn parks(&self, retries: u32) -> bool {
+        self.max_attempts
+            .is_some_and(|max_attempts| retries.saturating_add(1) >= max_attempts)
+    }
+
+    /// The retry queue and the delay in milliseconds (with jitter) of the retry after `retries` retries.
+    pub fn next_retry(&self, retries: u32) -> (&'static str, u64) {
+        let (queue, delay_in_seconds) = match retries as usize {
+            0 => (self.retry_queue, self.retry_interval_in_seconds),
+            n => self
+                .tiers
+                .get(n - 1)
+                .or_else(|| self.tiers.last())
+                .copied()
+                .unwrap_or((self.retry_queue, self.retry_interval_in_seconds)),
+        };
+        let delay = u64::from(delay_in_seconds) * 1000;
+        let max_jitter = delay * u64::from(self.jitter_percent.min(100)) / 100;
+        let jitter = if max_jitter == 0 {
+            0
+        } else {
+            rand::thread_rng().gen_range(0..=max_jitter)
+        };
+        (queue, delay - jitter)
+    }
+}
+
+/// The number of times the message has been retried,
+/// i.e. the number of times it has expired from a retry queue, as counted in its `x-death` header.
+pub fn retries_of(properties: &BasicProperties) -> u32 {
+    let headers = match properties.headers() {
+        Some(headers) => headers.inner(),
+        None => return 0,
+    };
+
+    let mut expired = 0;
+    if let Some(AMQPValue::FieldArray(deaths)) = headers.get("x-death") {
+        for death in deaths.as_slice() {
+            if let AMQPValue::FieldTable(death) = death {
+                let death = death.inner();
+                let is_expired = matches!(
+                    death.get("reason"),
+                    Some(AMQPValue::LongString(reason)) if reason.as_str() == "expired"
+                );
+                if is_expired {
+                    expired += death.get("count").and_then(as_count).unwrap_or(1);
+                }
+            }
+        }
+    }
+    let counted = headers.get(RETRIES_HEADER).and_then(as_count).unwrap_or(0);
+    expired.max(counted)
+}
+
+/// Reject the message, which failed with `error`, it is retried or parked according to `retry_policy`.
+///
+/// If publishing it to its retry queue or its parking queue fails,
+/// it is rejected as if the policy were fixed.
+pub(crate) async fn reject(
+    channel: Channel,
+    delivery: &Delivery,
+    retry_policy: RetryPolicy,
+    error: &str,
+) {
+    let tag = delivery.delivery_tag;
+    if retry_policy.is_fixed() {
+        return unreliable_ack_or_reject(channel, AckType::Reject, tag).await;
+    }
+
+    let retries = retries_of(&delivery.properties);
+    let published = if retry_policy.parks(retries) {
+        park(&channel, delivery, retry_policy, retries, error).await
+    } else {
+        retry_later(&channel, delivery, retry_policy, retries, error).await
+    };
+    match published {
+        Ok(()) => unreliable_ack_or_reject(channel, AckType::Ack, tag).await,
+        Err(e) => {
+            warn!(
+                "failed to publish message {} to its retry or parking queue, rejecting it instead: {}",
+                tag, e
+            );
+            unreliable_ack_or_reject(channel, AckType::Reject, tag).await
+        }
+    }
+}
+
+/// Publish a copy of the message to the retry queue of its tier, expiring after the delay of the tier.
+async fn retry_later(
+    channel: &Channel,
+    delivery: &Delivery,
+    retry_policy: RetryPolicy,
+    retries: u32,
+    error: &str,
+) -> Result<()> {
+    let (retry_queue, delay) = retry_policy.next_retry(retries);
+    debug!(
+        "retrying message {} in {}ms via {}, retried {} times before",
+        delivery.delivery_tag, delay, retry_queue, retries
+    );
+
+    let mut headers = headers_with_error(&delivery.properties, error);
+    headers.insert(
+        ShortString::from(RETRIES_HEADER),
+        AMQPValue::LongLongInt(LongLongInt::from(retries) + 1),
+    );
+    let properties = delivery
+        .properties
+        .clone()
+        .with_headers(headers)
+        .with_expiration(ShortString::from(delay.to_string()));
+
+    publish_copy(
+        channel,
+        &namespaced(retry_policy.retry_exchange),
+        retry_queue,
+        delivery,
+        properties,
+    )
+    .await
+}
+
+/// Publish a copy of the message to the parking queue, where it stays until replayed or purged,
+/// failing if the parking queue is not declared.
+async fn park(
+    channel: &Channel,
+    delivery: &Delivery,
+    retry_policy: RetryPolicy,
+    retries: u32,
+    error: &str,
+) -> Result<()> {
+    warn!(
+        "message {} failed {} times, parking it in {}",
+        delivery.delivery_tag,
+        retries + 1,
+        retry_policy.parking_queue
+    );
+
+    let mut headers = headers_with_error(&delivery.properties, error);
+    headers.insert(
+        ShortString::from(RETRIES_HEADER),
+        AMQPValue::LongLongInt(LongLongInt::from(retries)),
+    );
+    let parked_from = delivery.routing_key.as_str();
+    headers.insert(
+        ShortString::from(PARKED_FROM_HEADER),
+        AMQPValue::LongString(LongString::from(parked_from)),
+    );
+    headers.insert(
+        ShortString::from(PARKED_AT_HEADER),
+        AMQPValue::LongString(LongString::from(Utc::now().to_rfc3339())),
+    );
+    let properties = delivery.properties.clone().with_headers(headers);
+
+    // published to the default exchange, which routes to the queue named by the routing key
+    publish_copy(channel, "", retry_policy.parking_queue, delivery, properties).await
+}
+
+/// The headers of the message, with `error` appended to `ERRORS_HEADER`.
+fn headers_with_error(properties: &BasicProperties, error: &str) -> FieldTable {
+    let mut headers = properties.headers().clone().unwrap_or_default();
+    let mut errors = errors_of(properties);
+    errors.push(String::from(error));
+    let first_kept = errors.len().saturating_sub(MAX_ERRORS_KEPT);
+    let errors: Vec<AMQPValue> = errors[first_kept..]
+        .iter()
+        .map(|error| AMQPValue::LongString(LongString::from(error.as_str())))
+        .collect();
+    headers.insert(
+        ShortString::from(ERRORS_HEADER),
+        AMQPValue::FieldArray(FieldArray::from(errors)),
+    );
+    headers
+}
+
+/// The properties of the message without the expiration set by `retry_later`,
+/// for the messages taken out of a retry queue, which would expire wherever they are moved to.
+/// (RabbitMQ removes it itself when it dead-letters a message.)
+pub(crate) fn without_expiration(properties: &BasicProperties) -> BasicProperties {
+    // the properties cannot be unset, so all but the expiration are copied
+    macro_rules! copy {
+        ($copy:ident, $($property:ident => $with:ident),* $(,)?) => {{
+            $(
+                if let Some(value) = properties.$property() {
+                    $copy = $copy.$with(value.clone());
+                }
+            )*
+            $copy
+        }};
+    }
+    let mut copy = BasicProperties::default();
+    copy!(
+        copy,
+        content_type => with_content_type,
+        content_encoding => with_content_encoding,
+        headers => with_headers,
+        delivery_mode => with_delivery_mode,
+        priority => with_priority,
+        correlation_id => with_correlation_id,
+        reply_to => with_reply_to,
+        message_id => with_message_id,
+        timestamp => with_timestamp,
+        kind => with_kind,
+        user_id => with_user_id,
+        app_id => with_app_id,
+        cluster_id => with_cluster_id,
+    )
+}
+
+/// The errors of the last attempts of the message, oldest first, see `ERRORS_HEADER`.
+pub fn errors_of(properties: &BasicProperties) -> Vec<String> {
+    let errors = properties
+        .headers()
+        .as_ref()
+        .and_then(|headers| headers.inner().get(ERRORS_HEADER).cloned());
+    match errors {
+        Some(AMQPValue::FieldArray(errors)) => errors
+            .as_slice()
+            .iter()
+            .filter_map(|error| match error {
+                AMQPValue::LongString(error) => Some(error.to_string()),
+                _ => None,
+            })
+            .collect(),
+        _ => Vec::new(),
+    }
+}
+
+/// Publish a copy of the delivered message to `queue` (which is namespaced here) via `exchange`,
+/// and wait for RabbitMQ to confirm it, a message returned as unroutable is not confirmed,
+/// e.g. when the retry queue of a new tier is not declared yet.
+async fn publish_copy(
+    channel: &Channel,
+    exchange: &str,
+    queue: &str,
+    delivery: &Delivery,
+    properties: BasicProperties,
+) -> Result<()> {
+    let confirm = channel
+        .basic_publish(
+            exchange,
+            &namespaced(queue),
+            RMQ_BASIC_PUBLISH_OPTIONS,
+            delivery.data.clone(),
+            properties,
+        )
+        .await?
+        .await?;
+    confirmed(confirm, queue)
+}
+
+fn as_count(value: &AMQPValue) -> Option<u32> {
+    let count = match value {
+        AMQPValue::LongLongInt(n) => *n,
+        AMQPValue::LongInt(n) => i64::from(*n),
+        AMQPValue::LongUInt(n) => i64::from(*n),
+        AMQPValue::ShortInt(n) => i64::from(*n),
+        AMQPValue::ShortUInt(n) => i64::from(*n),
+        _ => return None,
+    };
+    u32::try_from(count).ok()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const TIERS: &[(&str, u32)] = &[("retry_input_1", 20), ("retry_input_2", 40)];
+
+    fn policy(tiers: &'static [(&'static str, u32)], jitter_percent: u8) -> RetryPolicy {
+        RetryPolicy {
+            retry_exchange: "retry",
+            retry_queue: "retry_input",
+            retry_interval_in_seconds: 10,
+            tiers,
+            jitter_percent,
+            max_attempts: Some(3),
+            parking_queue: "input_parked",
+        }
+    }
+
+    fn expired(queue: &str, count: AMQPValue) -> AMQPValue {
+        let mut death = FieldTable::default();
+        death.insert(
+            ShortString::from("queue"),
+            AMQPValue::LongString(LongString::from(queue)),
+        );
+        death.insert(
+            ShortString::from("reason"),
+            AMQPValue::LongString(LongString::from("expired")),
+        );
+        death.insert(ShortString::from("count"), count);
+        AMQPValue::FieldTable(death)
+    }
+
+    fn with_header(name: &str, value: AMQPValue) -> BasicProperties {
+        let mut headers = FieldTable::default();
+        headers.insert(ShortString::from(name), value);
+        BasicProperties::default().with_headers(headers)
+    }
+
+    #[test]
+    fn next_retry_goes_through_the_tiers_and_stays_on_the_last_one() {
+        let policy = policy(TIERS, 0);
+        assert_eq!(policy.next_retry(0), ("retry_input", 10_000));
+        assert_eq!(policy.next_retry(1), ("retry_input_1", 20_000));
+        assert_eq!(policy.next_retry(2), ("retry_input_2", 40_000));
+        assert_eq!(policy.next_retry(3), ("retry_input_2", 40_000));
+        assert_eq!(policy.next_retry(u32::MAX), ("retry_input_2", 40_000));
+    }
+
+    #[test]
+    fn next_retry_without_tiers_uses_the_retry_queue() {
+        let policy = policy(&[], 0);
+        assert_eq!(policy.next_retry(0), ("retry_input", 10_000));
+        assert_eq!(policy.next_retry(5), ("retry_input", 10_000));
+    }
+
+    #[test]
+    fn next_retry_is_shortened_by_the_jitter_at_most() {
+        let jittered = policy(TIERS, 25);
+        for _ in 0..100 {
+            let (queue, delay) = jittered.next_retry(1);
+            assert_eq!(queue, "retry_input_1");
+            assert!((15_000..=20_000).contains(&delay), "delay is {}", delay);
+        }
+        // a jitter above 100% takes the whole delay at most, it does not underflow
+        let jittered = policy(TIERS, 200);
+        for _ in 0..100 {
+            let (_, delay) = jittered.next_retry(0);
+            assert!(delay <= 10_000, "delay is {}", delay);
+        }
+    }
+
+    #[test]
+    fn parks_after_max_attempts() {
+        let policy = policy(TIERS, 0);
+        assert!(!policy.parks(0));
+        assert!(!policy.parks(1));
+        assert!(policy.parks(2));
+        assert!(policy.parks(u32::MAX));
+        let forever = RetryPolicy {
+            max_attempts: None,
+            ..policy
+        };
+        assert!(!forever.parks(u32::MAX));
+    }
+
+    #[test]
+    fn retries_are_counted_from_x_death() {
+        assert_eq!(retries_of(&BasicProperties::default()), 0);
+        // RabbitMQ sets the count as a long long int, other clients may use a long int
+        for count in [AMQPValue::LongLongInt(3), AMQPValue::LongInt(3)].iter() {
+            let deaths = FieldArray::from(vec![
+                expired("retry_input_1", count.clone()),
+                expired("retry_input", AMQPValue::LongLongInt(1)),
+            ]);
+            let properties = with_header("x-death", AMQPValue::FieldArray(deaths));
+            assert_eq!(retries_of(&properties), 4);
+        }
+    }
+
+    #[test]
+    fn retries_are_not_counted_from_rejections() {
+        let mut rejected = FieldTable::default();
+        rejected.insert(
+            ShortString::from("reason"),
+            AMQPValue::LongString(LongString::from("rejected")),
+        );
+        rejected.insert(ShortString::from("count"), AMQPValue::LongLongInt(2));
+        let deaths = FieldArray::from(vec![AMQPValue::FieldTable(rejected)]);
+        let properties = with_header("x-death", AMQPValue::FieldArray(deaths));
+        assert_eq!(retries_of(&properties), 0);
+    }
+
+    #[test]
+    fn retries_header_counts_when_x_death_is_lost() {
+        let properties = with_header(RETRIES_HEADER, AMQPValue::LongLongInt(2));
+        assert_eq!(retries_of(&properties), 2);
+    }
+
+    #[test]
+    fn errors_are_appended_and_trimmed_to_the_last_ones() {
+        let properties = BasicProperties::default();
+        let headers = headers_with_error(&properties, "error 1");
+        let properties = BasicProperties::default().with_headers(headers);
+        assert_eq!(errors_of(&properties), vec![String::from("error 1")]);
+
+        let mut properties = properties;
+        for i in 2..=MAX_ERRORS_KEPT + 3 {
+            let headers = headers_with_error(&properties, &format!("error {}", i));
+            properties = BasicProperties::default().with_headers(headers);
+        }
+        let errors = errors_of(&properties);
+        assert_eq!(errors.len(), MAX_ERRORS_KEPT);
+        assert_eq!(errors.first(), Some(&String::from("error 4")));
+        assert_eq!(
+            errors.last(),
+            Some(&format!("error {}", MAX_ERRORS_KEPT + 3))
+        );
+    }
+
+    #[test]
+    fn expiration_is_removed_and_the_rest_kept() {
+        let properties = with_header(RETRIES_HEADER, AMQPValue::LongLongInt(2))
+            .with_delivery_mode(2)
+            .with_message_id(ShortString::from("message"))
+            .with_expiration(ShortString::from("20000"));
+        let expected = with_header(RETRIES_HEADER, AMQPValue::LongLongInt(2))
+            .with_delivery_mode(2)
+            .with_message_id(ShortString::from("message"));
+        assert_eq!(without_expiration(&properties), expected);
+    }
+
+    #[test]
+    fn other_headers_are_kept() {
+        let properties = with_header(RETRIES_HEADER, AMQPValue::LongLongInt(2));
+        let headers = headers_with_error(&properties, "failed");
+        assert_eq!(
+            headers.inner().get(RETRIES_HEADER),
+            Some(&AMQPValue::LongLongInt(2))
+        );
+    }
+}
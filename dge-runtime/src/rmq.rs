@@ -1,4 +1,6 @@
 use futures::Future;
+use futures::StreamExt;
+use lapin::options::BasicCancelOptions;
 use lapin::options::BasicQosOptions;
 use lapin::Channel;
 use log::debug;
@@ -6,18 +8,35 @@ use log::info;
 use log::warn;
 use serde::de::DeserializeOwned;
 use serde_json;
+use std::sync::Arc;
+use std::time::Instant;
+use tokio::sync::mpsc;
+use tokio::sync::Semaphore;
 
 use super::rmq_primitive::constant::*;
-use super::rmq_primitive::create_channel;
 use super::rmq_primitive::unreliable_ack_or_reject;
 use super::rmq_primitive::AckType;
 use super::rmq_primitive::Responsibility;
+use crate::broker::Backoff;
+use crate::broker::Broker;
+use crate::envelope::Envelope;
+use crate::envelope::MessageContext;
+use crate::health::NodeInstance;
+use crate::metrics;
+use crate::namespace::namespaced;
+use crate::node_options::NodeSettings;
+use crate::retry;
+use crate::retry::RetryPolicy;
+use crate::shutdown;
+use crate::telemetry::TraceContext;
 use crate::Result;
 
-/// Read a message of type `InputMsg` from `input_queue`, and process it with `handler`.
+/// Read a message of type `InputMsg` from `input_queue`, and process it with `handler`,
+/// the channels are created by `broker`.
 ///
 /// During the processing, the `handler` can access its state of type `HandlerState`,
-/// and use the `Channel` to publish messages to other queues.
+/// and use the `Channel` to publish messages to other queues,
+/// it is taken from the pool of publishing channels of `broker` for the time of the handling.
 ///
 /// The `handler` can return `Ok(Responsibility::Accept)` to indicate that
 /// the handler has taken care of the message been processed,
@@ -28,38 +47,67 @@ use crate::Result;
 /// The `handler` can also return `Ok<Responsibility::Reject>` to indicate that
 /// the handler rejects the message,
 /// in this case an RabbitMQ `basic.reject` will be sent back to the RabbitMQ server,
-/// and the message will be redelivered later.
+/// and the message will be redelivered later, as `retry_policy` says.
 ///
 /// For convenience, and `Err(_)` is treated largely in a similar way with a rejection,
 /// the difference is that an `Ok<Responsibility::Reject>` is considered as an intentional rejection,
 /// thus no warnings are logged, whereas an `Err(_)` is treated as an unintentional rejection,
 /// which will cause warnings to be logged.
 ///
-/// This function never terminates.
+/// `input_queue` is namespaced, see `dge_runtime::namespace`.
 ///
-/// If the connection to the RabbitMQ server drops, it will be retried.
+/// The `handler` runs with the `MessageContext` of the message, telling that it is handled by
+/// the node of `instance`,
+/// so the messages it publishes are in the same run, see `dge_runtime::envelope`.
+/// The handling of each message is traced, see `dge_runtime::telemetry`,
+/// and counted in the metrics, see `dge_runtime::metrics`.
+///
+/// At most `settings.prefetch_count` unacknowledged messages are delivered by RabbitMQ,
+/// and at most `settings.max_in_flight` (if given) of them are handled at the same time.
+///
+/// `instance` is initialized by the time this is called, and reports whether the consumer
+/// is attached and when a message was last delivered, see `dge_runtime::health`.
+///
+/// This function only returns once the shutdown is requested, see `dge_runtime::shutdown`:
+/// the consumer is then cancelled, and the messages being handled are waited for
+/// before the channel is closed.
+///
+/// If the connection to the RabbitMQ server drops, or consuming fails,
+/// the consumer is attached again, with the delays between the attempts growing
+/// as `broker` is configured, see `dge_runtime::broker`.
 pub async fn consume_forever<InputMsg, HandlerState, HandlerResult>(
-    rmq_uri: &str,
+    broker: &Broker,
+    instance: NodeInstance,
     input_queue: &'static str,
+    retry_policy: RetryPolicy,
     handler: fn(HandlerState, Channel, InputMsg) -> HandlerResult,
     handler_state: HandlerState,
-    prefetch_count: u16,
+    settings: NodeSettings,
 ) where
     InputMsg: DeserializeOwned + Send + 'static,
     HandlerState: Clone + Send + 'static,
     HandlerResult: Future<Output = Result<Responsibility>> + Send + 'static,
 {
-    loop {
+    instance.initialized();
+    let mut backoff = Backoff::new(broker.options());
+    while !shutdown::is_requested() {
         // establish connection to rmq server and consume the queue
-        match consume_queue(
-            rmq_uri,
+        let consumed = consume_queue(
+            broker,
+            &instance,
             &input_queue,
+            retry_policy,
             handler,
             handler_state.clone(),
-            prefetch_count,
+            settings,
         )
-        .await
-        {
+        .await;
+        if instance.is_attached() {
+            // the consumer was attached, the attempts failing from now on are counted anew
+            backoff.reset();
+        }
+        instance.attached(false);
+        match consumed {
             Ok(()) => (),
             Err(e) => {
                 warn!(
@@ -68,33 +116,49 @@ pub async fn consume_forever<InputMsg, HandlerState, HandlerResult>(
                 );
             }
         }
+        if shutdown::is_requested() {
+            break;
+        }
 
         // sleep for a while before reconnecting to avoid rapid fire
-        let duration = std::time::Duration::from_millis(2000);
+        let duration = backoff.next();
         info!(
-            "sleep for {} seconds before reconnecting to queue {}",
-            &duration.as_secs(),
+            "sleep for {} ms before reconnecting to queue {}",
+            &duration.as_millis(),
             &input_queue
         );
-        tokio::time::sleep(duration).await
+        tokio::select! {
+            _ = tokio::time::sleep(duration) => (),
+            _ = shutdown::requested() => (),
+        }
     }
+    info!("stopped consuming queue {}", &input_queue);
 }
 
 async fn consume_queue<InputMsg, HandlerState, HandlerResult>(
-    rmq_uri: &str,
+    broker: &Broker,
+    instance: &NodeInstance,
     input_queue: &'static str,
+    retry_policy: RetryPolicy,
     handler: fn(HandlerState, Channel, InputMsg) -> HandlerResult,
     handler_state: HandlerState,
-    prefetch_count: u16,
+    settings: NodeSettings,
 ) -> Result<()>
 where
     InputMsg: DeserializeOwned + Send + 'static,
     HandlerState: Clone + Send + 'static,
     HandlerResult: Future<Output = Result<Responsibility>> + Send + 'static,
 {
+    let input_queue = &namespaced(input_queue);
+    let NodeSettings {
+        prefetch_count,
+        max_in_flight,
+        ..
+    } = settings;
+
     // establish communication
     info!("creating channel for consuming queue {}", input_queue);
-    let channel = create_channel(rmq_uri).await?;
+    let channel = broker.consume_channel().await?;
     info!("setting prefetch to be {}", prefetch_count);
     channel
         .basic_qos(prefetch_count, BasicQosOptions { global: false })
@@ -105,7 +169,7 @@ where
     // we can use the queue name as the identifier
     let consumer_tag = format!("dgec-{}", input_queue);
     info!("creating consumer {}", &consumer_tag);
-    let consumer = channel
+    let mut consumer = channel
         .basic_consume(
             input_queue,
             &consumer_tag,
@@ -114,24 +178,96 @@ where
         )
         .await?;
 
-    // consuming loop
+    instance.attached(true);
+
+    // limits the number of handlers running at the same time
+    let in_flight = max_in_flight.map(|n| Arc::new(Semaphore::new(n)));
+
+    // each message being handled holds a sender,
+    // so the receiver gets nothing but the end once they are all handled
+    let (handling_sender, mut handling_receiver) = mpsc::channel::<()>(1);
+
+    // consuming loop, until the consumer ends or the shutdown is requested,
+    // the deliveries are awaited, so the worker threads keep running the handlers meanwhile
     info!("entering consuming loop for queue {}", input_queue);
-    for delivery in consumer {
-        let (channel, msg) = delivery?;
-        tokio::spawn(handle_one_delivery(
+    let mut consumed = Ok(());
+    loop {
+        let delivery = tokio::select! {
+            delivery = consumer.next() => delivery,
+            _ = shutdown::requested() => break,
+        };
+        let (channel, msg) = match delivery {
+            None => break,
+            Some(Ok(delivery)) => delivery,
+            Some(Err(e)) => {
+                consumed = Err(e.into());
+                break;
+            }
+        };
+        instance.delivered();
+        let permit = match &in_flight {
+            None => None,
+            Some(in_flight) => tokio::select! {
+                permit = in_flight.clone().acquire_owned() => {
+                    Some(permit.expect("the semaphore is never closed"))
+                }
+                // the message is requeued as it is, not as a failed attempt
+                _ = shutdown::requested() => {
+                    debug!("shutting down, requeuing message {}", msg.delivery_tag);
+                    unreliable_ack_or_reject(channel, AckType::Requeue, msg.delivery_tag).await;
+                    break;
+                }
+            },
+        };
+        let handling = handle_one_delivery(
+            broker.clone(),
             channel,
             msg,
+            instance.node(),
+            retry_policy,
             handler,
             handler_state.clone(),
-        ));
+        );
+        let handling_sender = handling_sender.clone();
+        tokio::spawn(async move {
+            handling.await;
+            drop(permit);
+            drop(handling_sender);
+        });
     }
 
-    Ok(())
+    if shutdown::is_requested() {
+        info!("cancelling consumer {}", &consumer_tag);
+        if let Err(e) = channel
+            .basic_cancel(&consumer_tag, BasicCancelOptions::default())
+            .await
+        {
+            warn!("failed to cancel consumer {}: {}", &consumer_tag, e);
+        }
+        info!(
+            "consumer {} is cancelled, waiting for the messages being handled",
+            &consumer_tag
+        );
+        drop(handling_sender);
+        let _ = handling_receiver.recv().await;
+        channel
+            .close(
+                lapin::protocol::constants::REPLY_SUCCESS as lapin::types::ShortUInt,
+                "shutting down",
+            )
+            .await?;
+        info!("closed the channel of consumer {}", &consumer_tag);
+    }
+
+    consumed
 }
 
 async fn handle_one_delivery<InputMsg, HandlerState, HandlerResult>(
+    broker: Broker,
     channel: Channel,
     delivery: lapin::message::Delivery,
+    node: &'static str,
+    retry_policy: RetryPolicy,
     handle: fn(HandlerState, Channel, InputMsg) -> HandlerResult,
     handler_state: HandlerState,
 ) where
@@ -140,6 +276,9 @@ async fn handle_one_delivery<InputMsg, HandlerState, HandlerResult>(
     HandlerResult: Future<Output = Result<Responsibility>> + Send + 'static,
 {
     debug!("processing message of tag: {}", delivery.delivery_tag);
+    metrics::delivered(node);
+    let envelope = Envelope::of(&delivery.properties);
+    let consume = TraceContext::consume(node, &delivery, &envelope);
     match serde_json::from_slice::<InputMsg>(&delivery.data) {
         Err(e) => {
             // json parse failed, we just warn and drop the message.
@@ -150,25 +289,64 @@ async fn handle_one_delivery<InputMsg, HandlerState, HandlerResult>(
                 "failed to parse json when processing delivery: {}, msg will be dropped, error is: {}, data is: {:?}",
                 &delivery.delivery_tag, e, &delivery.data
             );
+            consume.fail(&e);
+            metrics::unparsable(node);
             unreliable_ack_or_reject(channel, AckType::Ack, delivery.delivery_tag).await
         }
-        Ok(msg) => match handle(handler_state, channel.clone(), msg).await {
-            Err(e) => {
-                warn!(
-                    "an error occurred while handling message {}, will requeue it, error is: {}",
-                    &delivery.delivery_tag, e
-                );
-                unreliable_ack_or_reject(channel, AckType::Reject, delivery.delivery_tag).await
-            }
-            Ok(Responsibility::Reject) => {
-                debug!("explicitly rejecting message {}", &delivery.delivery_tag);
-                unreliable_ack_or_reject(channel, AckType::Reject, delivery.delivery_tag).await
+        Ok(msg) => {
+            let handling = consume.child(format!("handle {}", node));
+            let context = MessageContext {
+                node,
+                envelope,
+                trace: handling.clone(),
+            };
+            let started = Instant::now();
+            let handle_msg = async {
+                let publishing = broker.publish_channel().await?;
+                handle(handler_state, publishing.channel(), msg).await
+            };
+            let handled = context.scope(handle_msg).await;
+            metrics::handled(node, started.elapsed());
+            if let Err(e) = &handled {
+                handling.fail(e);
+                consume.fail(e);
             }
+            handling.end();
 
-            Ok(Responsibility::Accept) => {
-                debug!("accepting message {}", &delivery.delivery_tag);
-                unreliable_ack_or_reject(channel, AckType::Ack, delivery.delivery_tag).await
+            match handled {
+                Err(e) => {
+                    warn!(
+                        "an error occurred while handling message {}, will requeue it, error is: {}",
+                        &delivery.delivery_tag, e
+                    );
+                    metrics::rejected(node, true);
+                    let rejecting = consume.child(String::from("reject"));
+                    retry::reject(channel, &delivery, retry_policy, &e.to_string()).await;
+                    rejecting.end();
+                }
+                Ok(Responsibility::Reject) => {
+                    debug!("explicitly rejecting message {}", &delivery.delivery_tag);
+                    metrics::rejected(node, false);
+                    let rejecting = consume.child(String::from("reject"));
+                    retry::reject(channel, &delivery, retry_policy, "rejected by the handler").await;
+                    rejecting.end();
+                }
+                Ok(Responsibility::Requeue) => {
+                    debug!("requeuing message {}", &delivery.delivery_tag);
+                    let requeuing = consume.child(String::from("requeue"));
+                    unreliable_ack_or_reject(channel, AckType::Requeue, delivery.delivery_tag)
+                        .await;
+                    requeuing.end();
+                }
+                Ok(Responsibility::Accept) => {
+                    debug!("accepting message {}", &delivery.delivery_tag);
+                    metrics::acked(node);
+                    let acking = consume.child(String::from("ack"));
+                    unreliable_ack_or_reject(channel, AckType::Ack, delivery.delivery_tag).await;
+                    acking.end();
+                }
             }
-        },
+        }
     }
+    consume.end();
 }
@@ -3,10 +3,13 @@ use lapin::types::AMQPValue;
 use lapin::types::FieldTable;
 use lapin::types::LongString;
 use lapin::types::ShortString;
+use lapin::Channel;
 use log::info;
 
 use crate::Result;
 
+use crate::namespace::namespaced;
+
 use crate::rmq_primitive;
 use crate::rmq_primitive::constant::RMQ_QUEUE_BIND_OPTIONS;
 use crate::rmq_primitive::constant::RMQ_QUEUE_DECLARE_OPTIONS;
@@ -28,21 +31,25 @@ use crate::rmq_primitive::constant::RMQ_QUEUE_DECLARE_OPTIONS;
 ///
 /// Both `work_exchange` and `retry_exchange` should be direct exchanges,
 /// the implementation depends on this.
+///
+/// The names of the exchanges and the queues are namespaced, see `dge_runtime::namespace`.
+/// They are declared on `channel`, which is left open.
 pub async fn init_work_queue<S: AsRef<str>>(
-    rmq_uri: S,
+    channel: &Channel,
     work_exchange: S,
     work_queue: S,
     retry_exchange: S,
     retry_queue: S,
     retry_interval_in_seconds: u32,
 ) -> Result<()> {
-    let rmq_uri = rmq_uri.as_ref();
-    let work_direct_exchange = work_exchange.as_ref();
-    let retry_direct_exchange = retry_exchange.as_ref();
-    let work_queue = work_queue.as_ref();
-    let retry_queue = retry_queue.as_ref();
-
-    let channel = rmq_primitive::create_channel(rmq_uri).await?;
+    let work_direct_exchange = namespaced(work_exchange.as_ref());
+    let work_direct_exchange = work_direct_exchange.as_str();
+    let retry_direct_exchange = namespaced(retry_exchange.as_ref());
+    let retry_direct_exchange = retry_direct_exchange.as_str();
+    let work_queue = namespaced(work_queue.as_ref());
+    let work_queue = work_queue.as_str();
+    let retry_queue = namespaced(retry_queue.as_ref());
+    let retry_queue = retry_queue.as_str();
 
     let exchanges: [&str; 2] = [work_direct_exchange, retry_direct_exchange];
     for exchange in exchanges.iter() {
@@ -126,13 +133,6 @@ pub async fn init_work_queue<S: AsRef<str>>(
         )
         .await?;
 
-    channel
-        .close(
-            lapin::protocol::constants::REPLY_SUCCESS as lapin::types::ShortUInt,
-            "normal close of channel",
-        )
-        .await?;
-
     info!(
         "done creating exchanges and queues for work queue {}",
         work_queue
@@ -147,9 +147,11 @@ pub async fn init_exchanges_and_queues<S: AsRef<str>>(
     retry_direct_exchange: S,
     queues: Vec<(S, S, u32)>,
 ) -> Result<()> {
+    let channel = rmq_primitive::create_channel(rmq_uri).await?;
+
     for (work_queue, retry_queue, retry_interval_in_seconds) in queues.into_iter() {
         let () = init_work_queue(
-            rmq_uri.as_ref(),
+            &channel,
             work_direct_exchange.as_ref(),
             work_queue.as_ref(),
             retry_direct_exchange.as_ref(),
@@ -158,5 +160,120 @@ pub async fn init_exchanges_and_queues<S: AsRef<str>>(
         ).await?;
     }
 
+    rmq_primitive::close_channel(&channel).await
+}
+
+/// Create the retry queues of the backoff tiers, see `dge_runtime::retry`,
+/// `tiers` are `(work_queue, retry_queue)`.
+///
+/// Unlike the retry queue created by `init_work_queue`, these have no `x-message-ttl`,
+/// each message expires after the delay of its tier (with jitter) set when it is published,
+/// so the delays can be changed without declaring the queues again.
+///
+/// The exchanges are expected to be created by `init_work_queue` already.
+pub async fn init_retry_tiers<S: AsRef<str>>(
+    rmq_uri: S,
+    work_direct_exchange: S,
+    retry_direct_exchange: S,
+    tiers: Vec<(S, S)>,
+) -> Result<()> {
+    if tiers.is_empty() {
+        return Ok(());
+    }
+
+    let channel = rmq_primitive::create_channel(rmq_uri).await?;
+
+    for (work_queue, retry_queue) in tiers.iter() {
+        init_retry_tier(
+            &channel,
+            work_direct_exchange.as_ref(),
+            work_queue.as_ref(),
+            retry_direct_exchange.as_ref(),
+            retry_queue.as_ref(),
+        )
+        .await?;
+    }
+
+    rmq_primitive::close_channel(&channel).await
+}
+
+/// Create the retry queue `retry_queue` of a backoff tier for `work_queue`, see `init_retry_tiers`,
+/// on `channel`, which is left open.
+pub async fn init_retry_tier<S: AsRef<str>>(
+    channel: &Channel,
+    work_exchange: S,
+    work_queue: S,
+    retry_exchange: S,
+    retry_queue: S,
+) -> Result<()> {
+    let work_direct_exchange = namespaced(work_exchange.as_ref());
+    let retry_direct_exchange = namespaced(retry_exchange.as_ref());
+    let work_queue = namespaced(work_queue.as_ref());
+    let retry_queue = namespaced(retry_queue.as_ref());
+
+    info!(
+        "declaring retry queue {} of a backoff tier for work queue {}",
+        retry_queue, work_queue
+    );
+    let mut queue_args = FieldTable::default();
+    queue_args.insert(
+        ShortString::from("x-dead-letter-exchange"),
+        AMQPValue::from(LongString::from(work_direct_exchange.as_str())),
+    );
+    queue_args.insert(
+        ShortString::from("x-dead-letter-routing-key"),
+        AMQPValue::from(LongString::from(work_queue.as_str())),
+    );
+    channel
+        .queue_declare(&retry_queue, RMQ_QUEUE_DECLARE_OPTIONS, queue_args)
+        .await?;
+
+    info!(
+        "binding retry queue {} to exchange {}",
+        retry_queue, retry_direct_exchange
+    );
+    channel
+        .queue_bind(
+            &retry_queue,
+            &retry_direct_exchange,
+            &retry_queue,
+            RMQ_QUEUE_BIND_OPTIONS,
+            FieldTable::default(),
+        )
+        .await?;
+
+    Ok(())
+}
+
+/// Create the parking queues, see `dge_runtime::retry`.
+///
+/// The messages are published to them via the default exchange, so they are not bound to any exchange,
+/// and they have no dead-letter exchange, the messages stay until replayed or purged.
+pub async fn init_parking_queues<S: AsRef<str>>(rmq_uri: S, parking_queues: Vec<S>) -> Result<()> {
+    if parking_queues.is_empty() {
+        return Ok(());
+    }
+
+    let channel = rmq_primitive::create_channel(rmq_uri).await?;
+
+    for parking_queue in parking_queues.iter() {
+        init_parking_queue(&channel, parking_queue.as_ref()).await?;
+    }
+
+    rmq_primitive::close_channel(&channel).await
+}
+
+/// Create the parking queue `parking_queue`, see `init_parking_queues`,
+/// on `channel`, which is left open.
+pub async fn init_parking_queue<S: AsRef<str>>(channel: &Channel, parking_queue: S) -> Result<()> {
+    let parking_queue = namespaced(parking_queue.as_ref());
+    info!("declaring parking queue {}", parking_queue);
+    channel
+        .queue_declare(
+            &parking_queue,
+            RMQ_QUEUE_DECLARE_OPTIONS,
+            FieldTable::default(),
+        )
+        .await?;
     Ok(())
 }
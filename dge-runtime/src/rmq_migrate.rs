@@ -0,0 +1,524 @@
+//! Executing the migration plans written by `dge plan-migration`.
+//!
+//! The plan migrates the queues and exchanges of a previous version of the graph to the ones of
+//! the current version, see `dge_gen::migration` for the steps.
+//! The messages are moved between the queues one by one,
+//! each is acknowledged only after it is confirmed by RabbitMQ in its new queue,
+//! so an interrupted migration loses no message, and can be run again.
+//!
+//! The names in the plan are namespaced, see `dge_runtime::namespace`.
+//!
+//! Except for `drain_and_delete`, which waits for the consumers of the previous version,
+//! the nodes consuming the migrated queues should be stopped while the migration runs,
+//! since the queues are deleted and declared again under them.
+
+use lapin::options::BasicGetOptions;
+use lapin::options::ExchangeDeleteOptions;
+use lapin::options::QueueDeclareOptions;
+use lapin::options::QueueDeleteOptions;
+use lapin::protocol::AMQPErrorKind;
+use lapin::protocol::AMQPSoftError;
+use lapin::types::FieldTable;
+use lapin::Channel;
+use log::info;
+use serde::Deserialize;
+use serde::Serialize;
+use std::fmt;
+use std::path::PathBuf;
+use std::time::Duration;
+use std::time::Instant;
+use structopt::StructOpt;
+
+use crate::broker::Broker;
+use crate::namespace::namespaced;
+use crate::retry;
+use crate::rmq_init;
+use crate::rmq_primitive::confirmed;
+use crate::rmq_primitive::constant::RMQ_BASIC_ACK_OPTIONS;
+use crate::rmq_primitive::constant::RMQ_BASIC_PUBLISH_OPTIONS;
+use crate::rmq_primitive::constant::RMQ_QUEUE_DECLARE_OPTIONS;
+use crate::Error;
+use crate::Result;
+
+/// The version of the migration plan document this crate understands.
+pub const FORMAT_VERSION: u32 = 1;
+
+/// How often the queues are checked while waiting for them to be drained.
+const DRAIN_CHECK_INTERVAL: Duration = Duration::from_secs(5);
+
+/// Run a migration plan written by `dge plan-migration`.
+#[derive(Clone, Debug, StructOpt)]
+pub struct MigrateOptions {
+    /// The migration plan
+    #[structopt(long, parse(from_os_str))]
+    pub plan: PathBuf,
+
+    /// Print the steps without executing them
+    #[structopt(long)]
+    pub dry_run: bool,
+
+    /// How long to wait for a queue no longer used to be emptied by its consumer, before failing
+    #[structopt(long, default_value = "600")]
+    pub drain_timeout_in_seconds: u64,
+}
+
+/// The same document as `dge_gen::migration::MigrationPlan`.
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub struct MigrationPlan {
+    pub format_version: u32,
+    pub exchanges: Exchanges,
+    pub steps: Vec<MigrationStep>,
+}
+
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Exchanges {
+    pub work: String,
+    pub retry: String,
+}
+
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(tag = "action", rename_all = "snake_case")]
+pub enum MigrationStep {
+    Declare {
+        queue: String,
+        retry_queue: String,
+        retry_interval_in_seconds: u32,
+        #[serde(default)]
+        retry_tiers: Vec<String>,
+        #[serde(default)]
+        parking_queue: Option<String>,
+    },
+    Move {
+        from: String,
+        from_retry_queue: String,
+        #[serde(default)]
+        from_retry_tiers: Vec<String>,
+        #[serde(default)]
+        from_parking_queue: Option<String>,
+        to: String,
+        #[serde(default)]
+        to_parking_queue: Option<String>,
+    },
+    Recreate {
+        queue: String,
+        old_retry_queue: String,
+        #[serde(default)]
+        old_retry_tiers: Vec<String>,
+        retry_queue: String,
+        retry_interval_in_seconds: u32,
+        #[serde(default)]
+        retry_tiers: Vec<String>,
+        reason: String,
+    },
+    DeclareRetryTiers {
+        queue: String,
+        retry_tiers: Vec<String>,
+    },
+    DeleteRetryTiers {
+        queue: String,
+        retry_tiers: Vec<String>,
+    },
+    DeclareParkingQueue {
+        queue: String,
+        parking_queue: String,
+    },
+    DeleteParkingQueue {
+        queue: String,
+        parking_queue: String,
+    },
+    DrainAndDelete {
+        queue: String,
+        retry_queue: String,
+        #[serde(default)]
+        retry_tiers: Vec<String>,
+        #[serde(default)]
+        parking_queue: Option<String>,
+    },
+    DeleteExchange {
+        exchange: String,
+    },
+}
+
+impl fmt::Display for MigrationStep {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            MigrationStep::Declare { queue, .. } => write!(f, "declare queue {}", queue),
+            MigrationStep::Move { from, to, .. } => write!(f, "move queue {} to {}", from, to),
+            MigrationStep::Recreate { queue, reason, .. } => {
+                write!(f, "recreate queue {}, because {}", queue, reason)
+            }
+            MigrationStep::DeclareRetryTiers { queue, .. } => {
+                write!(
+                    f,
+                    "declare the retry queues of the backoff tiers added to queue {}",
+                    queue
+                )
+            }
+            MigrationStep::DeleteRetryTiers { queue, .. } => write!(
+                f,
+                "delete the retry queues of the backoff tiers removed from queue {}",
+                queue
+            ),
+            MigrationStep::DeclareParkingQueue { queue, .. } => {
+                write!(f, "declare the parking queue of queue {}", queue)
+            }
+            MigrationStep::DeleteParkingQueue { queue, .. } => {
+                write!(f, "delete the parking queue of queue {}", queue)
+            }
+            MigrationStep::DrainAndDelete { queue, .. } => {
+                write!(f, "drain and delete queue {}", queue)
+            }
+            MigrationStep::DeleteExchange { exchange } => write!(f, "delete exchange {}", exchange),
+        }
+    }
+}
+
+impl MigrationPlan {
+    pub fn load(options: &MigrateOptions) -> Result<MigrationPlan> {
+        let plan: MigrationPlan = serde_json::from_str(&std::fs::read_to_string(&options.plan)?)?;
+        if plan.format_version > FORMAT_VERSION {
+            return Err(Error::UnsupportedMigrationPlan {
+                format_version: plan.format_version,
+                supported: FORMAT_VERSION,
+            });
+        }
+        Ok(plan)
+    }
+}
+
+/// Execute the plan given by `options`, step by step.
+pub async fn migrate(broker: &Broker, options: &MigrateOptions) -> Result<()> {
+    let plan = MigrationPlan::load(options)?;
+    let exchanges = &plan.exchanges;
+    let work_exchange = exchanges.work.as_str();
+    let retry_exchange = exchanges.retry.as_str();
+    let drain_timeout = Duration::from_secs(options.drain_timeout_in_seconds);
+
+    if plan.steps.is_empty() {
+        info!("nothing to migrate");
+    }
+    for (i, step) in plan.steps.iter().enumerate() {
+        if options.dry_run {
+            info!("step {}: {} (dry run)", i + 1, step);
+            continue;
+        }
+        info!("step {}: {}", i + 1, step);
+
+        match step {
+            MigrationStep::Declare {
+                queue,
+                retry_queue,
+                retry_interval_in_seconds,
+                retry_tiers,
+                parking_queue,
+            } => {
+                broker
+                    .with_channel(|channel| async move {
+                        rmq_init::init_work_queue(
+                            &channel,
+                            work_exchange,
+                            queue,
+                            retry_exchange,
+                            retry_queue,
+                            *retry_interval_in_seconds,
+                        )
+                        .await?;
+                        init_retry_tiers(&channel, exchanges, queue, retry_tiers).await?;
+                        match parking_queue {
+                            Some(parking_queue) => {
+                                rmq_init::init_parking_queue(&channel, parking_queue).await
+                            }
+                            None => Ok(()),
+                        }
+                    })
+                    .await?
+            }
+            MigrationStep::Move {
+                from,
+                from_retry_queue,
+                from_retry_tiers,
+                from_parking_queue,
+                to,
+                to_parking_queue,
+            } => {
+                let from_queues: Vec<&String> = [from, from_retry_queue]
+                    .iter()
+                    .copied()
+                    .chain(from_retry_tiers)
+                    .collect();
+                if let (Some(from_parking_queue), None) = (from_parking_queue, to_parking_queue) {
+                    ensure_not_parked(broker, from, from_parking_queue).await?;
+                }
+                for from_queue in from_queues.iter() {
+                    move_messages(broker, from_queue, to).await?;
+                }
+                if let (Some(from_parking_queue), Some(to_parking_queue)) =
+                    (from_parking_queue, to_parking_queue)
+                {
+                    move_messages(broker, from_parking_queue, to_parking_queue).await?;
+                }
+                for from_queue in from_queues.into_iter().chain(from_parking_queue) {
+                    delete_queue(broker, from_queue).await?;
+                }
+            }
+            MigrationStep::Recreate {
+                queue,
+                old_retry_queue,
+                old_retry_tiers,
+                retry_queue,
+                retry_interval_in_seconds,
+                retry_tiers,
+                ..
+            } => {
+                // the messages wait here while the queues are recreated,
+                // the ones waiting to be retried are retried right away
+                let temporary_queue = format!("{}.dge-migrate", queue);
+                let temporary_queue = temporary_queue.as_str();
+                broker
+                    .with_channel(|channel| async move {
+                        channel
+                            .queue_declare(
+                                &namespaced(temporary_queue),
+                                RMQ_QUEUE_DECLARE_OPTIONS,
+                                FieldTable::default(),
+                            )
+                            .await?;
+                        Ok(())
+                    })
+                    .await?;
+
+                let old_queues: Vec<&String> = [queue, old_retry_queue]
+                    .iter()
+                    .copied()
+                    .chain(old_retry_tiers)
+                    .collect();
+                for old_queue in old_queues.iter() {
+                    move_messages(broker, old_queue, temporary_queue).await?;
+                }
+                for old_queue in old_queues {
+                    delete_queue(broker, old_queue).await?;
+                }
+                broker
+                    .with_channel(|channel| async move {
+                        rmq_init::init_work_queue(
+                            &channel,
+                            work_exchange,
+                            queue,
+                            retry_exchange,
+                            retry_queue,
+                            *retry_interval_in_seconds,
+                        )
+                        .await?;
+                        init_retry_tiers(&channel, exchanges, queue, retry_tiers).await
+                    })
+                    .await?;
+                move_messages(broker, temporary_queue, queue).await?;
+                delete_queue(broker, temporary_queue).await?;
+            }
+            MigrationStep::DeclareRetryTiers { queue, retry_tiers } => {
+                broker
+                    .with_channel(|channel| async move {
+                        init_retry_tiers(&channel, exchanges, queue, retry_tiers).await
+                    })
+                    .await?
+            }
+            MigrationStep::DeleteRetryTiers { queue, retry_tiers } => {
+                // the messages waiting there are retried right away
+                for retry_tier in retry_tiers {
+                    move_messages(broker, retry_tier, queue).await?;
+                    delete_queue(broker, retry_tier).await?;
+                }
+            }
+            MigrationStep::DeclareParkingQueue { parking_queue, .. } => {
+                broker
+                    .with_channel(|channel| async move {
+                        rmq_init::init_parking_queue(&channel, parking_queue).await
+                    })
+                    .await?
+            }
+            MigrationStep::DeleteParkingQueue {
+                queue,
+                parking_queue,
+            } => {
+                ensure_not_parked(broker, queue, parking_queue).await?;
+                delete_queue(broker, parking_queue).await?;
+            }
+            MigrationStep::DrainAndDelete {
+                queue,
+                retry_queue,
+                retry_tiers,
+                parking_queue,
+            } => {
+                let queues: Vec<&String> = [queue, retry_queue]
+                    .iter()
+                    .copied()
+                    .chain(retry_tiers)
+                    .collect();
+                let started = Instant::now();
+                loop {
+                    let mut messages = 0;
+                    for queue in queues.iter() {
+                        messages += message_count(broker, queue).await?.unwrap_or(0);
+                    }
+                    if messages == 0 {
+                        break;
+                    }
+                    if started.elapsed() >= drain_timeout {
+                        return Err(Error::QueueNotDrained {
+                            queue: queue.clone(),
+                            messages,
+                        });
+                    }
+                    info!("waiting for {} messages in queue {} to be consumed", messages, queue);
+                    tokio::time::sleep(DRAIN_CHECK_INTERVAL).await;
+                }
+                // checked once drained, the last attempts may have parked messages
+                if let Some(parking_queue) = parking_queue {
+                    ensure_not_parked(broker, queue, parking_queue).await?;
+                }
+                for queue in queues.into_iter().chain(parking_queue) {
+                    delete_queue(broker, queue).await?;
+                }
+            }
+            MigrationStep::DeleteExchange { exchange } => {
+                broker
+                    .with_channel(|channel| async move {
+                        channel
+                            .exchange_delete(
+                                &namespaced(exchange),
+                                ExchangeDeleteOptions::default(),
+                            )
+                            .await?;
+                        Ok(())
+                    })
+                    .await?
+            }
+        }
+    }
+
+    info!("migration done");
+    Ok(())
+}
+
+/// Declare the retry queues `retry_tiers` of the backoff tiers for `queue` on `channel`.
+async fn init_retry_tiers(
+    channel: &Channel,
+    exchanges: &Exchanges,
+    queue: &str,
+    retry_tiers: &[String],
+) -> Result<()> {
+    for retry_tier in retry_tiers {
+        rmq_init::init_retry_tier(
+            channel,
+            exchanges.work.as_str(),
+            queue,
+            exchanges.retry.as_str(),
+            retry_tier,
+        )
+        .await?;
+    }
+    Ok(())
+}
+
+/// Fail if messages are parked in `parking_queue`, the parking queue of `queue`,
+/// before it is deleted, since they are never taken out of it otherwise.
+async fn ensure_not_parked(broker: &Broker, queue: &str, parking_queue: &str) -> Result<()> {
+    match message_count(broker, parking_queue).await? {
+        Some(messages) if messages > 0 => Err(Error::MessagesParked {
+            queue: String::from(queue),
+            parking_queue: String::from(parking_queue),
+            messages,
+        }),
+        _ => Ok(()),
+    }
+}
+
+/// The number of messages ready in `queue`, `None` if the queue does not exist.
+pub(crate) async fn message_count(broker: &Broker, queue: &str) -> Result<Option<u32>> {
+    let options = QueueDeclareOptions {
+        passive: true,
+        ..RMQ_QUEUE_DECLARE_OPTIONS
+    };
+    // a failed passive declaration closes the channel, so it gets its own channel
+    broker
+        .with_channel(|channel| async move {
+            match channel
+                .queue_declare(&namespaced(queue), options, FieldTable::default())
+                .await
+            {
+                Ok(queue) => Ok(Some(queue.message_count())),
+                Err(lapin::Error::ProtocolError(e))
+                    if e.kind() == &AMQPErrorKind::Soft(AMQPSoftError::NOTFOUND) =>
+                {
+                    Ok(None)
+                }
+                Err(e) => Err(e.into()),
+            }
+        })
+        .await
+}
+
+/// Move the messages in `from` to `to`, one by one, a missing `from` has no message.
+async fn move_messages(broker: &Broker, from: &str, to: &str) -> Result<()> {
+    if message_count(broker, from).await?.is_none() {
+        return Ok(());
+    }
+
+    // the message being moved when this fails is put back by closing the channel
+    let moved = broker
+        .with_channel(|channel| async move {
+            let mut moved = 0;
+            while let Some(msg) = channel
+                .basic_get(&namespaced(from), BasicGetOptions { no_ack: false })
+                .await?
+            {
+                let delivery = msg.delivery;
+                // the messages of a retry queue are moved without their expiration,
+                // so they neither expire in the temporary queue of `Recreate`,
+                // nor are dead-lettered from the work queue as another attempt
+                let properties = retry::without_expiration(&delivery.properties);
+                // published to the default exchange,
+                // which routes to the queue named by the routing key
+                let confirm = channel
+                    .basic_publish(
+                        "",
+                        &namespaced(to),
+                        RMQ_BASIC_PUBLISH_OPTIONS,
+                        delivery.data,
+                        properties,
+                    )
+                    .await?
+                    .await?;
+                // a message returned as unroutable, e.g. by a queue not declared, is not moved
+                confirmed(confirm, to)?;
+                delivery.acker.ack(RMQ_BASIC_ACK_OPTIONS).await?;
+                moved += 1;
+            }
+            Ok(moved)
+        })
+        .await?;
+
+    info!("moved {} messages from queue {} to {}", moved, from, to);
+    Ok(())
+}
+
+/// Delete `queue` if it is empty, deleting a missing queue is not an error.
+async fn delete_queue(broker: &Broker, queue: &str) -> Result<()> {
+    broker
+        .with_channel(|channel| async move {
+            channel
+                .queue_delete(
+                    &namespaced(queue),
+                    QueueDeleteOptions {
+                        if_unused: false,
+                        if_empty: true,
+                        nowait: false,
+                    },
+                )
+                .await?;
+            Ok(())
+        })
+        .await?;
+    info!("deleted queue {}", queue);
+    Ok(())
+}
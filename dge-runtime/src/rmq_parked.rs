@@ -0,0 +1,220 @@
+//! Inspecting, replaying and purging the messages parked after failing too many times,
+//! see `dge_runtime::retry`.
+//!
+//! The parking queues are named after the work queues they park messages from,
+//! which is how the subcommands refer to them, e.g. `parked show rest_call`.
+//! `show` gets the messages without acknowledging them, and closing the channel puts them back,
+//! so it leaves the parking queue as it is.
+
+use lapin::options::BasicGetOptions;
+use lapin::options::QueuePurgeOptions;
+use lapin::types::AMQPValue;
+use lapin::types::FieldTable;
+use std::collections::BTreeMap;
+use structopt::StructOpt;
+
+use crate::broker::Broker;
+use crate::namespace::namespaced;
+use crate::retry::errors_of;
+use crate::retry::retries_of;
+use crate::retry::ERRORS_HEADER;
+use crate::retry::PARKED_AT_HEADER;
+use crate::retry::PARKED_FROM_HEADER;
+use crate::retry::RETRIES_HEADER;
+use crate::rmq_migrate::message_count;
+use crate::rmq_primitive::confirmed;
+use crate::rmq_primitive::constant::RMQ_BASIC_ACK_OPTIONS;
+use crate::rmq_primitive::constant::RMQ_BASIC_PUBLISH_OPTIONS;
+use crate::Error;
+use crate::Result;
+
+/// The headers recording the attempts of a message, removed when it is replayed.
+const ATTEMPT_HEADERS: [&str; 5] = [
+    "x-death",
+    RETRIES_HEADER,
+    ERRORS_HEADER,
+    PARKED_FROM_HEADER,
+    PARKED_AT_HEADER,
+];
+
+/// Inspect and replay the messages parked after failing too many times.
+#[derive(Clone, Debug, StructOpt)]
+pub enum ParkedCommand {
+    /// Print the number of messages in each parking queue
+    List,
+    /// Print the messages parked from a work queue, with the errors of their attempts
+    Show {
+        /// The work queue the messages are parked from
+        queue: String,
+
+        /// How many messages to print
+        #[structopt(long, default_value = "10")]
+        limit: usize,
+    },
+    /// Publish the messages parked from a work queue back to it, with their attempts reset
+    Replay {
+        /// The work queue the messages are parked from
+        queue: String,
+
+        /// How many messages to replay, all of them if not given
+        #[structopt(long)]
+        limit: Option<usize>,
+    },
+    /// Delete the messages parked from a work queue
+    Purge {
+        /// The work queue the messages are parked from
+        queue: String,
+    },
+}
+
+/// Run `command` against the parking queues of the graph,
+/// given as `(work_queue, parking_queue)` by the generated code.
+pub async fn parked(
+    broker: &Broker,
+    work_exchange: &str,
+    parking_queues: &[(&str, &str)],
+    command: &ParkedCommand,
+) -> Result<()> {
+    match command {
+        ParkedCommand::List => {
+            if parking_queues.is_empty() {
+                println!("no queue of the graph has a maximum number of attempts");
+            }
+            for (work_queue, parking_queue) in parking_queues {
+                match message_count(broker, parking_queue).await? {
+                    Some(messages) => {
+                        println!("{}: {} messages parked in {}", work_queue, messages, parking_queue)
+                    }
+                    None => println!("{}: {} is not declared", work_queue, parking_queue),
+                }
+            }
+        }
+        ParkedCommand::Show { queue, limit } => {
+            let parking_queue = parking_queue_of(parking_queues, queue)?;
+            // the messages are not acknowledged, closing the channel puts them back
+            let shown = broker
+                .with_channel(|channel| async move {
+                    let mut shown = 0;
+                    while shown < *limit {
+                        let delivery = match channel
+                            .basic_get(
+                                &namespaced(parking_queue),
+                                BasicGetOptions { no_ack: false },
+                            )
+                            .await?
+                        {
+                            Some(msg) => msg.delivery,
+                            None => break,
+                        };
+                        shown += 1;
+
+                        let properties = &delivery.properties;
+                        println!("message {}", shown);
+                        if let Some(parked_at) = header_str(properties.headers(), PARKED_AT_HEADER)
+                        {
+                            println!("  parked at: {}", parked_at);
+                        }
+                        println!("  attempts: {}", retries_of(properties) + 1);
+                        for error in errors_of(properties) {
+                            println!("  error: {}", error);
+                        }
+                        println!("  body: {}", String::from_utf8_lossy(&delivery.data));
+                    }
+                    Ok(shown)
+                })
+                .await?;
+            if shown == 0 {
+                println!("no message is parked from {}", queue);
+            }
+        }
+        ParkedCommand::Replay { queue, limit } => {
+            let parking_queue = parking_queue_of(parking_queues, queue)?;
+            let replayed = broker
+                .with_channel(|channel| async move {
+                    let mut replayed = 0;
+                    while limit.is_none_or(|limit| replayed < limit) {
+                        let delivery = match channel
+                            .basic_get(
+                                &namespaced(parking_queue),
+                                BasicGetOptions { no_ack: false },
+                            )
+                            .await?
+                        {
+                            Some(msg) => msg.delivery,
+                            None => break,
+                        };
+
+                        let properties = delivery
+                            .properties
+                            .clone()
+                            .with_headers(without_attempts(delivery.properties.headers()));
+                        let confirm = channel
+                            .basic_publish(
+                                &namespaced(work_exchange),
+                                &namespaced(queue),
+                                RMQ_BASIC_PUBLISH_OPTIONS,
+                                delivery.data.clone(),
+                                properties,
+                            )
+                            .await?
+                            .await?;
+                        // a message returned as unroutable, e.g. by a work queue not declared,
+                        // is left parked, it is put back once the channel is closed
+                        confirmed(confirm, queue)?;
+                        delivery.acker.ack(RMQ_BASIC_ACK_OPTIONS).await?;
+                        replayed += 1;
+                    }
+                    Ok(replayed)
+                })
+                .await?;
+            println!("replayed {} messages to {}", replayed, queue);
+        }
+        ParkedCommand::Purge { queue } => {
+            let parking_queue = parking_queue_of(parking_queues, queue)?;
+            let purged = broker
+                .with_channel(|channel| async move {
+                    let purged = channel
+                        .queue_purge(&namespaced(parking_queue), QueuePurgeOptions::default())
+                        .await?;
+                    Ok(purged)
+                })
+                .await?;
+            println!("purged {} messages parked from {}", purged, queue);
+        }
+    }
+
+    Ok(())
+}
+
+fn parking_queue_of<'a>(parking_queues: &[(&str, &'a str)], queue: &str) -> Result<&'a str> {
+    parking_queues
+        .iter()
+        .find(|(work_queue, _)| *work_queue == queue)
+        .map(|(_, parking_queue)| *parking_queue)
+        .ok_or_else(|| Error::UnknownParkingQueue {
+            queue: String::from(queue),
+            known: parking_queues
+                .iter()
+                .map(|(work_queue, _)| *work_queue)
+                .collect::<Vec<_>>()
+                .join(", "),
+        })
+}
+
+fn header_str(headers: &Option<FieldTable>, name: &str) -> Option<String> {
+    match headers.as_ref()?.inner().get(name)? {
+        AMQPValue::LongString(value) => Some(value.to_string()),
+        _ => None,
+    }
+}
+
+fn without_attempts(headers: &Option<FieldTable>) -> FieldTable {
+    let kept: BTreeMap<_, _> = headers
+        .as_ref()
+        .map(|headers| headers.inner().clone())
+        .unwrap_or_default()
+        .into_iter()
+        .filter(|(name, _)| !ATTEMPT_HEADERS.contains(&name.as_str()))
+        .collect();
+    FieldTable::from(kept)
+}
@@ -1,11 +1,17 @@
+use lapin::publisher_confirm::Confirmation;
 use lapin::Channel;
 use lapin::Connection;
 use lapin::ConnectionProperties;
+use std::time::Instant;
 use log::debug;
 use log::info;
 use log::warn;
 use tokio_amqp::*;
 
+use crate::envelope;
+use crate::metrics;
+use crate::namespace::namespaced;
+use crate::telemetry::TraceContext;
 use crate::Error;
 use crate::Result;
 use constant::*;
@@ -13,11 +19,15 @@ use constant::*;
 pub enum Responsibility {
     Accept,
     Reject,
+    /// Give the message back to its queue as it is, e.g. when shutting down,
+    /// it does not count as an attempt, see `dge_runtime::retry`.
+    Requeue,
 }
 
 pub enum AckType {
     Ack,
     Reject,
+    Requeue,
 }
 
 pub async fn unreliable_ack_or_reject(
@@ -31,6 +41,10 @@ pub async fn unreliable_ack_or_reject(
             channel.basic_reject(tag, RMQ_BASIC_REJECT_DELAYED_REQUEUE_OPTIONS),
             "rejecting",
         ),
+        AckType::Requeue => (
+            channel.basic_reject(tag, RMQ_BASIC_REJECT_REQUEUE_OPTIONS),
+            "requeuing",
+        ),
     };
     debug!("{} message {}", action, tag);
     match fut.await {
@@ -67,6 +81,8 @@ pub async fn unreliable_ack_or_reject(
     }
 }
 
+/// A new connection and a channel in confirm mode on it, for the one-off commands,
+/// the nodes create their channels with `dge_runtime::broker::Broker`.
 pub async fn create_channel<S: AsRef<str>>(rmq_uri: S) -> Result<Channel> {
     let rmq_uri = rmq_uri.as_ref();
     info!("creating connection and channel");
@@ -78,31 +94,86 @@ pub async fn create_channel<S: AsRef<str>>(rmq_uri: S) -> Result<Channel> {
     Ok(channel)
 }
 
+/// Close `channel`, unless it is closed already, e.g. by RabbitMQ after a failed operation on it.
+pub(crate) async fn close_channel(channel: &Channel) -> Result<()> {
+    if channel.status().connected() {
+        channel
+            .close(
+                lapin::protocol::constants::REPLY_SUCCESS as lapin::types::ShortUInt,
+                "normal close of channel",
+            )
+            .await?;
+    }
+    Ok(())
+}
+
+/// Whether the message published to `queue` is confirmed by RabbitMQ as routed to it.
+///
+/// A mandatory message which cannot be routed is returned, and then acked all the same,
+/// so it is a failure as well.
+pub(crate) fn confirmed(confirmation: Confirmation, queue: &str) -> Result<()> {
+    let error = match confirmation {
+        Confirmation::Ack(None) => return Ok(()),
+        Confirmation::Ack(Some(returned)) => {
+            format!("the message is returned: {}", returned.reply_text)
+        }
+        _ => String::from("the message is not confirmed"),
+    };
+    Err(Error::FailedToPublishRmqMsg {
+        queue: String::from(queue),
+        error,
+    })
+}
+
 pub fn name_of_retry_queue(q: &str) -> String {
     format!("dge_retry_{}", q)
 }
 
-/// Publish the `msg` to `queue`.
+/// Publish the `msg` to `queue`, the names of `exchange` and `queue` are namespaced,
+/// see `dge_runtime::namespace`.
+///
+/// The message is given the envelope of a child of the message being handled, if any,
+/// see `dge_runtime::envelope`, and the trace context of its `publish` span,
+/// see `dge_runtime::telemetry`.
+/// The time taken is recorded in the metrics, see `dge_runtime::metrics`.
 pub async fn publish<S: AsRef<str>>(
     channel: Channel,
     exchange: S,
     queue: S,
     msg: Vec<u8>,
 ) -> Result<()> {
-    let exchange = exchange.as_ref();
     let queue = queue.as_ref();
+    let publishing = TraceContext::publish(queue);
+    let started = Instant::now();
+    let published = publish_traced(channel, exchange.as_ref(), queue, msg, &publishing).await;
+    metrics::published(queue, started.elapsed());
+    if let Err(e) = &published {
+        publishing.fail(e);
+    }
+    publishing.end();
+    published
+}
 
+async fn publish_traced(
+    channel: Channel,
+    exchange: &str,
+    queue: &str,
+    msg: Vec<u8>,
+    publishing: &TraceContext,
+) -> Result<()> {
     let confirm = channel
         .basic_publish(
-            exchange,
-            &queue,
+            &namespaced(exchange),
+            &namespaced(queue),
             RMQ_BASIC_PUBLISH_OPTIONS,
             msg,
             // 2 means durable
-            lapin::BasicProperties::default().with_delivery_mode(2),
+            publishing.inject(
+                envelope::outgoing().apply(lapin::BasicProperties::default().with_delivery_mode(2)),
+            ),
         )
         .await?
-        .wait()?;
+        .await?;
 
     if confirm.is_ack() {
         Ok(())
@@ -143,6 +214,9 @@ pub(crate) mod constant {
     pub static RMQ_BASIC_REJECT_DELAYED_REQUEUE_OPTIONS: BasicRejectOptions =
         BasicRejectOptions { requeue: false };
 
+    pub static RMQ_BASIC_REJECT_REQUEUE_OPTIONS: BasicRejectOptions =
+        BasicRejectOptions { requeue: true };
+
     pub static RMQ_BASIC_PUBLISH_OPTIONS: BasicPublishOptions = BasicPublishOptions {
         mandatory: true,  // require that the msg should be routed
         immediate: false, // false to allow the message to be queued
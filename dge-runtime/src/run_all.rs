@@ -0,0 +1,95 @@
+//! Running all the nodes of the graph in one process, for development.
+
+use futures::Future;
+use structopt::StructOpt;
+use tokio::task::JoinHandle;
+
+use crate::broker::ConnectionOptions;
+use crate::http::HttpOptions;
+use crate::node_options::parse_positive;
+use crate::shutdown::ShutdownOptions;
+use crate::Error;
+use crate::Result;
+
+/// Run every node of the graph as tasks of one tokio runtime, sharing the RabbitMQ connections.
+///
+/// Each node runs with the defaults declared in the graph,
+/// except for the worker threads, which are shared by all the nodes.
+#[derive(Clone, Debug, Default, StructOpt)]
+pub struct RunAllOptions {
+    /// The number of instances of a node, e.g. `--instances double=3`, can be given multiple times,
+    /// the nodes not given run one instance, 0 leaves the node out
+    #[structopt(long, parse(try_from_str = parse_instances))]
+    pub instances: Vec<(String, usize)>,
+
+    /// The number of worker threads of the tokio runtime, defaults to the number of CPU cores
+    #[structopt(long, parse(try_from_str = parse_positive))]
+    pub worker_threads: Option<usize>,
+
+    #[structopt(flatten)]
+    pub connection: ConnectionOptions,
+
+    #[structopt(flatten)]
+    pub http: HttpOptions,
+
+    #[structopt(flatten)]
+    pub shutdown: ShutdownOptions,
+}
+
+impl RunAllOptions {
+    /// Check that the nodes given in `--instances` are among `nodes`.
+    pub fn check_nodes(&self, nodes: &[&str]) -> Result<()> {
+        for (node, _) in self.instances.iter() {
+            if !nodes.contains(&node.as_str()) {
+                return Err(Error::UnknownNode {
+                    node: node.clone(),
+                    known: nodes.join(", "),
+                });
+            }
+        }
+        Ok(())
+    }
+
+    /// The number of instances of `node` to run, the last one given wins.
+    pub fn instances_of(&self, node: &str) -> usize {
+        self.instances
+            .iter()
+            .rev()
+            .find(|(name, _)| name == node)
+            .map_or(1, |(_, n)| *n)
+    }
+
+    /// Run `future` to completion on a multi-threaded tokio runtime.
+    pub fn block_on<F>(&self, future: F) -> Result<()>
+    where
+        F: Future<Output = Result<()>>,
+    {
+        let mut builder = tokio::runtime::Builder::new_multi_thread();
+        if let Some(worker_threads) = self.worker_threads {
+            builder.worker_threads(worker_threads);
+        }
+        builder.enable_all().build()?.block_on(future)
+    }
+}
+
+/// Wait for the spawned nodes, the first one failing (or panicking) fails them all.
+pub async fn wait_all(mut nodes: Vec<JoinHandle<Result<()>>>) -> Result<()> {
+    while !nodes.is_empty() {
+        let (finished, _, rest) = futures::future::select_all(nodes).await;
+        match finished {
+            Ok(result) => result?,
+            Err(e) => return Err(Error::NodePanicked(e.to_string())),
+        }
+        nodes = rest;
+    }
+    Ok(())
+}
+
+/// Parse `node=count`, the node name is accepted in both `snake_case` and `kebab-case`.
+fn parse_instances(s: &str) -> std::result::Result<(String, usize), String> {
+    let (node, count) = s
+        .split_once('=')
+        .ok_or_else(|| String::from("expecting node=count, e.g. double=3"))?;
+    let count = count.trim().parse::<usize>().map_err(|e| e.to_string())?;
+    Ok((node.trim().replace('-', "_"), count))
+}
@@ -0,0 +1,117 @@
+//! Shutting down the nodes gracefully on SIGTERM or SIGINT.
+//!
+//! Once the shutdown is requested, the consumers are cancelled, so no more messages are delivered,
+//! and the `poll` nodes stop adding jobs and dispatching checks.
+//! The messages delivered but not handled, or not added as jobs, are requeued as they are,
+//! without counting as attempts, see `dge_runtime::retry`.
+//! The messages being handled, with their acknowledgements and publications,
+//! and the checks running are waited for, up to `--shutdown-timeout-seconds`,
+//! or until a second signal, which stops waiting right away.
+//! Either way, the channels and the connections of the broker are closed then,
+//! and the messages not acknowledged by then are redelivered by RabbitMQ, e.g. to another instance.
+
+use futures::Future;
+use log::info;
+use log::warn;
+use once_cell::sync::Lazy;
+use std::time::Duration;
+use structopt::StructOpt;
+use tokio_util::sync::CancellationToken;
+
+use crate::broker::Broker;
+use crate::Result;
+
+static SHUTDOWN: Lazy<CancellationToken> = Lazy::new(CancellationToken::new);
+
+/// How the process shuts down.
+#[derive(Clone, Debug, StructOpt)]
+pub struct ShutdownOptions {
+    /// How long to wait for the messages being handled after SIGTERM or SIGINT, before exiting anyway
+    #[structopt(long, default_value = "30")]
+    pub shutdown_timeout_seconds: u64,
+}
+
+impl Default for ShutdownOptions {
+    fn default() -> Self {
+        ShutdownOptions {
+            shutdown_timeout_seconds: 30,
+        }
+    }
+}
+
+/// Request the shutdown of every node of the process, as SIGTERM does.
+pub fn request() {
+    SHUTDOWN.cancel();
+}
+
+pub fn is_requested() -> bool {
+    SHUTDOWN.is_cancelled()
+}
+
+/// Completes once the shutdown is requested.
+pub async fn requested() {
+    SHUTDOWN.cancelled().await
+}
+
+/// Run `future`, requesting the shutdown on SIGTERM or SIGINT,
+/// after which `future` is expected to complete within `options.shutdown_timeout_seconds`,
+/// or it is dropped.
+/// The connections of `broker` are closed once `future` completes or is dropped.
+pub async fn on_signal<F>(options: ShutdownOptions, broker: Broker, future: F) -> Result<()>
+where
+    F: Future<Output = Result<()>>,
+{
+    let result = until_shut_down(options, future).await;
+    let closed = broker.close().await;
+    result.and(closed)
+}
+
+async fn until_shut_down<F>(options: ShutdownOptions, future: F) -> Result<()>
+where
+    F: Future<Output = Result<()>>,
+{
+    tokio::pin!(future);
+    tokio::select! {
+        result = &mut future => return result,
+        signal = signal() => signal?,
+    }
+
+    info!(
+        "shutting down, waiting up to {} seconds for the messages being handled",
+        options.shutdown_timeout_seconds
+    );
+    request();
+    let timeout = tokio::time::sleep(Duration::from_secs(options.shutdown_timeout_seconds));
+    tokio::select! {
+        result = &mut future => {
+            info!("shut down");
+            result
+        }
+        _ = timeout => {
+            warn!("not shut down in time, the messages not acknowledged will be redelivered");
+            Ok(())
+        }
+        signal = signal() => {
+            warn!("signalled again, the messages not acknowledged will be redelivered");
+            signal
+        }
+    }
+}
+
+#[cfg(unix)]
+async fn signal() -> Result<()> {
+    use tokio::signal::unix::SignalKind;
+
+    let mut terminate = tokio::signal::unix::signal(SignalKind::terminate())?;
+    tokio::select! {
+        interrupted = tokio::signal::ctrl_c() => interrupted?,
+        _ = terminate.recv() => (),
+    }
+    Ok(())
+}
+
+#[cfg(not(unix))]
+async fn signal() -> Result<()> {
+    tokio::signal::ctrl_c().await?;
+    Ok(())
+}
@@ -0,0 +1,266 @@
+//! Tracing the handling of the messages across the nodes with OpenTelemetry,
+//! when dge-runtime is built with the `otel` feature.
+//!
+//! Each delivery gets a `consume` span, with the `handle` span of the handler and the `ack`
+//! (or `reject`) span as children, and each message published gets a `publish` span,
+//! a child of the span it is published in.
+//! The context of the `publish` span is injected into the headers of the message
+//! as W3C `traceparent` and `tracestate`, and the `consume` span of its delivery is its child,
+//! so a run shows up as a single trace across the nodes.
+//!
+//! The spans are exported via OTLP over HTTP when `OTEL_EXPORTER_OTLP_ENDPOINT`
+//! (or `OTEL_EXPORTER_OTLP_TRACES_ENDPOINT`) is set, e.g. to `http://localhost:4318`,
+//! otherwise the trace context is only passed along.
+//!
+//! Without the feature, everything here does nothing.
+
+use lapin::message::Delivery;
+use lapin::BasicProperties;
+use std::fmt::Display;
+use std::future::Future;
+
+use crate::envelope::Envelope;
+use crate::Result;
+
+#[cfg(feature = "otel")]
+use opentelemetry::context::FutureExt;
+#[cfg(feature = "otel")]
+use opentelemetry::trace::SpanKind;
+#[cfg(feature = "otel")]
+use opentelemetry::trace::Status;
+#[cfg(feature = "otel")]
+use opentelemetry::trace::TraceContextExt;
+#[cfg(feature = "otel")]
+use opentelemetry::trace::Tracer;
+#[cfg(feature = "otel")]
+use opentelemetry::KeyValue;
+
+/// The name of the tracer creating the spans.
+#[cfg(feature = "otel")]
+const TRACER: &str = "dge-runtime";
+
+/// Exports the spans of this process until it is dropped, returned by `init`.
+pub struct Telemetry {
+    #[cfg(feature = "otel")]
+    provider: Option<opentelemetry_sdk::trace::SdkTracerProvider>,
+}
+
+/// Export the spans of this process as `service_name`, if an OTLP endpoint is configured.
+///
+/// The returned value flushes the spans not exported yet when it is dropped,
+/// so it should live until the process exits.
+#[cfg(feature = "otel")]
+pub fn init(service_name: &str) -> Result<Telemetry> {
+    use opentelemetry_sdk::trace::SdkTracerProvider;
+    use opentelemetry_sdk::Resource;
+
+    let configured = ["OTEL_EXPORTER_OTLP_ENDPOINT", "OTEL_EXPORTER_OTLP_TRACES_ENDPOINT"]
+        .iter()
+        .any(|name| std::env::var_os(name).is_some());
+    if !configured {
+        return Ok(Telemetry { provider: None });
+    }
+
+    let exporter = opentelemetry_otlp::SpanExporter::builder()
+        .with_http()
+        .build()
+        .map_err(|e| crate::Error::FailedToInitTelemetry(e.to_string()))?;
+    let provider = SdkTracerProvider::builder()
+        .with_batch_exporter(exporter)
+        .with_resource(
+            Resource::builder()
+                .with_service_name(String::from(service_name))
+                .build(),
+        )
+        .build();
+    opentelemetry::global::set_tracer_provider(provider.clone());
+    log::info!("exporting the spans of {} via OTLP", service_name);
+
+    Ok(Telemetry {
+        provider: Some(provider),
+    })
+}
+
+/// Export the spans of this process as `service_name`, if an OTLP endpoint is configured.
+///
+/// The returned value flushes the spans not exported yet when it is dropped,
+/// so it should live until the process exits.
+#[cfg(not(feature = "otel"))]
+pub fn init(_service_name: &str) -> Result<Telemetry> {
+    Ok(Telemetry {})
+}
+
+impl Drop for Telemetry {
+    fn drop(&mut self) {
+        #[cfg(feature = "otel")]
+        if let Some(provider) = self.provider.take() {
+            if let Err(e) = provider.shutdown() {
+                log::warn!("failed to export the remaining spans: {}", e);
+            }
+        }
+    }
+}
+
+/// A span, and the trace it belongs to.
+#[derive(Clone, Debug, Default)]
+pub(crate) struct TraceContext {
+    #[cfg(feature = "otel")]
+    cx: opentelemetry::Context,
+}
+
+impl TraceContext {
+    /// The `consume` span of `delivery`, received by `node`,
+    /// a child of the span it is published in, if its headers tell.
+    #[cfg(feature = "otel")]
+    pub(crate) fn consume(node: &str, delivery: &Delivery, envelope: &Envelope) -> TraceContext {
+        use opentelemetry::propagation::TextMapPropagator;
+        use opentelemetry_sdk::propagation::TraceContextPropagator;
+
+        let headers = delivery.properties.headers().as_ref().map(|h| h.inner());
+        let parent = TraceContextPropagator::new().extract(&HeaderExtractor(headers));
+        let queue = delivery.routing_key.to_string();
+        TraceContext::start(
+            &parent,
+            format!("consume {}", queue),
+            SpanKind::Consumer,
+            vec![
+                KeyValue::new("messaging.system", "rabbitmq"),
+                KeyValue::new("messaging.destination.name", queue),
+                KeyValue::new("messaging.message.id", envelope.message_id.clone()),
+                KeyValue::new("dge.node", String::from(node)),
+                KeyValue::new("dge.run_id", envelope.run_id.clone()),
+            ],
+        )
+    }
+
+    #[cfg(not(feature = "otel"))]
+    pub(crate) fn consume(_node: &str, _delivery: &Delivery, _envelope: &Envelope) -> TraceContext {
+        TraceContext {}
+    }
+
+    /// The `publish` span of a message published to `queue`, a child of the current span.
+    #[cfg(feature = "otel")]
+    pub(crate) fn publish(queue: &str) -> TraceContext {
+        TraceContext::start(
+            &opentelemetry::Context::current(),
+            format!("publish {}", queue),
+            SpanKind::Producer,
+            vec![
+                KeyValue::new("messaging.system", "rabbitmq"),
+                KeyValue::new("messaging.destination.name", String::from(queue)),
+            ],
+        )
+    }
+
+    #[cfg(not(feature = "otel"))]
+    pub(crate) fn publish(_queue: &str) -> TraceContext {
+        TraceContext {}
+    }
+
+    /// A span named `name` inside this span.
+    #[cfg(feature = "otel")]
+    pub(crate) fn child(&self, name: String) -> TraceContext {
+        TraceContext::start(&self.cx, name, SpanKind::Internal, Vec::new())
+    }
+
+    #[cfg(not(feature = "otel"))]
+    pub(crate) fn child(&self, _name: String) -> TraceContext {
+        TraceContext {}
+    }
+
+    #[cfg(feature = "otel")]
+    fn start(
+        parent: &opentelemetry::Context,
+        name: String,
+        kind: SpanKind,
+        attributes: Vec<KeyValue>,
+    ) -> TraceContext {
+        let tracer = opentelemetry::global::tracer(TRACER);
+        let span = tracer
+            .span_builder(name)
+            .with_kind(kind)
+            .with_attributes(attributes)
+            .start_with_context(&tracer, parent);
+        TraceContext {
+            cx: parent.with_span(span),
+        }
+    }
+
+    /// `properties` with the context of this span in the W3C headers, the other headers are kept.
+    #[cfg(feature = "otel")]
+    pub(crate) fn inject(&self, properties: BasicProperties) -> BasicProperties {
+        use opentelemetry::propagation::TextMapPropagator;
+        use opentelemetry_sdk::propagation::TraceContextPropagator;
+
+        let mut headers = properties.headers().clone().unwrap_or_default();
+        TraceContextPropagator::new().inject_context(&self.cx, &mut HeaderInjector(&mut headers));
+        properties.with_headers(headers)
+    }
+
+    #[cfg(not(feature = "otel"))]
+    pub(crate) fn inject(&self, properties: BasicProperties) -> BasicProperties {
+        properties
+    }
+
+    /// Run `future` in this span, the spans started by it are its children.
+    #[cfg(feature = "otel")]
+    pub(crate) async fn scope<F: Future>(&self, future: F) -> F::Output {
+        future.with_context(self.cx.clone()).await
+    }
+
+    #[cfg(not(feature = "otel"))]
+    pub(crate) async fn scope<F: Future>(&self, future: F) -> F::Output {
+        future.await
+    }
+
+    /// Mark the span as failed with `error`.
+    #[cfg(feature = "otel")]
+    pub(crate) fn fail(&self, error: &dyn Display) {
+        self.cx.span().set_status(Status::error(error.to_string()));
+    }
+
+    #[cfg(not(feature = "otel"))]
+    pub(crate) fn fail(&self, _error: &dyn Display) {}
+
+    #[cfg(feature = "otel")]
+    pub(crate) fn end(&self) {
+        self.cx.span().end();
+    }
+
+    #[cfg(not(feature = "otel"))]
+    pub(crate) fn end(&self) {}
+}
+
+#[cfg(feature = "otel")]
+struct HeaderExtractor<'a>(
+    Option<&'a std::collections::BTreeMap<lapin::types::ShortString, lapin::types::AMQPValue>>,
+);
+
+#[cfg(feature = "otel")]
+impl opentelemetry::propagation::Extractor for HeaderExtractor<'_> {
+    fn get(&self, key: &str) -> Option<&str> {
+        match self.0?.get(key)? {
+            lapin::types::AMQPValue::LongString(value) => Some(value.as_str()),
+            _ => None,
+        }
+    }
+
+    fn keys(&self) -> Vec<&str> {
+        self.0
+            .map(|headers| headers.keys().map(|key| key.as_str()).collect())
+            .unwrap_or_default()
+    }
+}
+
+#[cfg(feature = "otel")]
+struct HeaderInjector<'a>(&'a mut lapin::types::FieldTable);
+
+#[cfg(feature = "otel")]
+impl opentelemetry::propagation::Injector for HeaderInjector<'_> {
+    fn set(&mut self, key: &str, value: String) {
+        self.0.insert(
+            lapin::types::ShortString::from(key),
+            lapin::types::AMQPValue::LongString(lapin::types::LongString::from(value)),
+        );
+    }
+}